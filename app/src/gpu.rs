@@ -746,12 +746,18 @@ impl State<'_> {
         buffer.shape_until_scroll(&mut self.font_system, false);
     }
     pub fn world_to_view(&self, point: Vec3) -> Vec2 {
-        let transformed = (self.camera_matrix.project_point3(point).xy() / Vec2::new(2., -2.)) + Vec2::new(0.5, 0.5);
+        self.project(self.camera_matrix, point)
+    }
+    pub fn camera_to_view(&self, camera: &dyn Camera, point: Vec3) -> Vec2 {
+        self.project(camera.matrix(&self.config), point)
+    }
+    fn project(&self, matrix: Mat4, point: Vec3) -> Vec2 {
+        let transformed = (matrix.project_point3(point).xy() / Vec2::new(2., -2.)) + Vec2::new(0.5, 0.5);
         let screen_size = Vec2::new(self.config.width as f32, self.config.height as f32);
 
         transformed * screen_size
     }
-    pub fn draw_text(&mut self, buffer: &mut glyphon::Buffer, point: Vec2) -> Result<(), String> {
+    pub fn draw_text(&mut self, texts: &[(&glyphon::Buffer, Vec2)]) -> Result<(), String> {
         self.viewport.update(&self.queue, glyphon::Resolution {
             width: self.config.width,
             height: self.config.height,
@@ -763,8 +769,8 @@ impl State<'_> {
                 &mut self.font_system,
                 &mut self.atlas,
                 &mut self.viewport,
-                [glyphon::TextArea {
-                    buffer,
+                texts.iter().map(|(buffer, point)| glyphon::TextArea {
+                    buffer: *buffer,
                     left: point.x,
                     top: point.y,
                     scale: 1.0,
@@ -776,7 +782,7 @@ impl State<'_> {
                     },
                     default_color: glyphon::Color::rgb(255, 255, 255),
                     custom_glyphs: &[],
-                }],
+                }),
                 &mut self.swash_cache,
             ).map_err(|e| e.to_string())
             .map_err(|e| format!("failed to prepare a text render: {}", e))?;
@@ -4,7 +4,7 @@
 
 use std::rc::Rc;
 use glam::{Vec2, Vec3};
-use logic::{field::{level_to_gravity, Field}, piece::Piece, well::{Block, BlockDirections, Well, WELL_COLS, WELL_ROWS}};
+use logic::{field::Field, piece::Piece, well::{Block, BlockDirections, Well, WELL_COLS, WELL_ROWS}};
 use crate::{gpu::{parallelogram, rectangle, Camera2D, Camera3D, State}};
 
 fn lerp(a: f32, b: f32, f: f32) -> f32 {
@@ -20,6 +20,7 @@ fn texture_index(block: Block) -> i32 {
         Block::Cyan => 4,
         Block::Blue => 5,
         Block::Purple => 6,
+        Block::Gray => 7,
     }
 }
 
@@ -59,6 +60,7 @@ pub struct Graphics {
     well: (Rc<wgpu::BindGroup>, Rc<wgpu::TextureView>),
     next: (Rc<wgpu::BindGroup>, Rc<wgpu::TextureView>),
     score_buffer: glyphon::Buffer,
+    hud_buffer: glyphon::Buffer,
 }
 
 impl Graphics {
@@ -68,13 +70,15 @@ impl Graphics {
         let well = state.create_texture(WELL_COLS as u32 * 8, WELL_ROWS as u32 * 8);
         let next = state.create_texture(4 * 8, 4 * 8);
         let mut buffer = state.create_buffer();
-        Graphics::score_text(&mut buffer, state, 0, 0);
+        Graphics::score_text(&mut buffer, state, 0, 0, &[]);
+        let hud_buffer = state.create_buffer();
 
         Ok(Graphics {
             tilemap,
             well,
             next,
             score_buffer: buffer,
+            hud_buffer,
             level000: state.upload_texture(include_bytes!("gfx/level000.png"), wgpu::FilterMode::Nearest)?,
             level100: state.upload_texture(include_bytes!("gfx/level100.png"), wgpu::FilterMode::Nearest)?,
             level200: state.upload_texture(include_bytes!("gfx/level200.png"), wgpu::FilterMode::Nearest)?,
@@ -88,7 +92,7 @@ impl Graphics {
             level1000: state.upload_texture(include_bytes!("gfx/level1000.png"), wgpu::FilterMode::Nearest)?,
         })
     }
-    pub fn score_text(buffer: &mut glyphon::Buffer, state: &mut State, gravity: i32, level: u32) {
+    pub fn score_text(buffer: &mut glyphon::Buffer, state: &mut State, gravity: i32, level: u32, banner: &[String]) {
         let attrs = glyphon::Attrs::new().family(glyphon::Family::Name("Hanken Grotesk")).weight(glyphon::Weight::MEDIUM).color(glyphon::Color::rgba(255, 255, 255, 180));
 
         let is_20g = gravity >= 256;
@@ -98,9 +102,19 @@ impl Graphics {
             gravity / 256
         };
 
-        state.set_buffer_text(buffer, [
+        let gravity_label = format!("{}", gravity_amount);
+        let level_label = format!("{}", level);
+        let section_label = format!("{}\n", ((level / 100) + 1) * 100);
+
+        let banner = banner.iter().map(|line| format!("{}\n", line)).collect::<Vec<_>>();
+
+        let mut spans = vec![];
+        for line in &banner {
+            spans.push((line.as_str(), attrs.metrics(glyphon::Metrics::relative(24., 1.2)).color(glyphon::Color::rgba(255, 200, 80, 255))));
+        }
+        spans.extend([
             ("Gravity\n", attrs.metrics(glyphon::Metrics::relative(24., 1.2))),
-            (&format!("{}", gravity_amount), attrs.metrics(glyphon::Metrics::relative(32., 1.2)).weight(glyphon::Weight::BOLD).color(glyphon::Color::rgba(255, 255, 255, 255))),
+            (&gravity_label, attrs.metrics(glyphon::Metrics::relative(32., 1.2)).weight(glyphon::Weight::BOLD).color(glyphon::Color::rgba(255, 255, 255, 255))),
             if is_20g {
                 ("G", attrs.metrics(glyphon::Metrics::relative(32., 1.2)))
             } else {
@@ -108,10 +122,18 @@ impl Graphics {
             },
             ("\n", attrs),
             ("Level\n", attrs.metrics(glyphon::Metrics::relative(24., 1.2))),
-            (&format!("{}", level), attrs.metrics(glyphon::Metrics::relative(32., 1.2)).weight(glyphon::Weight::BOLD).color(glyphon::Color::rgba(255, 255, 255, 255))),
+            (&level_label, attrs.metrics(glyphon::Metrics::relative(32., 1.2)).weight(glyphon::Weight::BOLD).color(glyphon::Color::rgba(255, 255, 255, 255))),
             (" /", attrs.metrics(glyphon::Metrics::relative(24., 1.2))),
-            (&format!("{}\n", ((level / 100) + 1) * 100), attrs.metrics(glyphon::Metrics::relative(24., 1.2))),
-        ], attrs);
+            (&section_label, attrs.metrics(glyphon::Metrics::relative(24., 1.2))),
+        ]);
+
+        state.set_buffer_text(buffer, spans, attrs);
+    }
+    pub fn hud_text(buffer: &mut glyphon::Buffer, state: &mut State, lines: &[String]) {
+        let attrs = glyphon::Attrs::new().family(glyphon::Family::Name("Hanken Grotesk")).weight(glyphon::Weight::MEDIUM).color(glyphon::Color::rgba(255, 255, 255, 220));
+        let text = lines.join("\n");
+
+        state.set_buffer_text(buffer, [(text.as_str(), attrs.metrics(glyphon::Metrics::relative(18., 1.2)))], attrs);
     }
     pub fn queue_well_bg(state: &mut State) {
         let well_width = WELL_COLS as f32;
@@ -264,6 +286,17 @@ impl Graphics {
             }
         }
 
+        for (i, row) in well.blocks.iter().enumerate() {
+            for (j, col) in row.iter().enumerate() {
+                if col.map(|it| it.jewel).unwrap_or(false) {
+                    let bx = j as f32 + 0.3;
+                    let by = i as f32 + 0.3;
+
+                    state.queue_draw(rectangle(Vec3::new(bx, by, 0.), 0.4, 0.4, Vec2::new(0., 0.), 1., 1., wgpu::Color { r: 1., g: 1., b: 1., a: 0.8 }));
+                }
+            }
+        }
+
         let pixel_color = wgpu::Color { r: 0.9, g: 0.9, b: 0.9, a: 0.4 };
         const DST_BLOCK_SIZE: f32 = 1.;
         const DST_PIXEL_SIZE: f32 = 1. / 8.;
@@ -388,7 +421,22 @@ impl Graphics {
 
         Ok(())
     }
-    pub fn render(&mut self, field: &Field, well: &Well, piece: Option<&Piece>, next: &Piece, state: &mut State) -> Result<(), String> {
+    pub fn well_cell_at(state: &State, point: Vec2) -> Option<(usize, usize)> {
+        let camera = Camera3D::default();
+        let well_width = WELL_COLS as f32;
+        let well_height = WELL_ROWS as f32;
+
+        let top_left = state.camera_to_view(&camera, Vec3::new(well_width / -2., well_height / 2., 0.));
+        let bottom_right = state.camera_to_view(&camera, Vec3::new(well_width / 2., well_height / -2., 0.));
+        let cell = (point - top_left) / (bottom_right - top_left) * Vec2::new(well_width, well_height);
+
+        if cell.x < 0. || cell.y < 0. || cell.x >= well_width || cell.y >= well_height {
+            None
+        } else {
+            Some((cell.y as usize, cell.x as usize))
+        }
+    }
+    pub fn render(&mut self, field: &Field, well: &Well, piece: Option<&Piece>, next: &Piece, banner: &[String], hud: &[String], state: &mut State) -> Result<(), String> {
         self.render_well(well, piece, state)?;
         self.render_next(next, state)?;
 
@@ -437,8 +485,14 @@ impl Graphics {
         state.do_draw()?;
 
         let point = state.world_to_view(Vec3::new(well_width / 2. + 1., well_height / 2., 0.));
-        Graphics::score_text(&mut self.score_buffer, state, level_to_gravity(field.level), field.level);
-        state.draw_text(&mut self.score_buffer, point)?;
+        Graphics::score_text(&mut self.score_buffer, state, field.gravity(), field.level, banner);
+        if hud.is_empty() {
+            state.draw_text(&[(&self.score_buffer, point)])?;
+        } else {
+            let hud_point = state.world_to_view(Vec3::new(well_width / -2. - 7., well_height / 2., 0.));
+            Graphics::hud_text(&mut self.hud_buffer, state, hud);
+            state.draw_text(&[(&self.score_buffer, point), (&self.hud_buffer, hud_point)])?;
+        }
 
         state.complete_render_pass()?;
 
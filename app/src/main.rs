@@ -8,6 +8,10 @@ mod graphics_gpu;
 #[cfg(not(target_family = "wasm"))]
 mod main_sdl;
 #[cfg(not(target_family = "wasm"))]
+mod practice_sdl;
+#[cfg(not(target_family = "wasm"))]
+mod records_sdl;
+#[cfg(not(target_family = "wasm"))]
 mod sounds_sdl;
 
 #[cfg(not(target_family = "wasm"))]
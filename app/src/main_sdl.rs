@@ -2,8 +2,12 @@ use std::{collections::HashSet, time::Duration};
 use logic::{
     field::{Field, GameState},
     hooks::Cubes,
-    input::{Input, InputProvider, Inputs}, well::WELL_COLS,
+    fumen::Fumen,
+    input::{Input, InputProvider, Inputs}, practice::PracticeSetup, puzzle::{Puzzle, StageList}, recording::GameRecord, records::{RecordEntry, Records, NORMAL_MODE}, rewind::Rewind, stats::{GameResult, Stats}, well::WELL_COLS,
 };
+use nanoserde::DeJson;
+use crate::practice_sdl::Editor;
+use crate::records_sdl::{load_records, now, save_records, save_replay};
 use crate::sounds_sdl::ClientSounds;
 use crate::graphics_gpu::Graphics;
 use sdl2::{self as sdl};
@@ -93,10 +97,23 @@ impl InputProvider for SDLInputs {
     }
 }
 
+fn load_puzzle(path: &str) -> Result<Puzzle, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    let list = StageList::deserialize_json(&contents).map_err(|e| format!("failed to parse {}: {}", path, e))?;
+    Puzzle::new(list.stages).map_err(|e| format!("invalid stage list {}: {}", path, e))
+}
+
 pub fn main() -> Result<(), String> {
+    let args = std::env::args().collect::<Vec<_>>();
+    let mut puzzle = match args.iter().position(|it| it == "--puzzle") {
+        Some(idx) => Some(load_puzzle(args.get(idx + 1).ok_or("--puzzle requires a stage list")?)?),
+        None => None,
+    };
+
     let ctx = sdl::init()?;
 
     let video = ctx.video()?;
+    let clipboard = video.clipboard();
     let _audio = ctx.audio()?;
     let controller = ctx.game_controller()?;
     let _c = (0..controller.num_joysticks()?)
@@ -131,6 +148,7 @@ pub fn main() -> Result<(), String> {
     let mut graphics = Graphics::new(&mut gpu_state)?;
 
     let mut field = Field::new();
+    let mut replay = GameRecord::new(&field);
     let mut input_provider = SDLInputs::new();
     let mut inputs = Inputs::new();
 
@@ -140,10 +158,37 @@ pub fn main() -> Result<(), String> {
 
     let mut ticks = 0u64;
 
+    let mut rewind = Rewind::new(60 * 60);
+    let mut rewinding = false;
+
+    let mut editor: Option<Editor> = None;
+    let mut practice: Option<PracticeSetup> = None;
+
+    let mut stats = Stats::new();
+    let mut result: Option<GameResult> = None;
+    let mut show_stats = false;
+
+    // records are nice to have, so a missing or broken file shouldn't keep the game from starting
+    let mut records = load_records().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        Records::new()
+    });
+    let mut rank: Option<usize> = None;
+    let mut cheated = false;
+
     let mut stepper = nanotime::StepData::new(Duration::from_secs_f64(1. / 60.));
 
     'running: loop {
         for event in event_pump.poll_iter() {
+            // while the editor is open it gets every key and mouse event except the one that closes
+            // it, so nothing it doesn't use leaks through to the game underneath
+            if let Some(ref mut editor) = editor {
+                let passes = matches!(event, Event::Quit { .. } | Event::Window { .. } | Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. });
+                if !passes {
+                    editor.handle_event(&event, &gpu_state, &clipboard);
+                    continue;
+                }
+            }
             match event {
                 Event::Window {
                     window_id,
@@ -177,6 +222,60 @@ pub fn main() -> Result<(), String> {
                     ..
                 } => {
                     field.level += 50;
+                    cheated = true;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
+                    ..
+                } if puzzle.is_none() => {
+                    if let Some(closed) = editor.take() {
+                        match closed.setup.to_field() {
+                        Ok(practice_field) => {
+                            field = practice_field;
+                            practice = Some(closed.setup);
+                            inputs = Inputs::new();
+                            stats = Stats::new();
+                            result = None;
+                            rewind.reset();
+                        }
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            editor = Some(closed);
+                        }
+                        }
+                    } else {
+                        editor = Some(Editor::new(practice.clone().unwrap_or_else(|| PracticeSetup::from_field(&field))));
+                        input_provider = SDLInputs::new();
+                        rewinding = false;
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    repeat: false,
+                    ..
+                } => {
+                    show_stats = !show_stats;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    repeat: false,
+                    ..
+                } => {
+                    let shown = puzzle.as_ref().map(|it| &it.field).unwrap_or(&field);
+                    clipboard.set_clipboard_text(&Fumen::from_field(shown).encode())?;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => {
+                    rewinding = true;
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => {
+                    rewinding = false;
                 }
                 Event::KeyUp {
                     keycode:
@@ -199,16 +298,88 @@ pub fn main() -> Result<(), String> {
             }
         }
 
-        ticks += 1;
-        inputs.tick(ticks, &mut input_provider);
-        field.update(&mut inputs, &mut sounds, &mut cubes);
+        if let Some(ref mut puzzle) = puzzle {
+            ticks += 1;
+            inputs.tick(ticks, &mut input_provider);
+            puzzle.update(&inputs, &mut sounds, &mut cubes);
+        } else if let Some(ref mut editor) = editor {
+            if editor.take_changed() {
+                if let Ok(preview) = editor.setup.to_field() {
+                    field = preview;
+                }
+            }
+        } else if rewinding {
+            rewind.step_back(&mut ticks, &mut field, &mut inputs, &mut stats);
+        } else {
+            let was_over = matches!(field.state, GameState::GameOver { .. });
+            rewind.record(ticks, &field, &inputs, &stats);
+
+            ticks += 1;
+            inputs.tick(ticks, &mut input_provider);
+            replay.record(&inputs);
+            let locked = field.update(&mut inputs, &mut sounds, &mut cubes);
+            stats.update(&field, &inputs, locked);
+
+            let is_over = matches!(field.state, GameState::GameOver { .. });
+            if !was_over && is_over {
+                let finished = GameResult::new(&field, &stats);
+                if practice.is_none() && !rewind.is_practice() && !cheated {
+                    let mut entry = RecordEntry::new(&finished, now());
+                    if records.qualifies(NORMAL_MODE, &entry) {
+                        match save_replay(&replay, entry.date) {
+                            Ok(path) => entry.replay = Some(path),
+                            Err(e) => eprintln!("{}", e),
+                        }
+                    }
+                    rank = records.submit(NORMAL_MODE, entry);
+                    if let Err(e) = save_records(&records) {
+                        eprintln!("{}", e);
+                    }
+                }
+                result = Some(finished);
+            } else if was_over && !is_over {
+                if let Some(Ok(practice_field)) = practice.as_ref().map(PracticeSetup::to_field) {
+                    field = practice_field;
+                }
+                stats = Stats::new();
+                result = None;
+                rank = None;
+                cheated = false;
+                rewind.reset();
+                replay = GameRecord::new(&field);
+            }
+        }
+
+        let (shown, banner) = if let Some(ref puzzle) = puzzle {
+            (&puzzle.field, puzzle.status())
+        } else if let Some(ref editor) = editor {
+            (&field, editor.banner())
+        } else if practice.is_some() || rewind.is_practice() {
+            (&field, vec!["Practice".to_string()])
+        } else {
+            (&field, vec![])
+        };
+        let hud = if let Some(ref result) = result {
+            let mut lines = result.summary();
+            if practice.is_none() && !rewind.is_practice() {
+                lines.push(String::new());
+                lines.extend(records.table(NORMAL_MODE, rank));
+            }
+            lines
+        } else if stats.keys == 0 && puzzle.is_none() && editor.is_none() && practice.is_none() {
+            records.table(NORMAL_MODE, None)
+        } else if show_stats && puzzle.is_none() && editor.is_none() {
+            stats.summary()
+        } else {
+            vec![]
+        };
 
-        match field.state {
+        match shown.state {
             GameState::ActivePiece { piece, .. } => {
-                graphics.render(&field, &field.well, Some(&piece), &field.next, &mut gpu_state)?;
+                graphics.render(shown, &shown.well, Some(&piece), &shown.next, &banner, &hud, &mut gpu_state)?;
             }
             _ => {
-                graphics.render(&field, &field.well, None, &field.next, &mut gpu_state)?;
+                graphics.render(shown, &shown.well, None, &shown.next, &banner, &hud, &mut gpu_state)?;
             }
         }
 
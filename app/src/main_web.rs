@@ -4,7 +4,8 @@
 
 use std::collections::HashSet;
 
-use logic::{field::{Field, GameState}, hooks::{Cubes, Sounds}, input::{Input, InputProvider, Inputs}};
+use logic::{field::{Field, GameState}, hooks::{Cubes, Sounds}, input::{Input, InputProvider, Inputs}, recording::GameRecord, records::{RecordEntry, Records, NORMAL_MODE}, rewind::Rewind, stats::{GameResult, Stats}};
+use nanoserde::{DeJson, SerJson};
 use wasm_bindgen::prelude::wasm_bindgen;
 use web_sys::HtmlCanvasElement;
 use wgpu::SurfaceTarget;
@@ -14,6 +15,32 @@ struct DummyImpl;
 impl Cubes for DummyImpl {
     fn spawn_cube(&mut self, _x: i32, _y: i32, _color: logic::well::Block) {}
 }
+const RECORDS_KEY: &str = "edrefis-records";
+const REPLAY_KEY_PREFIX: &str = "edrefis-replay-";
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+fn load_records() -> Records {
+    local_storage()
+        .and_then(|storage| storage.get_item(RECORDS_KEY).ok()?)
+        .and_then(|contents| Records::deserialize_json(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save_records(records: &Records) {
+    if let Some(storage) = local_storage() {
+        let _ = storage.set_item(RECORDS_KEY, &records.serialize_json());
+    }
+}
+
+fn save_replay(replay: &GameRecord, date: u64) -> Option<String> {
+    let key = format!("{}{}", REPLAY_KEY_PREFIX, date);
+    local_storage()?.set_item(&key, &replay.serialize_json()).ok()?;
+    Some(key)
+}
+
 impl Sounds for DummyImpl {
     fn block_spawn(&mut self, _color: logic::well::Block) {}
     fn line_clear(&mut self) {}
@@ -26,9 +53,17 @@ pub struct App {
     gpu: State<'static>,
     graphics: Graphics,
     field: Field,
+    replay: GameRecord,
     inputs: Inputs,
     input_provider: WebInputs,
     ticks: u64,
+    rewind: Rewind,
+    rewinding: bool,
+    stats: Stats,
+    result: Option<GameResult>,
+    show_stats: bool,
+    records: Records,
+    rank: Option<usize>,
 }
 
 fn input_to_web_code(key: Input) -> &'static str {
@@ -87,13 +122,22 @@ impl App {
         }).await.map_err(|e| format!("failed to set up gpu: {}", e))?;
         let graphics = Graphics::new(&mut gpu).map_err(|e| format!("failed to load graphics: {}", e))?;
 
+        let field = Field::new();
         Ok(App {
             gpu,
             graphics,
-            field: Field::new(),
+            replay: GameRecord::new(&field),
+            field,
             inputs: Inputs::new(),
             input_provider: WebInputs::new(),
             ticks: 0u64,
+            rewind: Rewind::new(60 * 60),
+            rewinding: false,
+            stats: Stats::new(),
+            result: None,
+            show_stats: false,
+            records: load_records(),
+            rank: None,
         })
     }
 }
@@ -106,25 +150,84 @@ impl App {
     pub fn tick(&mut self) {
         let mut sounds = DummyImpl;
         let mut cubes = DummyImpl;
+        if self.rewinding {
+            self.rewind.step_back(&mut self.ticks, &mut self.field, &mut self.inputs, &mut self.stats);
+            return;
+        }
+
+        let was_over = matches!(self.field.state, GameState::GameOver { .. });
+        self.rewind.record(self.ticks, &self.field, &self.inputs, &self.stats);
+
         self.ticks += 1;
         self.inputs.tick(self.ticks, &mut self.input_provider);
-        self.field.update(&mut self.inputs, &mut sounds, &mut cubes);
+        self.replay.record(&self.inputs);
+        let locked = self.field.update(&mut self.inputs, &mut sounds, &mut cubes);
+        self.stats.update(&self.field, &self.inputs, locked);
+
+        let is_over = matches!(self.field.state, GameState::GameOver { .. });
+        if !was_over && is_over {
+            let finished = GameResult::new(&self.field, &self.stats);
+            if !self.rewind.is_practice() {
+                let date = (js_sys::Date::now() / 1000.) as u64;
+                let mut entry = RecordEntry::new(&finished, date);
+                if self.records.qualifies(NORMAL_MODE, &entry) {
+                    entry.replay = save_replay(&self.replay, date);
+                }
+                self.rank = self.records.submit(NORMAL_MODE, entry);
+                save_records(&self.records);
+            }
+            self.result = Some(finished);
+        } else if was_over && !is_over {
+            self.stats = Stats::new();
+            self.result = None;
+            self.rank = None;
+            self.rewind.reset();
+            self.replay = GameRecord::new(&self.field);
+        }
     }
     pub fn draw(&mut self) -> Result<(), String> {
+        let banner = if self.rewind.is_practice() {
+            vec!["Practice".to_string()]
+        } else {
+            vec![]
+        };
+        let hud = if let Some(ref result) = self.result {
+            let mut lines = result.summary();
+            if !self.rewind.is_practice() {
+                lines.push(String::new());
+                lines.extend(self.records.table(NORMAL_MODE, self.rank));
+            }
+            lines
+        } else if self.stats.keys == 0 {
+            self.records.table(NORMAL_MODE, None)
+        } else if self.show_stats {
+            self.stats.summary()
+        } else {
+            vec![]
+        };
         match self.field.state {
             GameState::ActivePiece { piece, .. } => {
-                self.graphics.render(&self.field, &self.field.well, Some(&piece), &self.field.next, &mut self.gpu)?;
+                self.graphics.render(&self.field, &self.field.well, Some(&piece), &self.field.next, &banner, &hud, &mut self.gpu)?;
             }
             _ => {
-                self.graphics.render(&self.field, &self.field.well, None, &self.field.next, &mut self.gpu)?;
+                self.graphics.render(&self.field, &self.field.well, None, &self.field.next, &banner, &hud, &mut self.gpu)?;
             }
         }
         Ok(())
     }
     pub fn key_down(&mut self, event: web_sys::KeyboardEvent) {
+        if event.code() == "Backspace" {
+            self.rewinding = true;
+        }
+        if event.code() == "Tab" && !event.repeat() {
+            self.show_stats = !self.show_stats;
+        }
         self.input_provider.push_key(event.code());
     }
     pub fn key_up(&mut self, event: web_sys::KeyboardEvent) {
+        if event.code() == "Backspace" {
+            self.rewinding = false;
+        }
         self.input_provider.release_key(event.code());
     }
 }
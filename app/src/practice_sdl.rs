@@ -0,0 +1,169 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use glam::Vec2;
+use logic::{fumen::Fumen, practice::{piece_from_letter, piece_letter, PracticeSetup}, well::Block};
+use nanoserde::{DeJson, SerJson};
+use sdl2::{clipboard::ClipboardUtil, event::Event, keyboard::Keycode, mouse::MouseButton};
+
+use crate::{gpu, graphics_gpu::Graphics};
+
+pub const SETUP_PATH: &str = "practice.json";
+
+const PAINT_COLORS: &[Block] = &[
+    Block::Red,
+    Block::Orange,
+    Block::Yellow,
+    Block::Green,
+    Block::Cyan,
+    Block::Blue,
+    Block::Purple,
+    Block::Gray,
+];
+
+pub struct Editor {
+    pub setup: PracticeSetup,
+    paint: Block,
+    stroke: Option<Option<Block>>,
+    message: Option<String>,
+    changed: bool,
+}
+
+pub fn load_setup(path: &str) -> Result<PracticeSetup, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    let setup = PracticeSetup::deserialize_json(&contents).map_err(|e| format!("failed to parse {}: {}", path, e))?;
+    setup.validate().map_err(|e| format!("invalid setup {}: {}", path, e))?;
+    Ok(setup)
+}
+
+pub fn save_setup(setup: &PracticeSetup, path: &str) -> Result<(), String> {
+    std::fs::write(path, setup.serialize_json()).map_err(|e| format!("failed to write {}: {}", path, e))
+}
+
+impl Editor {
+    pub fn new(setup: PracticeSetup) -> Editor {
+        Editor {
+            setup,
+            paint: Block::Red,
+            stroke: None,
+            message: None,
+            changed: true,
+        }
+    }
+    // whether the setup was edited since the last call, so the preview only gets rebuilt then
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+    fn paint_at(&mut self, x: i32, y: i32, gpu_state: &gpu::State) {
+        if let Some(color) = self.stroke {
+            if let Some((row, col)) = Graphics::well_cell_at(gpu_state, Vec2::new(x as f32, y as f32)) {
+                self.setup.paint(row, col, color);
+                self.changed = true;
+            }
+        }
+    }
+    pub fn handle_event(&mut self, event: &Event, gpu_state: &gpu::State, clipboard: &ClipboardUtil) -> bool {
+        match *event {
+            Event::MouseButtonDown { mouse_btn, x, y, .. } => {
+                self.stroke = match mouse_btn {
+                    MouseButton::Left => Some(Some(self.paint)),
+                    MouseButton::Right => Some(None),
+                    _ => None,
+                };
+                self.paint_at(x, y, gpu_state);
+            }
+            Event::MouseMotion { x, y, .. } => {
+                self.paint_at(x, y, gpu_state);
+            }
+            Event::MouseButtonUp { .. } => {
+                self.stroke = None;
+            }
+            Event::KeyDown { keycode: Some(keycode), .. } => {
+                match keycode {
+                    Keycode::Num1 | Keycode::Num2 | Keycode::Num3 | Keycode::Num4 | Keycode::Num5 | Keycode::Num6 | Keycode::Num7 | Keycode::Num8 => {
+                        self.paint = PAINT_COLORS[(keycode.into_i32() - Keycode::Num1.into_i32()) as usize];
+                    }
+                    Keycode::I | Keycode::O | Keycode::T | Keycode::S | Keycode::Z | Keycode::J | Keycode::L => {
+                        if let Some(piece) = keycode.name().chars().next().and_then(piece_from_letter) {
+                            self.setup.queue.push(piece);
+                            self.changed = true;
+                        }
+                    }
+                    Keycode::Backspace => {
+                        self.setup.queue.pop();
+                        self.changed = true;
+                    }
+                    Keycode::Delete => {
+                        self.setup.queue.clear();
+                        self.changed = true;
+                    }
+                    Keycode::PageUp => {
+                        self.setup.level += 10;
+                        self.changed = true;
+                    }
+                    Keycode::PageDown => {
+                        self.setup.level = self.setup.level.saturating_sub(10);
+                        self.changed = true;
+                    }
+                    Keycode::G => {
+                        self.setup.cycle_gravity();
+                        self.changed = true;
+                    }
+                    Keycode::F5 => {
+                        self.message = Some(match save_setup(&self.setup, SETUP_PATH) {
+                            Ok(()) => format!("Saved {}", SETUP_PATH),
+                            Err(e) => e,
+                        });
+                    }
+                    Keycode::F6 => {
+                        self.message = Some(match clipboard.set_clipboard_text(&self.setup.to_fumen().encode()) {
+                            Ok(()) => "Copied fumen".to_string(),
+                            Err(e) => e,
+                        });
+                    }
+                    Keycode::F7 => {
+                        self.message = Some(match clipboard.clipboard_text().and_then(|text| Fumen::decode(&text)) {
+                            Ok(fumen) => {
+                                let setup = PracticeSetup::from_fumen(fumen, self.setup.level, self.setup.gravity);
+                                match setup.validate() {
+                                Ok(()) => {
+                                    self.setup = setup;
+                                    self.changed = true;
+                                    "Pasted fumen".to_string()
+                                }
+                                Err(e) => e,
+                                }
+                            }
+                            Err(e) => e,
+                        });
+                    }
+                    Keycode::F9 => {
+                        self.message = Some(match load_setup(SETUP_PATH) {
+                            Ok(setup) => {
+                                self.setup = setup;
+                                self.changed = true;
+                                format!("Loaded {}", SETUP_PATH)
+                            }
+                            Err(e) => e,
+                        });
+                    }
+                    _ => return false,
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+    pub fn banner(&self) -> Vec<String> {
+        let mut lines = vec![
+            "Practice editor".to_string(),
+            format!("Paint: {}", piece_letter(self.paint)),
+            format!("Queue: {}", if self.setup.queue.is_empty() { "random".to_string() } else { self.setup.queue.iter().map(|it| piece_letter(*it)).collect() }),
+        ];
+        if let Some(ref message) = self.message {
+            lines.push(message.clone());
+        }
+        lines
+    }
+}
@@ -0,0 +1,51 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::PathBuf;
+
+use logic::{recording::GameRecord, records::Records};
+use nanoserde::{DeJson, SerJson};
+
+const RECORDS_FILE: &str = "records.json";
+const REPLAYS_DIR: &str = "replays";
+
+fn data_dir() -> Result<PathBuf, String> {
+    let dir = sdl2::filesystem::pref_path("", "Edrefis").map_err(|e| format!("failed to find data directory: {}", e))?;
+    Ok(PathBuf::from(dir))
+}
+
+fn records_path() -> Result<PathBuf, String> {
+    Ok(data_dir()?.join(RECORDS_FILE))
+}
+
+pub fn load_records() -> Result<Records, String> {
+    let path = records_path()?;
+    if !path.exists() {
+        return Ok(Records::new());
+    }
+    let contents = std::fs::read_to_string(&path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    Records::deserialize_json(&contents).map_err(|e| format!("failed to parse {}: {}", path.display(), e))
+}
+
+pub fn save_records(records: &Records) -> Result<(), String> {
+    let path = records_path()?;
+    std::fs::write(&path, records.serialize_json()).map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
+
+// returns the replay's path relative to the data directory, which is what records refer to it by
+pub fn save_replay(replay: &GameRecord, date: u64) -> Result<String, String> {
+    let dir = data_dir()?.join(REPLAYS_DIR);
+    std::fs::create_dir_all(&dir).map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+    let name = format!("{}.json", date);
+    let path = dir.join(&name);
+    std::fs::write(&path, replay.serialize_json()).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    Ok(format!("{}/{}", REPLAYS_DIR, name))
+}
+
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|it| it.as_secs())
+        .unwrap_or(0)
+}
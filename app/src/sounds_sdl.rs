@@ -44,6 +44,7 @@ impl Sounds for ClientSounds {
             Block::Purple => &self.pieces5,
             Block::Cyan => &self.pieces6,
             Block::Red => &self.pieces7,
+            Block::Gray => return,
         }, 0).unwrap();
     }
     fn lock(&mut self) {
@@ -0,0 +1,78 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::VecDeque;
+
+use macroquad::prelude::*;
+
+use crate::text::{Text, Weight};
+
+const MAX_LINES: usize = 50;
+const VISIBLE_LINES: usize = 8;
+const MAX_INPUT: usize = 200;
+const LINE_HEIGHT: f32 = 16.;
+
+pub struct ChatPanel {
+    lines: VecDeque<(String, Color)>,
+    input: Option<String>,
+}
+
+impl ChatPanel {
+    pub fn new() -> ChatPanel {
+        ChatPanel {
+            lines: VecDeque::new(),
+            input: None,
+        }
+    }
+    pub fn push(&mut self, line: String, color: Color) {
+        self.lines.push_back((line, color));
+        while self.lines.len() > MAX_LINES {
+            self.lines.pop_front();
+        }
+    }
+    pub fn typing(&self) -> bool {
+        self.input.is_some()
+    }
+    // enter opens the input and sends what was typed, escape throws it away; returns the
+    // message to send, if any
+    pub fn handle_keys(&mut self) -> Option<String> {
+        let Some(ref mut input) = self.input else {
+            if is_key_pressed(KeyCode::Enter) {
+                self.input = Some(String::new());
+                while get_char_pressed().is_some() {}
+            }
+            return None;
+        };
+
+        while let Some(ch) = get_char_pressed() {
+            if !ch.is_control() && input.chars().count() < MAX_INPUT {
+                input.push(ch);
+            }
+        }
+        if is_key_pressed(KeyCode::Backspace) {
+            input.pop();
+        }
+        if is_key_pressed(KeyCode::Escape) {
+            self.input = None;
+        } else if is_key_pressed(KeyCode::Enter) {
+            let message = self.input.take().unwrap_or_default();
+            if !message.trim().is_empty() {
+                return Some(message);
+            }
+        }
+        None
+    }
+    pub fn draw(&self, text: &Text) {
+        let x = 10.;
+        let mut y = screen_height() - 10.;
+        if let Some(ref input) = self.input {
+            draw_rectangle(0., y - LINE_HEIGHT, screen_width() / 2., LINE_HEIGHT + 6., Color::new(0., 0., 0., 0.5));
+            text.draw_text(&format!("> {}_", input), x, y, Weight::Medium, WHITE, 14.);
+        }
+        for (line, color) in self.lines.iter().rev().take(VISIBLE_LINES) {
+            y -= LINE_HEIGHT;
+            text.draw_text(line, x, y, Weight::Medium, *color, 14.);
+        }
+    }
+}
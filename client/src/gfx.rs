@@ -24,6 +24,7 @@ pub fn color(block: Block) -> Color {
     Block::Cyan => Color::new(0.0, 0.9411764705882353, 0.8274509803921568, 1.0),
     Block::Blue => Color::new(0.25098039215686274, 0.6235294117647059, 0.9725490196078431, 1.0),
     Block::Purple => Color::new(0.7137254901960784, 0.47058823529411764, 0.9607843137254902, 1.0),
+    Block::Gray => Color::new(0.6, 0.6, 0.6, 1.0),
     }
 }
 pub fn texture_index(block: Block) -> i32 {
@@ -35,6 +36,7 @@ pub fn texture_index(block: Block) -> i32 {
     Block::Cyan => 4,
     Block::Blue => 5,
     Block::Purple => 6,
+    Block::Gray => 7,
     }
 }
 
@@ -4,6 +4,7 @@
 
 use macroquad::prelude::*;
 
+use logic::clock::TICKS_PER_SECOND;
 use logic::input::{Input, InputProvider, Inputs};
 
 fn input_to_macroquad_key(input: Input) -> KeyCode {
@@ -17,7 +18,10 @@ fn input_to_macroquad_key(input: Input) -> KeyCode {
     }
 }
 
-pub struct MacroquadInputProvider;
+// a muted provider reports every key as up, so anything held is released rather than stuck
+pub struct MacroquadInputProvider {
+    muted: bool,
+}
 impl InputProvider for MacroquadInputProvider {
     fn peek(&mut self) {
     }
@@ -26,21 +30,22 @@ impl InputProvider for MacroquadInputProvider {
     }
 
     fn key_just_pressed(&self, input: Input) -> bool {
-        is_key_pressed(input_to_macroquad_key(input))
+        !self.muted && is_key_pressed(input_to_macroquad_key(input))
     }
 
     fn key_down(&self, input: Input) -> bool {
-        is_key_down(input_to_macroquad_key(input))
+        !self.muted && is_key_down(input_to_macroquad_key(input))
     }
 }
 
 impl MacroquadInputProvider {
     pub fn new() -> MacroquadInputProvider {
-        MacroquadInputProvider {}
+        MacroquadInputProvider { muted: false }
     }
 }
 
 pub trait Updater {
+    fn accepts_input(&self) -> bool;
     fn update(&mut self, inputs: &Inputs, ticks: u64);
     fn draw(&mut self);
 }
@@ -63,8 +68,9 @@ impl<U: Updater> Ticker<U> {
     }
     pub async fn run(&mut self) {
         loop {
-            let expected_update_calls_count = (get_time() * 60.) as u64;
+            let expected_update_calls_count = (get_time() * TICKS_PER_SECOND as f64) as u64;
             for _ in 0..expected_update_calls_count - self.update_calls_count {
+                self.macroquad_inputs.muted = !self.updater.accepts_input();
                 self.inputs.tick(self.update_calls_count, &mut self.macroquad_inputs);
                 self.update_calls_count += 1;
                 self.updater.update(&self.inputs, self.update_calls_count);
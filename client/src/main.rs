@@ -2,29 +2,51 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use core::str;
-use std::collections::{HashSet, VecDeque};
+use std::collections::VecDeque;
 
+use chat::ChatPanel;
 use cubes::{lerp, ClientCubes};
-use logic::field::{level_to_gravity, Field, GameState};
+use logic::clock::{ClockSync, TICKS_PER_SECOND};
+use logic::field::{Field, GameState};
 use gfx::{color, Graphics, DST_BLOCK_SIZE};
 use macroquad::prelude::*;
-use logic::input::{Input, InputProvider, Inputs, INPUTS};
-use macroutils::{MacroquadInputProvider, Ticker, Updater};
-use nanoserde::{DeJson, SerJson};
-use logic::proto::{ClientToServer, ServerToClient};
-use quad_net::quad_socket::client::QuadSocket;
-use replay::Replay;
+use logic::hooks::Silent;
+use logic::input::{Inputs, INPUTS};
+use macroutils::{Ticker, Updater};
+use nakama::Nakama;
+use online::{Online, OnlineEvent};
+use logic::proto::{decode_server, encode_client, ClientToServer, ServerToClient, PROTOCOL_VERSION};
+use logic::wire::WireFormat;
+use logic::rollback::{RemoteEvent, Rollback, RollbackConfig};
+use logic::snapshot::Snapshot;
+use logic::netsim::{NetConditions, SimulatedLink};
+use replay::{Replay, ReplayInputProvider};
+use socket::Socket;
 use sound::ClientSounds;
 use text::{Text, Weight};
+use logic::stats::{GameResult, Stats};
 use logic::well::{WELL_COLS, WELL_ROWS};
 
+mod chat;
 mod cubes;
 mod gfx;
 mod macroutils;
+mod nakama;
+mod online;
+mod replay;
+mod socket;
 mod sound;
 mod text;
-mod replay;
+
+const TIME_SYNC_INTERVAL: u64 = TICKS_PER_SECOND;
+// Field::new and the restart after a game over both deal from this seed
+const SOLO_SEED: u32 = 10;
+// a field played back from a replay doesn't belong to any client
+const REPLAY_FIELD: u32 = u32::MAX;
+
+fn micros() -> u64 {
+    (get_time() * 1_000_000.) as u64
+}
 
 struct FieldAndGraphics {
     render_target: RenderTarget,
@@ -38,10 +60,18 @@ struct FieldAndGraphics {
 
     field: Field,
     cubes: ClientCubes,
+    stats: Stats,
 
     client_id: u32,
+    tick: u64,
 
-    inputs_override: Option<(Inputs, NetworkInputProvider)>,
+    rollback: Option<Rollback>,
+    playback: Option<Playback>,
+}
+
+struct Playback {
+    inputs: Inputs,
+    provider: ReplayInputProvider,
 }
 
 fn make(w: f32, h: f32) -> (RenderTarget, Camera2D) {
@@ -54,7 +84,7 @@ fn make(w: f32, h: f32) -> (RenderTarget, Camera2D) {
 }
 
 impl FieldAndGraphics {
-    fn new(inputs_override: Option<(Inputs, NetworkInputProvider)>, field: Field, client_id: u32) -> FieldAndGraphics {
+    fn new(rollback: Option<Rollback>, field: Field, client_id: u32) -> FieldAndGraphics {
         let (well_render_target, render_target_cam) = make(
             DST_BLOCK_SIZE * WELL_COLS as f32,
             DST_BLOCK_SIZE * WELL_ROWS as f32,
@@ -64,7 +94,7 @@ impl FieldAndGraphics {
         let (next_target, next_target_cam) = make(DST_BLOCK_SIZE * 4., DST_BLOCK_SIZE * 2.);
 
         let (left_ui_target, left_ui_cam) =
-            make(DST_BLOCK_SIZE * 5 as f32, DST_BLOCK_SIZE * WELL_ROWS as f32);
+            make(DST_BLOCK_SIZE * 5., DST_BLOCK_SIZE * WELL_ROWS as f32);
 
         FieldAndGraphics {
             render_target: well_render_target,
@@ -75,8 +105,11 @@ impl FieldAndGraphics {
             left_ui_cam,
             field,
             cubes: ClientCubes::new(),
+            stats: Stats::new(),
             client_id,
-            inputs_override,
+            tick: 0,
+            rollback,
+            playback: None,
         }
     }
 }
@@ -85,14 +118,26 @@ struct Game {
     graphics: Graphics,
     sounds: ClientSounds,
     text: Text,
-    replay: Replay,
     my_id: u32,
-    network: QuadSocket,
+    session_token: Option<String>,
+    network: SimulatedLink<Socket>,
+    format: WireFormat,
+    handshake: bool,
     last_tick: f64,
 
     fields: Vec<FieldAndGraphics>,
+    rollback_config: RollbackConfig,
+    // the server tick the match started on, and the local tick its start message arrived on
+    match_start: Option<(u64, u64)>,
+    spectating: bool,
+    show_stats: bool,
     fps: VecDeque<i32>,
     differences: VecDeque<f64>,
+    clock: ClockSync,
+    chat: ChatPanel,
+    // the solo game being played, which is only replayable if it started from SOLO_SEED
+    replay: Option<Replay>,
+    online: Option<Online>,
 }
 
 impl Game {
@@ -199,7 +244,7 @@ impl Game {
         let gravity_header =
             self.text
                 .draw_text("Gravity", sidebar_x, gravity_y, Weight::Medium, WHITE, 24.);
-        let gravity = level_to_gravity(field.field.level);
+        let gravity = field.field.gravity();
         let gravity_label = self.text.draw_text(
             &format!(
                 "{}",
@@ -258,6 +303,13 @@ impl Game {
             WHITE,
             24.,
         );
+
+        if self.show_stats {
+            let stats_y = 300.;
+            for (idx, line) in field.stats.summary().iter().enumerate() {
+                self.text.draw_text(line, sidebar_x, stats_y + idx as f32 * 18., Weight::Medium, WHITE, 16.);
+            }
+        }
     }
     fn draw_field(&self, field: &FieldAndGraphics, offset: Mat4) {
         self.draw_field_well(field);
@@ -320,83 +372,266 @@ impl Game {
         self.text.draw_text(&format!("Average ms between ticks: {:.2}", (self.differences.iter().sum::<f64>() / self.differences.len() as f64) * 1000.), 10., 46., Weight::Medium, WHITE, 12.);
         self.text.draw_text(&format!("Upper 25% ticks: {:.2}", (upper_ticks / amt_ticks) * 1000.), 10., 58., Weight::Medium, WHITE, 12.);
         self.text.draw_text(&format!("Lower 25% ticks: {:.2}", (lower_ticks / amt_ticks) * 1000.), 10., 70., Weight::Medium, WHITE, 12.);
+        let millis = |micros: Option<i64>| micros.map(|it| format!("{:.2}", it as f64 / 1000.)).unwrap_or("-".to_string());
+        self.text.draw_text(&format!("Clock offset ms: {}", millis(self.clock.offset())), 10., 82., Weight::Medium, WHITE, 12.);
+        self.text.draw_text(&format!("Round trip ms: {}", millis(self.clock.rtt().map(|it| it as i64))), 10., 94., Weight::Medium, WHITE, 12.);
+        self.text.draw_text(&format!("Jitter ms: {}", millis(self.clock.jitter().map(|it| it as i64))), 10., 106., Weight::Medium, WHITE, 12.);
+        let server_tick = self.clock.server_tick(micros(), TICKS_PER_SECOND as f64).map(|it| it.to_string()).unwrap_or("-".to_string());
+        self.text.draw_text(&format!("Server tick: {}", server_tick), 10., 118., Weight::Medium, WHITE, 12.);
     }
 }
 
-struct NetworkInputProvider {
-    just_pressed: HashSet<Input>,
-    current: HashSet<Input>,
-}
-impl InputProvider for NetworkInputProvider {
-    fn peek(&mut self) {
+impl Game {
+    fn apply_snapshot(&mut self, client_id: u32, tick: u64, snapshot: Snapshot) {
+        match self.fields.iter_mut().find(|it| it.client_id == client_id) {
+        Some(field) => {
+            if let Some(updated) = snapshot.apply(Some((field.tick, &field.field))) {
+                field.field = updated;
+                field.tick = tick;
+            }
+        }
+        None => {
+            if let Some(updated) = snapshot.apply(None) {
+                let mut field = FieldAndGraphics::new(None, updated, client_id);
+                field.tick = tick;
+                self.fields.push(field);
+            }
+        }
+        }
     }
-    fn consume(&mut self) {
-        self.just_pressed.clear();
+    fn welcome(&mut self, client_id: u32, token: String) {
+        for field in self.fields.iter_mut().filter(|it| it.client_id == self.my_id) {
+            field.client_id = client_id;
+        }
+        self.my_id = client_id;
+        self.session_token = Some(token);
+    }
+    fn rollback_for(&mut self, client_id: u32) -> Option<&mut Rollback> {
+        self.fields.iter_mut().find(|it| it.client_id == client_id).and_then(|it| it.rollback.as_mut())
     }
-    fn key_just_pressed(&self, input: Input) -> bool {
-        self.just_pressed.contains(&input)
+    fn start_match(&mut self, seed: u32, start_tick: u64, ticks: u64) {
+        self.replay = None;
+        for field in &mut self.fields {
+            field.field = Field::seeded(seed);
+            field.stats = Stats::new();
+            field.rollback = Some(Rollback::new(field.field.clone(), 0, self.rollback_config));
+        }
+        self.match_start = Some((start_tick, ticks));
+    }
+    fn end_match(&mut self) {
+        for field in &mut self.fields {
+            field.rollback = None;
+        }
+        self.match_start = None;
     }
-    fn key_down(&self, input: Input) -> bool {
-        self.current.contains(&input)
+    fn watch(&mut self, replay: Replay) {
+        let mut field = FieldAndGraphics::new(None, Field::seeded(replay.seed()), REPLAY_FIELD);
+        field.playback = Some(Playback { inputs: Inputs::new(), provider: ReplayInputProvider::new(replay) });
+        self.fields.retain(|it| it.client_id != REPLAY_FIELD);
+        self.fields.push(field);
+    }
+    fn poll_online(&mut self) {
+        let Some(ref mut online) = self.online else {
+            return;
+        };
+        for event in online.poll(miniquad::date::now() as u64) {
+            match event {
+            OnlineEvent::Message(message) => self.chat.push(message, YELLOW),
+            OnlineEvent::Replay(replay) => self.watch(replay),
+            }
+        }
+    }
+    fn update_solo(&mut self, inputs: &Inputs) {
+        let mut finished = None;
+        for field in self.fields.iter_mut().filter(|it| it.client_id == self.my_id && !self.spectating) {
+            let was_over = matches!(field.field.state, GameState::GameOver { .. });
+            if let Some(ref mut replay) = self.replay {
+                replay.replay_tick(inputs);
+            }
+            let locked = field.field.update(inputs, &mut self.sounds, &mut field.cubes);
+            field.stats.update(&field.field, inputs, locked);
+
+            let is_over = matches!(field.field.state, GameState::GameOver { .. });
+            if !was_over && is_over {
+                finished = Some(GameResult::new(&field.field, &field.stats));
+            } else if was_over && !is_over {
+                self.replay = Some(Replay::new(SOLO_SEED));
+            }
+        }
+        let (Some(result), Some(replay), Some(online)) = (finished, self.replay.as_ref(), self.online.as_mut()) else {
+            return;
+        };
+        let key = (miniquad::date::now() as u64).to_string();
+        if online.submit(&key, replay, result.level, result.ticks) {
+            self.chat.push(format!("uploading the replay, pass --watch {} to see it again", key), YELLOW);
+        }
+    }
+    fn update_playback(&mut self) {
+        for field in &mut self.fields {
+            let Some(ref mut playback) = field.playback else {
+                continue;
+            };
+            if matches!(field.field.state, GameState::GameOver { .. }) {
+                continue;
+            }
+            playback.inputs.tick(field.tick, &mut playback.provider);
+            field.tick += 1;
+            let locked = field.field.update(&playback.inputs, &mut Silent, &mut field.cubes);
+            field.stats.update(&field.field, &playback.inputs, locked);
+        }
+    }
+    // ticks since the match started, going by the server's clock once it's known
+    fn match_tick(&self, ticks: u64) -> Option<u64> {
+        let (start_tick, started_at) = self.match_start?;
+        match self.clock.server_tick(micros(), TICKS_PER_SECOND as f64) {
+        Some(tick) => Some(tick.saturating_sub(start_tick)),
+        None => Some(ticks.saturating_sub(started_at)),
+        }
+    }
+    // our own inputs are sent stamped input_delay ticks ahead and applied locally at that same
+    // tick, so our field matches the server's; garbage can still arrive late and roll it back
+    fn update_match(&mut self, inputs: &Inputs, tick: u64) {
+        let config = self.rollback_config;
+        let my_id = self.my_id;
+        if !self.spectating {
+            for input in INPUTS {
+                let up = if inputs.key_just_pressed(*input) {
+                    true
+                } else if inputs.key_just_released(*input) {
+                    false
+                } else {
+                    continue;
+                };
+                let at = tick + config.input_delay;
+                self.network.send(get_time(), &encode_client(&ClientToServer::Input { input: *input, up, tick: at }, self.format));
+                if let Some(rollback) = self.rollback_for(my_id) {
+                    rollback.receive(at, RemoteEvent::Input { input: *input, up });
+                }
+            }
+        }
+        for field in &mut self.fields {
+            let Some(rollback) = field.rollback.as_mut() else {
+                continue;
+            };
+            if field.client_id == my_id {
+                rollback.confirm(tick.saturating_sub(config.max_rollback));
+                rollback.advance(tick + 1 + config.input_delay, &mut self.sounds, &mut field.cubes);
+            } else {
+                rollback.advance(tick + 1, &mut Silent, &mut field.cubes);
+            }
+            field.field = rollback.field().clone();
+            field.stats = rollback.stats().clone();
+        }
+    }
+    // a simulated outage drops the connection, so come back with the session token once it's over
+    fn reconnect(&mut self) {
+        if !self.network.can_reconnect(get_time()) {
+            return;
+        }
+        let Some(socket) = Socket::connect() else {
+            return;
+        };
+        self.network.reconnect(socket);
+        self.handshake = false;
+        self.network.send(get_time(), &encode_client(&ClientToServer::Hello { version: PROTOCOL_VERSION, format: self.format }, WireFormat::Json));
+        self.network.send(get_time(), &encode_client(&ClientToServer::Join { token: self.session_token.clone() }, self.format));
+    }
+    fn receive(&mut self, ticks: u64) {
+        while let Some(bytes) = self.network.try_recv(get_time()) {
+            let format = if self.handshake { self.format } else { WireFormat::Json };
+            let Ok(msg) = decode_server(&bytes, format) else {
+                continue;
+            };
+            match msg {
+            ServerToClient::Hello { .. } => {
+                self.handshake = true;
+            }
+            ServerToClient::Welcome { client_id, token, .. } => {
+                self.welcome(client_id, token);
+            }
+            ServerToClient::Ping { nonce } => {
+                self.network.send(get_time(), &encode_client(&ClientToServer::Pong { nonce }, self.format));
+            }
+            ServerToClient::TimeSync { client_time, server_time, tick } => {
+                self.clock.sample(client_time, server_time, tick, micros());
+            }
+            ServerToClient::Snapshot { client_id, tick, snapshot } => {
+                self.apply_snapshot(client_id, tick, snapshot);
+            }
+            ServerToClient::Join { client_id, field } => {
+                self.fields.retain(|it| it.client_id != client_id);
+                self.fields.push(FieldAndGraphics::new(None, field, client_id));
+            }
+            ServerToClient::Leave { client_id } => {
+                self.fields.retain(|f| { f.client_id != client_id });
+            }
+            ServerToClient::RoomLeft {} => {
+                self.fields.clear();
+                self.end_match();
+            }
+            ServerToClient::Start { seed, start_tick } => {
+                self.start_match(seed, start_tick, ticks);
+            }
+            ServerToClient::RoomFinished { .. } => {
+                self.end_match();
+            }
+            ServerToClient::Input { client_id, input, up, tick } => {
+                if let Some(rollback) = self.rollback_for(client_id) {
+                    rollback.receive(tick, RemoteEvent::Input { input, up });
+                }
+            }
+            ServerToClient::Garbage { client_id, lines, tick } => {
+                if let Some(rollback) = self.rollback_for(client_id) {
+                    rollback.receive(tick, RemoteEvent::Garbage { lines });
+                }
+            }
+            ServerToClient::Tick { client_id, tick } => {
+                if let Some(rollback) = self.rollback_for(client_id) {
+                    rollback.confirm(tick);
+                }
+            }
+            ServerToClient::Chat { name, message, .. } => {
+                self.chat.push(format!("{}: {}", name, message), WHITE);
+            }
+            ServerToClient::Announcement { message } => {
+                self.chat.push(format!("[server] {}", message), YELLOW);
+            }
+            ServerToClient::RoomError { message } => {
+                self.chat.push(message, ORANGE);
+            }
+            ServerToClient::Identified { player, key } => {
+                self.chat.push(format!("playing as {}, pass --player-key {} to use this name again", player, key), YELLOW);
+            }
+            _ => {}
+            }
+        }
     }
 }
 
 impl Updater for Game {
+    // keys typed into chat shouldn't also move pieces
+    fn accepts_input(&self) -> bool {
+        !self.chat.typing()
+    }
     fn update(&mut self, inputs: &Inputs, ticks: u64) {
-        // for input in INPUTS {
-        //     if inputs.key_just_pressed(*input) {
-        //         self.network.send(ClientToServer::Input { input: *input, up: true }.serialize_json().as_bytes());
-        //     } else if inputs.key_just_released(*input) {
-        //         self.network.send(ClientToServer::Input { input: *input, up: false }.serialize_json().as_bytes());
-        //     }
-        // }
-
-        // while let Some(bytes) = self.network.try_recv() {
-        //     let msg = ServerToClient::deserialize_json(str::from_utf8(&bytes).unwrap()).unwrap();
-        //     match msg {
-        //     ServerToClient::Join { client_id, field } => {
-        //         self.fields.push(FieldAndGraphics::new(Some((Inputs::new(), NetworkInputProvider {
-        //             just_pressed: HashSet::new(),
-        //             current: HashSet::new(),
-        //         })), field, client_id));
-        //     }
-        //     ServerToClient::Leave { client_id } => {
-        //         self.fields.retain(|f| { f.client_id != client_id });
-        //     }
-        //     ServerToClient::Input { client_id, input, up } => {
-        //         if let Some(field) = self.fields.iter_mut().find(|it| it.client_id == client_id) {
-        //             if let Some((_inputs, provider)) = &mut field.inputs_override {
-        //                 if up {
-        //                     provider.just_pressed.insert(input);
-        //                     provider.current.insert(input);
-        //                 } else {
-        //                     provider.current.remove(&input);
-        //                 }
-        //             }
-        //         }
-        //     }
-        //     ServerToClient::Tick { client_id } => {
-        //         if let Some(field) = self.fields.iter_mut().find(|it| it.client_id == client_id) {
-        //             if let Some((ref mut inner, ref mut provider)) = &mut field.inputs_override {
-        //                 inner.tick(ticks, provider);
-        //                 field.field.update(&inner, &mut self.sounds, &mut field.cubes);
-        //             }
-        //         }
-        //     }
-        //     }
-        // }
-
-        // self.replay.replay_tick(inputs);
-        // for field in &mut self.fields {
-        //     if let Some(_) = field.inputs_override {
-        //         // inner.tick(ticks, provider);
-        //         // field.field.update(&inner, &mut self.sounds, &mut field.cubes);
-        //     } else {
-        //         field.field.update(&inputs, &mut self.sounds, &mut field.cubes);
-        //     }
-        //     field.cubes.tick();
-        // }
-        // self.network.send(ClientToServer::Tick {}.serialize_json().as_bytes());
+        self.reconnect();
+        self.receive(ticks);
+        self.poll_online();
+        if self.handshake && ticks.is_multiple_of(TIME_SYNC_INTERVAL) {
+            self.network.send(get_time(), &encode_client(&ClientToServer::TimeSync { client_time: micros() }, self.format));
+        }
+
+        match self.match_tick(ticks) {
+        Some(tick) => self.update_match(inputs, tick),
+        None => self.update_solo(inputs),
+        }
+        self.update_playback();
+        for field in &mut self.fields {
+            field.cubes.tick();
+        }
+        if is_key_pressed(KeyCode::Tab) && !self.chat.typing() {
+            self.show_stats = !self.show_stats;
+        }
         self.fps.push_back(get_fps());
         while self.fps.len() >= 60*10 {
             self.fps.pop_front();
@@ -415,8 +650,6 @@ impl Updater for Game {
         let gl = unsafe { get_internal_gl() }.quad_gl;
 
         self.draw_perf();
-        return;
-
         for (idx, field) in self.fields.iter().enumerate() {
             self.draw_field(
                 field,
@@ -442,18 +675,45 @@ impl Updater for Game {
             }
             gl.pop_model_matrix();
         }
+
+        // chat keys are read once per frame rather than in update, which can run several times a
+        // frame and would see the same enter press twice
+        if let Some(message) = self.chat.handle_keys() {
+            self.network.send(get_time(), &encode_client(&ClientToServer::Chat { message }, self.format));
+        }
+        set_default_camera();
+        self.chat.draw(&self.text);
     }
 }
 
 #[macroquad::main("Edrefis")]
 async fn main() {
     macroquad::rand::srand(macroquad::miniquad::date::now() as u64);
-    let my_id = macroquad::rand::rand();
+    let my_id = 0;
+    let spectate = std::env::args().skip_while(|it| it != "--spectate").nth(1);
+    let player = std::env::args().skip_while(|it| it != "--player").nth(1);
+    let player_key = std::env::args().skip_while(|it| it != "--player-key").nth(1);
+    let format = if std::env::args().any(|it| it == "--json") { WireFormat::Json } else { WireFormat::Binary };
+    let mut rollback_config = RollbackConfig::new();
+    if let Some(delay) = std::env::args().skip_while(|it| it != "--input-delay").nth(1).and_then(|it| it.parse().ok()) {
+        rollback_config.input_delay = delay;
+    }
+    if let Some(max) = std::env::args().skip_while(|it| it != "--max-rollback").nth(1).and_then(|it| it.parse().ok()) {
+        rollback_config.max_rollback = max;
+    }
 
     let mut ticker = Ticker::new(Game {
-        fields: vec![
-            FieldAndGraphics::new(None, Field::new(), my_id),
-        ],
+        fields: if spectate.is_some() {
+            vec![]
+        } else {
+            vec![
+                FieldAndGraphics::new(None, Field::new(), my_id),
+            ]
+        },
+        rollback_config,
+        match_start: None,
+        spectating: spectate.is_some(),
+        show_stats: false,
             // if cfg!(target_arch = "wasm32") {
             //     vec![FieldAndGraphics::new(None)]
             // } else {
@@ -469,24 +729,36 @@ async fn main() {
             //     ]
             // },
         my_id,
+        session_token: None,
         network: {
-            #[cfg(not(target_arch = "wasm32"))]
-            let mut socket = QuadSocket::connect("blackquill.cc:8088").unwrap();
-            #[cfg(target_arch = "wasm32")]
-            let mut socket = QuadSocket::connect("wss://1293045598395830332.discordsays.com/.proxy/api").unwrap();
+            let socket = Socket::connect().unwrap();
             #[cfg(target_arch = "wasm32")]
             {
-                while socket.is_wasm_websocket_connected() == false {
+                while !socket.is_connected() {
                     next_frame().await;
                 }
             }
-            socket.send(ClientToServer::Join { client_id: my_id }.serialize_json().as_bytes());
+            let args = std::env::args().collect::<Vec<_>>();
+            let now = get_time();
+            let mut socket = SimulatedLink::new(socket, NetConditions::from_args(&args), macroquad::rand::rand(), now);
+            socket.send(now, &encode_client(&ClientToServer::Hello { version: PROTOCOL_VERSION, format }, WireFormat::Json));
+            socket.send(now, &encode_client(&ClientToServer::Join { token: None }, format));
+            if let Some(ref name) = spectate {
+                socket.send(now, &encode_client(&ClientToServer::Spectate { name: name.clone() }, format));
+            }
+            if let Some(player) = player {
+                socket.send(now, &encode_client(&ClientToServer::Identify { player, key: player_key }, format));
+                if std::env::args().any(|it| it == "--ranked") {
+                    socket.send(now, &encode_client(&ClientToServer::QueueRanked {}, format));
+                }
+            }
             socket
         },
+        format,
+        handshake: false,
         graphics: Graphics::new(),
         text: Text::new().unwrap(),
         sounds: ClientSounds::new().await.unwrap(),
-        replay: Replay::new(10),
         fps: {
             let mut it = VecDeque::new();
             it.push_back(60);
@@ -494,6 +766,16 @@ async fn main() {
         },
         last_tick: get_time(),
         differences: VecDeque::new(),
+        clock: ClockSync::new(16),
+        chat: ChatPanel::new(),
+        replay: Some(Replay::new(SOLO_SEED)),
+        online: std::env::args().skip_while(|it| it != "--nakama").nth(1).map(|url| {
+            let key = std::env::args().skip_while(|it| it != "--nakama-key").nth(1).unwrap_or("defaultkey".to_string());
+            let device_id = std::env::args().skip_while(|it| it != "--device-id").nth(1)
+                .unwrap_or_else(|| format!("edrefis-{:08x}{:08x}", macroquad::rand::rand(), macroquad::rand::rand()));
+            let watch = std::env::args().skip_while(|it| it != "--watch").nth(1);
+            Online::new(Nakama::new(&key, &url), &device_id, watch)
+        }),
     });
     ticker.run().await
 }
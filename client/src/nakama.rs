@@ -4,7 +4,7 @@
 
 use std::marker::PhantomData;
 use nanoserde::{DeJson, DeJsonErr, SerJson};
-use quad_net::{http_request::{HttpError, Request, RequestBuilder}, web_socket::WebSocket};
+use quad_net::http_request::{HttpError, Method, Request, RequestBuilder};
 use urlencoding::encode;
 
 pub struct DecoderRequest<T: DeJson> {
@@ -15,15 +15,23 @@ pub struct DecoderRequest<T: DeJson> {
 #[derive(Debug)]
 pub enum RequestError {
     Json(DeJsonErr),
-    Http(HttpError),
+    Http(Box<HttpError>),
+}
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+        RequestError::Json(error) => write!(f, "bad response: {}", error),
+        RequestError::Http(error) => write!(f, "request failed: {:?}", error),
+        }
+    }
 }
 impl<T: DeJson> DecoderRequest<T> {
     pub fn try_recv(&mut self) -> Option<Result<T, RequestError>> {
         self.request.try_recv()
             .map(|res| {
                 res
-                    .map_err(|it| RequestError::Http(it))
-                    .and_then(|data| { T::deserialize_json(&data).map_err(|it| RequestError::Json(it)) })
+                    .map_err(|e| RequestError::Http(Box::new(e)))
+                    .and_then(|data| { T::deserialize_json(&data).map_err(RequestError::Json) })
             })
     }
 }
@@ -36,11 +44,139 @@ pub struct Nakama {
     key: String,
     base_url: String,
 }
-#[derive(DeJson, Debug)]
+#[derive(DeJson, Debug, Clone)]
 pub struct Session {
     pub token: String,
     pub refresh_token: String,
 }
+#[derive(DeJson, Debug, Clone)]
+// mirrors the REST response, not all of which the game shows
+#[allow(dead_code)]
+pub struct LeaderboardRecord {
+    pub leaderboard_id: String,
+    #[nserde(default)]
+    pub owner_id: String,
+    #[nserde(default)]
+    pub username: String,
+    #[nserde(default)]
+    pub score: String,
+    #[nserde(default)]
+    pub subscore: String,
+    #[nserde(default)]
+    pub metadata: String,
+    #[nserde(default)]
+    pub rank: String,
+}
+#[derive(DeJson, Debug)]
+// mirrors the REST response, not all of which the game shows
+#[allow(dead_code)]
+pub struct LeaderboardRecordList {
+    #[nserde(default)]
+    pub records: Vec<LeaderboardRecord>,
+    #[nserde(default)]
+    pub owner_records: Vec<LeaderboardRecord>,
+    #[nserde(default)]
+    pub next_cursor: String,
+    #[nserde(default)]
+    pub prev_cursor: String,
+}
+#[derive(DeJson, Debug, Clone)]
+// mirrors the REST response, not all of which the game shows
+#[allow(dead_code)]
+pub struct StorageObject {
+    pub collection: String,
+    pub key: String,
+    #[nserde(default)]
+    pub user_id: String,
+    #[nserde(default)]
+    pub value: String,
+    #[nserde(default)]
+    pub version: String,
+}
+#[derive(DeJson, Debug)]
+pub struct StorageObjects {
+    #[nserde(default)]
+    pub objects: Vec<StorageObject>,
+}
+#[derive(DeJson, Debug)]
+// mirrors the REST response, not all of which the game shows
+#[allow(dead_code)]
+pub struct StorageObjectAck {
+    pub collection: String,
+    pub key: String,
+    #[nserde(default)]
+    pub version: String,
+    #[nserde(default)]
+    pub user_id: String,
+}
+#[derive(DeJson, Debug)]
+// mirrors the REST response, not all of which the game shows
+#[allow(dead_code)]
+pub struct StorageObjectAcks {
+    #[nserde(default)]
+    pub acks: Vec<StorageObjectAck>,
+}
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |acc, (idx, byte)| acc | (*byte as u32) << (16 - idx * 8));
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                out.push(BASE64[(bits >> (18 - idx * 6)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64url_decode(data: &str) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let mut bits = 0u32;
+    let mut count = 0;
+    for ch in data.bytes().take_while(|it| *it != b'=') {
+        let value = match ch {
+        b'-' => 62,
+        b'_' => 63,
+        _ => BASE64.iter().position(|it| *it == ch)? as u32,
+        };
+        bits = bits << 6 | value;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[derive(DeJson)]
+struct Claims {
+    exp: u64,
+    #[nserde(default)]
+    uid: String,
+}
+
+impl Session {
+    fn claims(&self) -> Option<Claims> {
+        let payload = base64url_decode(self.token.split('.').nth(1)?)?;
+        Claims::deserialize_json(std::str::from_utf8(&payload).ok()?).ok()
+    }
+    pub fn expires_at(&self) -> Option<u64> {
+        self.claims().map(|it| it.exp)
+    }
+    pub fn user_id(&self) -> Option<String> {
+        self.claims().map(|it| it.uid).filter(|it| !it.is_empty())
+    }
+    pub fn needs_refresh(&self, now: u64, margin: u64) -> bool {
+        self.expires_at().map(|exp| now + margin >= exp).unwrap_or(true)
+    }
+}
+
 impl Nakama {
     pub fn new(key: &str, base_url: &str) -> Nakama {
         Nakama {
@@ -51,6 +187,25 @@ impl Nakama {
     fn make_uri(&self, uri: &str) -> String {
         self.base_url.clone() + uri
     }
+    fn basic_auth(&self) -> String {
+        format!("Basic {}", base64_encode(format!("{}:", self.key).as_bytes()))
+    }
+    fn bearer_auth(session: &Session) -> String {
+        format!("Bearer {}", session.token)
+    }
+    fn request<T: DeJson>(&self, method: Method, uri: &str, authorization: &str, body: Option<&str>) -> DecoderRequest<T> {
+        let builder = RequestBuilder::new(&self.make_uri(uri))
+            .header("Content-Type", "application/json")
+            .header("Authorization", authorization)
+            .method(method);
+        match body {
+            Some(body) => builder.body(body),
+            None => builder,
+        }
+        .send()
+        .into()
+    }
+    #[allow(dead_code)]
     pub fn authenticate_email(&self, email: &str, password: &str) -> DecoderRequest<Session> {
         #[derive(SerJson)]
         struct AuthenticateEmail<'a> {
@@ -58,15 +213,235 @@ impl Nakama {
             password: &'a str,
         }
 
-        RequestBuilder::new(&self.make_uri("/v2/account/authenticate/email"))
-            .header("Content-Type", "application/json")
-            .header("Authorization", "Basic ZGVmYXVsdGtleTo=")
-            .method(quad_net::http_request::Method::Post)
-            .body(
-                &AuthenticateEmail { email, password }
-                .serialize_json(),
-            )
-            .send()
-            .into()
+        self.request(
+            Method::Post,
+            "/v2/account/authenticate/email",
+            &self.basic_auth(),
+            Some(&AuthenticateEmail { email, password }.serialize_json()),
+        )
+    }
+    fn authenticate_id(&self, kind: &str, id: &str, create: bool, username: Option<&str>) -> DecoderRequest<Session> {
+        #[derive(SerJson)]
+        struct AuthenticateId<'a> {
+            id: &'a str,
+        }
+
+        let mut uri = format!("/v2/account/authenticate/{}?create={}", kind, create);
+        if let Some(username) = username {
+            uri += &format!("&username={}", encode(username));
+        }
+        self.request(Method::Post, &uri, &self.basic_auth(), Some(&AuthenticateId { id }.serialize_json()))
+    }
+    pub fn authenticate_device(&self, device_id: &str, create: bool, username: Option<&str>) -> DecoderRequest<Session> {
+        self.authenticate_id("device", device_id, create, username)
+    }
+    #[allow(dead_code)]
+    pub fn authenticate_custom(&self, custom_id: &str, create: bool, username: Option<&str>) -> DecoderRequest<Session> {
+        self.authenticate_id("custom", custom_id, create, username)
+    }
+    pub fn refresh_session(&self, session: &Session) -> DecoderRequest<Session> {
+        #[derive(SerJson)]
+        struct SessionRefresh<'a> {
+            token: &'a str,
+        }
+
+        self.request(
+            Method::Post,
+            "/v2/account/session/refresh",
+            &self.basic_auth(),
+            Some(&SessionRefresh { token: &session.refresh_token }.serialize_json()),
+        )
+    }
+    pub fn write_leaderboard_record(&self, session: &Session, leaderboard_id: &str, score: i64, subscore: i64, metadata: &str) -> DecoderRequest<LeaderboardRecord> {
+        #[derive(SerJson)]
+        struct WriteRecord<'a> {
+            score: String,
+            subscore: String,
+            metadata: &'a str,
+        }
+
+        self.request(
+            Method::Post,
+            &format!("/v2/leaderboard/{}", encode(leaderboard_id)),
+            &Nakama::bearer_auth(session),
+            Some(&WriteRecord { score: score.to_string(), subscore: subscore.to_string(), metadata }.serialize_json()),
+        )
+    }
+    pub fn list_leaderboard_records(&self, session: &Session, leaderboard_id: &str, limit: u32, cursor: Option<&str>) -> DecoderRequest<LeaderboardRecordList> {
+        let mut uri = format!("/v2/leaderboard/{}?limit={}", encode(leaderboard_id), limit);
+        if let Some(cursor) = cursor {
+            uri += &format!("&cursor={}", encode(cursor));
+        }
+        self.request(Method::Get, &uri, &Nakama::bearer_auth(session), None)
+    }
+    pub fn write_storage_object(&self, session: &Session, collection: &str, key: &str, value: &str, version: Option<&str>) -> DecoderRequest<StorageObjectAcks> {
+        #[derive(SerJson)]
+        struct WriteObject<'a> {
+            collection: &'a str,
+            key: &'a str,
+            value: &'a str,
+            version: Option<&'a str>,
+            permission_read: u32,
+            permission_write: u32,
+        }
+        #[derive(SerJson)]
+        struct WriteObjects<'a> {
+            objects: Vec<WriteObject<'a>>,
+        }
+
+        let objects = WriteObjects {
+            objects: vec![WriteObject { collection, key, value, version, permission_read: 1, permission_write: 1 }],
+        };
+        self.request(Method::Put, "/v2/storage", &Nakama::bearer_auth(session), Some(&objects.serialize_json()))
+    }
+    pub fn read_storage_object(&self, session: &Session, collection: &str, key: &str, user_id: &str) -> DecoderRequest<StorageObjects> {
+        #[derive(SerJson)]
+        struct ReadObjectId<'a> {
+            collection: &'a str,
+            key: &'a str,
+            user_id: &'a str,
+        }
+        #[derive(SerJson)]
+        struct ReadObjects<'a> {
+            object_ids: Vec<ReadObjectId<'a>>,
+        }
+
+        let ids = ReadObjects {
+            object_ids: vec![ReadObjectId { collection, key, user_id }],
+        };
+        self.request(Method::Post, "/v2/storage", &Nakama::bearer_auth(session), Some(&ids.serialize_json()))
+    }
+    pub fn write_replay(&self, session: &Session, key: &str, replay: &str) -> DecoderRequest<StorageObjectAcks> {
+        self.write_storage_object(session, "replays", key, replay, None)
+    }
+    pub fn read_replay(&self, session: &Session, key: &str, user_id: &str) -> DecoderRequest<StorageObjects> {
+        self.read_storage_object(session, "replays", key, user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{BufRead, BufReader, Read, Write}, net::TcpListener, sync::mpsc, time::{Duration, Instant}};
+
+    use super::*;
+
+    // answers a single request with the given status and body and hands back what was asked for
+    fn serve(status: &str, body: &str) -> (Nakama, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        let response = format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                request += &line;
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request += &String::from_utf8(body).unwrap();
+            reader.into_inner().write_all(response.as_bytes()).unwrap();
+            tx.send(request).unwrap();
+        });
+        (Nakama::new("server-key", &url), rx)
+    }
+
+    fn wait<T: DeJson>(mut request: DecoderRequest<T>) -> Result<T, RequestError> {
+        let started = Instant::now();
+        loop {
+            if let Some(result) = request.try_recv() {
+                return result;
+            }
+            assert!(started.elapsed() < Duration::from_secs(5), "no response from the mock server");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn session() -> Session {
+        Session { token: "header.eyJleHAiOjE3MDAwMDAwMDB9.signature".to_string(), refresh_token: "refresh".to_string() }
+    }
+
+    #[test]
+    fn authenticates_a_device() {
+        let (nakama, requests) = serve("200 OK", r#"{"token":"a.b.c","refresh_token":"r","created":true}"#);
+        let session = wait(nakama.authenticate_device("device-1", true, Some("edre fis"))).unwrap();
+        assert_eq!(session.token, "a.b.c");
+        assert_eq!(session.refresh_token, "r");
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("POST /v2/account/authenticate/device?create=true&username=edre%20fis HTTP/1.1\r\n"), "{}", request);
+        assert!(request.contains("Authorization: Basic c2VydmVyLWtleTo=\r\n"), "{}", request);
+        assert!(request.ends_with(r#"{"id":"device-1"}"#), "{}", request);
+    }
+
+    #[test]
+    fn refreshes_with_the_refresh_token() {
+        let (nakama, requests) = serve("200 OK", r#"{"token":"new","refresh_token":"refresh"}"#);
+        assert_eq!(wait(nakama.refresh_session(&session())).unwrap().token, "new");
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("POST /v2/account/session/refresh HTTP/1.1\r\n"), "{}", request);
+        assert!(request.ends_with(r#"{"token":"refresh"}"#), "{}", request);
+    }
+
+    #[test]
+    fn lists_leaderboard_records() {
+        let (nakama, requests) = serve("200 OK", r#"{"records":[{"leaderboard_id":"normal","owner_id":"u1","username":"edrefis","score":"999","subscore":"53000","rank":"1"}],"next_cursor":"abc"}"#);
+        let list = wait(nakama.list_leaderboard_records(&session(), "normal mode", 10, Some("a/b"))).unwrap();
+        assert_eq!(list.records.len(), 1);
+        assert_eq!(list.records[0].username, "edrefis");
+        assert_eq!(list.records[0].score, "999");
+        assert_eq!(list.next_cursor, "abc");
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("GET /v2/leaderboard/normal%20mode?limit=10&cursor=a%2Fb HTTP/1.1\r\n"), "{}", request);
+        assert!(request.contains(&format!("Authorization: Bearer {}\r\n", session().token)), "{}", request);
+    }
+
+    #[test]
+    fn writes_storage_objects() {
+        let (nakama, requests) = serve("200 OK", r#"{"acks":[{"collection":"replays","key":"best","version":"v1","user_id":"u1"}]}"#);
+        let acks = wait(nakama.write_replay(&session(), "best", "{}")).unwrap();
+        assert_eq!(acks.acks[0].version, "v1");
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("PUT /v2/storage HTTP/1.1\r\n"), "{}", request);
+        assert!(request.contains(r#""collection":"replays","key":"best","value":"{}""#), "{}", request);
+    }
+
+    #[test]
+    fn reports_http_and_json_errors() {
+        let (nakama, _requests) = serve("401 Unauthorized", r#"{"error":"bad key"}"#);
+        assert!(matches!(wait(nakama.authenticate_custom("me", false, None)), Err(RequestError::Http(_))));
+
+        let (nakama, _requests) = serve("200 OK", "not json");
+        assert!(matches!(wait(nakama.authenticate_custom("me", false, None)), Err(RequestError::Json(_))));
+    }
+
+    #[test]
+    fn session_expiry() {
+        assert_eq!(session().expires_at(), Some(1_700_000_000));
+        assert!(!session().needs_refresh(1_699_000_000, 60));
+        assert!(session().needs_refresh(1_699_999_950, 60));
+        assert!(Session { token: "garbage".to_string(), refresh_token: String::new() }.needs_refresh(0, 0));
+    }
+
+    #[test]
+    fn session_user_id() {
+        assert_eq!(session().user_id(), None);
+        let session = Session { token: "header.eyJleHAiOjE3MDAwMDAwMDAsInVpZCI6InUxIn0=.signature".to_string(), refresh_token: String::new() };
+        assert_eq!(session.user_id().as_deref(), Some("u1"));
     }
 }
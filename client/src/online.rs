@@ -0,0 +1,125 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use nanoserde::{DeJson, SerJson};
+
+use crate::nakama::{DecoderRequest, LeaderboardRecord, LeaderboardRecordList, Nakama, RequestError, Session, StorageObjectAcks, StorageObjects};
+use crate::replay::Replay;
+
+// the leaderboard has to be created on the server; higher levels rank first and faster times
+// break ties, so times are stored negated
+pub const LEADERBOARD: &str = "normal";
+const LEADERBOARD_LINES: u32 = 5;
+// seconds before the session expires that it gets refreshed
+const REFRESH_MARGIN: u64 = 60;
+
+pub enum OnlineEvent {
+    Message(String),
+    Replay(Replay),
+}
+
+// signs the device in, keeps the session fresh and uploads finished games; everything is
+// asynchronous, so results come back through `poll`
+pub struct Online {
+    nakama: Nakama,
+    session: Option<Session>,
+    signing_in: Option<DecoderRequest<Session>>,
+    watch: Option<String>,
+    uploads: Vec<DecoderRequest<StorageObjectAcks>>,
+    scores: Vec<DecoderRequest<LeaderboardRecord>>,
+    leaderboards: Vec<DecoderRequest<LeaderboardRecordList>>,
+    downloads: Vec<DecoderRequest<StorageObjects>>,
+}
+
+fn finished<T: DeJson>(requests: &mut Vec<DecoderRequest<T>>) -> Vec<Result<T, RequestError>> {
+    let mut results = vec![];
+    requests.retain_mut(|request| match request.try_recv() {
+    Some(result) => {
+        results.push(result);
+        false
+    }
+    None => true,
+    });
+    results
+}
+
+impl Online {
+    pub fn new(nakama: Nakama, device_id: &str, watch: Option<String>) -> Online {
+        Online {
+            signing_in: Some(nakama.authenticate_device(device_id, true, None)),
+            nakama,
+            session: None,
+            watch,
+            uploads: vec![],
+            scores: vec![],
+            leaderboards: vec![],
+            downloads: vec![],
+        }
+    }
+    fn signed_in(&mut self, session: Session) {
+        self.leaderboards.push(self.nakama.list_leaderboard_records(&session, LEADERBOARD, LEADERBOARD_LINES, None));
+        if let (Some(key), Some(user_id)) = (self.watch.take(), session.user_id()) {
+            self.downloads.push(self.nakama.read_replay(&session, &key, &user_id));
+        }
+        self.session = Some(session);
+    }
+    pub fn submit(&mut self, key: &str, replay: &Replay, level: u32, ticks: u64) -> bool {
+        let Some(ref session) = self.session else {
+            return false;
+        };
+        self.uploads.push(self.nakama.write_replay(session, key, &replay.serialize_json()));
+        let metadata = format!("{{\"replay\":{}}}", key.serialize_json());
+        self.scores.push(self.nakama.write_leaderboard_record(session, LEADERBOARD, level as i64, -(ticks as i64), &metadata));
+        true
+    }
+    pub fn poll(&mut self, now: u64) -> Vec<OnlineEvent> {
+        let mut events = vec![];
+        if let Some(result) = self.signing_in.as_mut().and_then(|it| it.try_recv()) {
+            let first = self.session.is_none();
+            self.signing_in = None;
+            match result {
+            Ok(session) if first => self.signed_in(session),
+            Ok(session) => self.session = Some(session),
+            Err(error) => {
+                self.session = None;
+                events.push(OnlineEvent::Message(format!("signed out of the leaderboard: {}", error)));
+            }
+            }
+        }
+        if let Some(session) = self.session.as_ref().filter(|it| self.signing_in.is_none() && it.needs_refresh(now, REFRESH_MARGIN)) {
+            self.signing_in = Some(self.nakama.refresh_session(session));
+        }
+
+        for result in finished(&mut self.uploads) {
+            if let Err(error) = result {
+                events.push(OnlineEvent::Message(format!("couldn't upload the replay: {}", error)));
+            }
+        }
+        for result in finished(&mut self.scores) {
+            events.push(OnlineEvent::Message(match result {
+            Ok(record) => format!("rank {} on the {} leaderboard", record.rank, record.leaderboard_id),
+            Err(error) => format!("couldn't submit the score: {}", error),
+            }));
+        }
+        for result in finished(&mut self.leaderboards) {
+            match result {
+            Ok(list) => events.extend(list.records.into_iter().map(|record| {
+                OnlineEvent::Message(format!("{}. {} Lv{}", record.rank, record.username, record.score))
+            })),
+            Err(error) => events.push(OnlineEvent::Message(format!("couldn't load the leaderboard: {}", error))),
+            }
+        }
+        for result in finished(&mut self.downloads) {
+            let replay = result.map_err(|error| error.to_string()).and_then(|objects| {
+                let object = objects.objects.into_iter().next().ok_or("no such replay".to_string())?;
+                Replay::deserialize_json(&object.value).map_err(|error| error.to_string())
+            });
+            events.push(match replay {
+            Ok(replay) => OnlineEvent::Replay(replay),
+            Err(error) => OnlineEvent::Message(format!("couldn't load the replay: {}", error)),
+            });
+        }
+        events
+    }
+}
@@ -70,6 +70,9 @@ impl Replay {
             ticks: vec![],
         }
     }
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
     pub fn replay_tick(&mut self, inputs: &Inputs) {
         let mut replay_tick = InputTick { down: vec![], up: vec![] };
         for input in RECORDABLE_INPUTS {
@@ -0,0 +1,32 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use logic::netsim::Transport;
+use quad_net::quad_socket::client::QuadSocket;
+
+#[cfg(not(target_arch = "wasm32"))]
+const SERVER: &str = "blackquill.cc:8088";
+#[cfg(target_arch = "wasm32")]
+const SERVER: &str = "wss://1293045598395830332.discordsays.com/.proxy/api";
+
+pub struct Socket(QuadSocket);
+
+impl Socket {
+    pub fn connect() -> Option<Socket> {
+        QuadSocket::connect(SERVER).ok().map(Socket)
+    }
+    #[cfg(target_arch = "wasm32")]
+    pub fn is_connected(&self) -> bool {
+        self.0.is_wasm_websocket_connected()
+    }
+}
+
+impl Transport for Socket {
+    fn send(&mut self, data: &[u8]) {
+        self.0.send(data);
+    }
+    fn try_recv(&mut self) -> Option<Vec<u8>> {
+        self.0.try_recv()
+    }
+}
@@ -49,6 +49,7 @@ impl Sounds for ClientSounds {
         Block::Purple => play_sound_once(&self.piece5),
         Block::Cyan => play_sound_once(&self.piece6),
         Block::Red => play_sound_once(&self.piece7),
+        Block::Gray => {}
         }
     }
 }
@@ -26,9 +26,6 @@ impl Text {
             Weight::Medium => Some(&self.medium),
         }
     }
-    pub fn measure_text(&self, text: &str, weight: Weight, size: f32) -> TextDimensions {
-        measure_text(text, self.font(weight), size as u16, 1.)
-    }
     pub fn draw_text(&self, text: &str, x: f32, y: f32, weight: Weight, color: Color, size: f32) -> TextDimensions {
         // let (font_size, font_scale, font_scale_aspect) = camera_font_scale(size);
         draw_text_ex(text, x, y, TextParams {
@@ -0,0 +1,126 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::VecDeque;
+
+// the game is tuned tick by tick, so the server and every client step at exactly this rate
+pub const TICKS_PER_SECOND: u64 = 60;
+
+#[derive(Clone, Copy, Debug)]
+struct ClockSample {
+    rtt: u64,
+    offset: i64,
+    server_time: u64,
+    tick: u64,
+}
+
+// all times are in microseconds; the offset is taken from the sample with the lowest round trip,
+// since that one spent the least time sitting in queues
+pub struct ClockSync {
+    samples: VecDeque<ClockSample>,
+    capacity: usize,
+}
+
+impl ClockSync {
+    pub fn new(capacity: usize) -> ClockSync {
+        let capacity = capacity.max(1);
+        ClockSync {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+    pub fn sample(&mut self, client_sent: u64, server_time: u64, tick: u64, client_received: u64) {
+        let rtt = client_received.saturating_sub(client_sent);
+        let offset = server_time as i64 - (client_sent + rtt / 2) as i64;
+        while self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(ClockSample { rtt, offset, server_time, tick });
+    }
+    fn best(&self) -> Option<&ClockSample> {
+        self.samples.iter().min_by_key(|it| it.rtt)
+    }
+    pub fn rtt(&self) -> Option<u64> {
+        self.best().map(|it| it.rtt)
+    }
+    pub fn offset(&self) -> Option<i64> {
+        self.best().map(|it| it.offset)
+    }
+    pub fn jitter(&self) -> Option<u64> {
+        if self.samples.len() < 2 {
+            return None;
+        }
+        let mean = self.samples.iter().map(|it| it.rtt).sum::<u64>() / self.samples.len() as u64;
+        Some(self.samples.iter().map(|it| it.rtt.abs_diff(mean)).sum::<u64>() / self.samples.len() as u64)
+    }
+    pub fn server_time(&self, client_now: u64) -> Option<u64> {
+        self.offset().map(|offset| (client_now as i64 + offset).max(0) as u64)
+    }
+    pub fn server_tick(&self, client_now: u64, ticks_per_second: f64) -> Option<u64> {
+        let latest = self.samples.back()?;
+        let elapsed = self.server_time(client_now)?.saturating_sub(latest.server_time);
+        Some(latest.tick + (elapsed as f64 * ticks_per_second / 1_000_000.) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (client_sent, server_time, tick, client_received); the third took far longer than the rest
+    const SAMPLES: &[(u64, u64, u64, u64)] = &[
+        (0, 60_000, 100, 40_000),
+        (100_000, 150_000, 105, 120_000),
+        (200_000, 400_000, 120, 500_000),
+        (600_000, 655_000, 150, 630_000),
+    ];
+
+    fn synced(capacity: usize) -> ClockSync {
+        let mut clock = ClockSync::new(capacity);
+        for &(sent, server_time, tick, received) in SAMPLES {
+            clock.sample(sent, server_time, tick, received);
+        }
+        clock
+    }
+
+    #[test]
+    fn estimates_come_from_the_fastest_round_trip() {
+        let clock = synced(8);
+        assert_eq!(clock.rtt(), Some(20_000));
+        assert_eq!(clock.offset(), Some(40_000));
+        assert_eq!(clock.server_time(700_000), Some(740_000));
+    }
+
+    #[test]
+    fn jitter_is_the_mean_deviation_of_round_trips() {
+        assert_eq!(synced(8).jitter(), Some(101_250));
+
+        let mut clock = ClockSync::new(8);
+        clock.sample(0, 60_000, 100, 40_000);
+        assert_eq!(clock.jitter(), None);
+    }
+
+    #[test]
+    fn server_tick_counts_on_from_the_latest_sample() {
+        let clock = synced(8);
+        assert_eq!(clock.server_tick(630_000, TICKS_PER_SECOND as f64), Some(150));
+        assert_eq!(clock.server_tick(700_000, TICKS_PER_SECOND as f64), Some(155));
+        assert_eq!(ClockSync::new(8).server_tick(700_000, TICKS_PER_SECOND as f64), None);
+    }
+
+    #[test]
+    fn old_samples_are_forgotten() {
+        let clock = synced(2);
+        assert_eq!(clock.rtt(), Some(30_000));
+        assert_eq!(clock.offset(), Some(40_000));
+        assert_eq!(clock.jitter(), Some(135_000));
+    }
+
+    #[test]
+    fn a_clock_without_room_still_keeps_the_latest_sample() {
+        let clock = synced(0);
+        assert_eq!(clock.rtt(), Some(30_000));
+        assert_eq!(clock.jitter(), None);
+    }
+}
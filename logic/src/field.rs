@@ -2,11 +2,11 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use nanoserde::{DeJson, SerJson};
+use nanoserde::{DeBin, DeJson, SerBin, SerJson};
 
-use crate::{hooks::{Cubes, Sounds}, input::{Input, Inputs}, piece::Piece, randomizer::Randomizer, well::Well};
+use crate::{garbage::Garbage, hooks::{Cubes, Sounds}, input::{Input, Inputs}, piece::Piece, randomizer::Randomizer, well::Well};
 
-#[derive(Debug, Clone, SerJson, DeJson)]
+#[derive(Debug, Clone, SerJson, SerBin, DeJson, DeBin)]
 pub enum GameState {
     ActivePiece {
         piece: Piece,
@@ -23,13 +23,22 @@ pub enum GameState {
     },
 }
 
-#[derive(SerJson, DeJson, Clone)]
+#[derive(Debug, Clone, Copy)]
+pub struct Locked {
+    pub piece: Piece,
+    pub lines: u32,
+}
+
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone)]
 pub struct Field {
     pub randomizer: Randomizer,
 
     pub well: Well,
     pub next: Piece,
     pub level: u32,
+    pub gravity_override: Option<i32>,
+    #[nserde(default)]
+    pub garbage: Garbage,
 
     pub state: GameState,
 }
@@ -101,12 +110,18 @@ pub fn level_to_gravity(level: u32) -> i32 {
 
 impl Field {
     pub fn new() -> Field {
-        let mut randomizer = Randomizer::new();
-
+        Field::with_randomizer(Randomizer::new(), Garbage::new(10))
+    }
+    pub fn seeded(seed: u32) -> Field {
+        Field::with_randomizer(Randomizer::seeded(seed), Garbage::new(seed))
+    }
+    fn with_randomizer(mut randomizer: Randomizer, garbage: Garbage) -> Field {
         Field {
             well: Well::new(),
             next: randomizer.next_piece(),
             level: 0,
+            gravity_override: None,
+            garbage,
             state: GameState::ActivePiece {
                 piece: randomizer.next_piece(),
             },
@@ -114,7 +129,12 @@ impl Field {
             randomizer,
         }
     }
-    pub fn update(&mut self, inputs: &Inputs, sounds: &mut dyn Sounds, cubes: &mut dyn Cubes) {
+    pub fn gravity(&self) -> i32 {
+        self.gravity_override.unwrap_or_else(|| level_to_gravity(self.level))
+    }
+    pub fn update(&mut self, inputs: &Inputs, sounds: &mut dyn Sounds, cubes: &mut dyn Cubes) -> Option<Locked> {
+        let gravity = self.gravity();
+        let mut locked = None;
         match self.state {
             GameState::ActivePiece { ref mut piece } => {
                 piece.do_sonic(&self.well, inputs);
@@ -123,13 +143,14 @@ impl Field {
                 piece.do_gravity(
                     &self.well,
                     inputs,
-                    level_to_gravity(self.level),
+                    gravity,
                     sounds,
                     true,
                 );
 
                 if piece.do_lock(&mut self.well, inputs, sounds) {
                     let cleared_rows = self.well.do_clear();
+                    locked = Some(Locked { piece: *piece, lines: cleared_rows.len() as u32 });
                     if cleared_rows.len() > 0 {
                         sounds.line_clear();
                         self.level += cleared_rows.len() as u32;
@@ -147,6 +168,8 @@ impl Field {
                             ticks_remaining: ticks_of_line_clear,
                             rows_to_lower,
                         };
+                    } else if self.garbage.insert_into(&mut self.well) {
+                        self.state = GameState::GameOver { ticks_remaining: 60 * 5 };
                     } else {
                         self.state = GameState::PlaceDelay {
                             ticks_remaining: 30,
@@ -186,7 +209,7 @@ impl Field {
                         self.next.do_gravity(
                             &self.well,
                             inputs,
-                            level_to_gravity(self.level),
+                            self.gravity(),
                             sounds,
                             false,
                         );
@@ -208,9 +231,11 @@ impl Field {
                         piece: randomizer.next_piece(),
                     };
                     self.randomizer = randomizer;
+                    self.garbage.reset();
                     self.level = 0;
                 }
             }
         }
+        locked
     }
 }
@@ -0,0 +1,164 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{collections::{HashMap, HashSet, VecDeque}, sync::OnceLock};
+
+use crate::{input::{Input, Inputs}, piece::Piece, well::{Block, Well, WELL_COLS}};
+
+pub const FINESSE_INPUTS: [Input; 4] = [Input::Left, Input::Right, Input::CW, Input::CCW];
+
+pub type Footprint = Vec<(i32, i32)>;
+
+pub fn footprint(piece: &Piece) -> Footprint {
+    let mut cells = vec![];
+    for (ri, row) in piece.rotations.piece_map()[piece.rotation].iter().enumerate() {
+        for (ci, col) in row.iter().enumerate() {
+            if *col {
+                cells.push((piece.x + ci as i32, ri as i32));
+            }
+        }
+    }
+    let top = cells.iter().map(|it| it.1).min().unwrap_or(0);
+    for cell in cells.iter_mut() {
+        cell.1 -= top;
+    }
+    cells.sort();
+    cells
+}
+
+fn shifted(piece: &Piece, well: &Well, dx: i32) -> Option<Piece> {
+    if piece.collides_with(well, dx, 0, piece.rotation) {
+        None
+    } else {
+        let mut moved = *piece;
+        moved.x += dx;
+        Some(moved)
+    }
+}
+
+fn shifted_to_wall(piece: &Piece, well: &Well, dx: i32) -> Option<Piece> {
+    let mut moved = shifted(piece, well, dx)?;
+    while let Some(next) = shifted(&moved, well, dx) {
+        moved = next;
+    }
+    Some(moved)
+}
+
+fn rotated(piece: &Piece, well: &Well, cw: bool) -> Option<Piece> {
+    let mut rotated = *piece;
+    let rotation = if cw { piece.rotation.cw() } else { piece.rotation.ccw() };
+    if rotated.try_rotate(well, rotation) {
+        Some(rotated)
+    } else {
+        None
+    }
+}
+
+const PIECES: [Block; 7] = [Block::Red, Block::Orange, Block::Yellow, Block::Green, Block::Cyan, Block::Blue, Block::Purple];
+
+fn search(color: Block) -> HashMap<Footprint, u32> {
+    let well = Well::new();
+    let spawn = Piece::new(color);
+
+    let mut seen = HashSet::new();
+    let mut table = HashMap::new();
+    let mut queue = VecDeque::new();
+    seen.insert((spawn.x, spawn.rotation));
+    queue.push_back((spawn, 0));
+
+    while let Some((piece, presses)) = queue.pop_front() {
+        table.entry(footprint(&piece)).or_insert(presses);
+
+        let moves = [
+            shifted(&piece, &well, -1),
+            shifted(&piece, &well, 1),
+            shifted_to_wall(&piece, &well, -1),
+            shifted_to_wall(&piece, &well, 1),
+            rotated(&piece, &well, true),
+            rotated(&piece, &well, false),
+        ];
+        for next in moves.into_iter().flatten() {
+            if seen.insert((next.x, next.rotation)) {
+                queue.push_back((next, presses + 1));
+            }
+        }
+    }
+    table
+}
+
+// the search only ever runs on an empty well, so each piece's table is worked out once
+pub fn optimal_table(color: Block) -> &'static HashMap<Footprint, u32> {
+    static TABLES: OnceLock<Vec<HashMap<Footprint, u32>>> = OnceLock::new();
+    let tables = TABLES.get_or_init(|| PIECES.iter().map(|it| search(*it)).collect());
+    let index = PIECES.iter().position(|it| *it == color).expect("garbage blocks are never pieces");
+    &tables[index]
+}
+
+pub fn optimal_inputs(piece: &Piece) -> Option<u32> {
+    optimal_table(piece.color).get(&footprint(piece)).copied()
+}
+
+pub fn is_drop_placement(well: &Well, piece: &Piece) -> bool {
+    let map = piece.rotations.piece_map()[piece.rotation];
+    let own = map.iter().enumerate()
+        .flat_map(|(ri, row)| row.iter().enumerate().filter(|it| *it.1).map(move |(ci, _)| (piece.x + ci as i32, piece.y + ri as i32)))
+        .collect::<Vec<_>>();
+
+    own.iter().all(|(x, y)| {
+        if *x < 0 || *x >= WELL_COLS as i32 {
+            return false;
+        }
+        (0..*y).filter(|above| !own.contains(&(*x, *above)))
+            .all(|above| well.blocks[above as usize][*x as usize].is_none())
+    })
+}
+
+pub fn presses(inputs: &Inputs) -> u32 {
+    FINESSE_INPUTS.iter().filter(|it| inputs.key_just_pressed(**it)).count() as u32
+}
+
+pub fn is_fault(well: &Well, piece: &Piece, presses: u32) -> bool {
+    if !is_drop_placement(well, piece) {
+        return false;
+    }
+    optimal_inputs(piece).map(|optimal| presses > optimal).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::Rotation;
+
+    fn counts(color: Block, rotation: Rotation) -> Vec<u32> {
+        let mut counts = vec![];
+        for x in -3..WELL_COLS as i32 {
+            let mut piece = Piece::new(color);
+            piece.rotation = rotation;
+            piece.x = x;
+            if !piece.collides_with(&Well::new(), 0, 0, rotation) {
+                counts.push(optimal_inputs(&piece).unwrap());
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn known_counts() {
+        let expected: &[(Block, [&[u32]; 4])] = &[
+            (Block::Red, [&[1, 2, 1, 0, 1, 2, 1], &[2, 3, 2, 3, 2, 1, 2, 3, 2, 2], &[1, 2, 1, 0, 1, 2, 1], &[2, 3, 2, 3, 2, 1, 2, 3, 2, 2]]),
+            (Block::Yellow, [&[1, 2, 2, 1, 0, 1, 2, 2, 1]; 4]),
+            (Block::Cyan, [&[1, 2, 1, 0, 1, 2, 2, 1], &[2, 3, 2, 1, 2, 3, 3, 2, 2], &[3, 4, 3, 2, 3, 4, 4, 3], &[2, 2, 3, 2, 1, 2, 3, 3, 2]]),
+        ];
+        for (color, rotations) in expected {
+            for (rotation, counts_by_column) in [Rotation::R0, Rotation::R90, Rotation::R180, Rotation::R270].into_iter().zip(rotations) {
+                assert_eq!(counts(*color, rotation), *counts_by_column, "{:?} {:?}", color, rotation);
+            }
+        }
+    }
+
+    #[test]
+    fn tables_are_cached() {
+        assert!(std::ptr::eq(optimal_table(Block::Blue), optimal_table(Block::Blue)));
+    }
+}
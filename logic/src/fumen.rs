@@ -0,0 +1,453 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::HashSet;
+
+use crate::{
+    field::{Field, GameState},
+    piece::{Piece, Rotation},
+    practice::{piece_from_letter, piece_letter},
+    well::{Block, BlockDirections, Tile, Well, WELL_COLS, WELL_ROWS},
+};
+
+const ENCODE_TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const PREFIX: &str = "v115@";
+
+const FIELD_TOP: usize = 23;
+const FIELD_WIDTH: usize = 10;
+const FIELD_BLOCKS: usize = (FIELD_TOP + 1) * FIELD_WIDTH;
+const HIDDEN_ROWS: usize = FIELD_TOP - WELL_ROWS;
+
+const COMMENT_TABLE_LENGTH: u32 = 96;
+const QUIZ_PREFIX: &str = "#Q=";
+
+const FLAG_RISE: u32 = 1 << 0;
+const FLAG_MIRROR: u32 = 1 << 1;
+const FLAG_COLORIZE: u32 = 1 << 2;
+const FLAG_COMMENT: u32 = 1 << 3;
+const FLAG_NO_LOCK: u32 = 1 << 4;
+
+#[derive(Clone)]
+pub struct Fumen {
+    pub well: Well,
+    pub current: Option<Piece>,
+    pub next: Vec<Block>,
+    // what the page does once it's done: lock the piece, mirror the field, raise garbage. only the
+    // first page is read, so these don't change what gets loaded, but they're written back out
+    pub lock: bool,
+    pub mirror: bool,
+    pub rise: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FumenRotation {
+    Reverse,
+    Right,
+    Spawn,
+    Left,
+}
+
+const FUMEN_ROTATIONS: &[FumenRotation] = &[
+    FumenRotation::Reverse,
+    FumenRotation::Right,
+    FumenRotation::Spawn,
+    FumenRotation::Left,
+];
+
+const ROTATIONS: &[Rotation] = &[Rotation::R0, Rotation::R90, Rotation::R180, Rotation::R270];
+
+fn block_to_kind(block: Option<Block>) -> u32 {
+    match block {
+    None => 0,
+    Some(Block::Red) => 1,
+    Some(Block::Orange) => 2,
+    Some(Block::Yellow) => 3,
+    Some(Block::Green) => 4,
+    Some(Block::Cyan) => 5,
+    Some(Block::Blue) => 6,
+    Some(Block::Purple) => 7,
+    Some(Block::Gray) => 8,
+    }
+}
+
+fn kind_to_block(kind: u32) -> Result<Option<Block>, String> {
+    match kind {
+    0 => Ok(None),
+    1 => Ok(Some(Block::Red)),
+    2 => Ok(Some(Block::Orange)),
+    3 => Ok(Some(Block::Yellow)),
+    4 => Ok(Some(Block::Green)),
+    5 => Ok(Some(Block::Cyan)),
+    6 => Ok(Some(Block::Blue)),
+    7 => Ok(Some(Block::Purple)),
+    8 => Ok(Some(Block::Gray)),
+    _ => Err(format!("invalid block kind {}", kind)),
+    }
+}
+
+fn spawn_cells(kind: u32) -> [(i32, i32); 4] {
+    match kind {
+    1 => [(0, 0), (-1, 0), (1, 0), (2, 0)],
+    2 => [(0, 0), (-1, 0), (1, 0), (1, 1)],
+    3 => [(0, 0), (1, 0), (0, 1), (1, 1)],
+    4 => [(0, 0), (1, 0), (0, 1), (-1, 1)],
+    5 => [(0, 0), (-1, 0), (1, 0), (0, 1)],
+    6 => [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+    7 => [(0, 0), (-1, 0), (0, 1), (1, 1)],
+    _ => unreachable!("only pieces have cells"),
+    }
+}
+
+fn fumen_cells(kind: u32, rotation: FumenRotation, location: usize) -> HashSet<(i32, i32)> {
+    let mut x = (location % FIELD_WIDTH) as i32;
+    let mut y = FIELD_TOP as i32 - (location / FIELD_WIDTH) as i32 - 1;
+
+    match (kind, rotation) {
+    (3, FumenRotation::Left) => { x -= 1; y += 1; }
+    (3, FumenRotation::Reverse) => { x -= 1; }
+    (3, FumenRotation::Spawn) => { y += 1; }
+    (1, FumenRotation::Reverse) => { x -= 1; }
+    (1, FumenRotation::Left) => { y += 1; }
+    (7, FumenRotation::Spawn) => { y += 1; }
+    (7, FumenRotation::Right) => { x += 1; }
+    (4, FumenRotation::Spawn) => { y += 1; }
+    (4, FumenRotation::Left) => { x -= 1; }
+    _ => {}
+    }
+
+    spawn_cells(kind)
+        .iter()
+        .map(|&(dx, dy)| match rotation {
+            FumenRotation::Spawn => (dx, dy),
+            FumenRotation::Right => (dy, -dx),
+            FumenRotation::Reverse => (-dx, -dy),
+            FumenRotation::Left => (-dy, dx),
+        })
+        .map(|(dx, dy)| (x + dx, y + dy))
+        .collect()
+}
+
+fn piece_cells(piece: &Piece) -> HashSet<(i32, i32)> {
+    let mut cells = HashSet::new();
+    for (ri, row) in piece.rotations.piece_map()[piece.rotation].iter().enumerate() {
+        for (ci, col) in row.iter().enumerate() {
+            if *col {
+                let well_row = piece.y + ri as i32;
+                cells.insert((piece.x + ci as i32, FIELD_TOP as i32 - 1 - HIDDEN_ROWS as i32 - well_row));
+            }
+        }
+    }
+    cells
+}
+
+struct Writer {
+    out: String,
+}
+
+impl Writer {
+    fn push(&mut self, mut value: u32, digits: usize) {
+        for _ in 0..digits {
+            self.out.push(ENCODE_TABLE[(value % 64) as usize] as char);
+            value /= 64;
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn poll(&mut self, digits: usize) -> Result<u32, String> {
+        let mut value = 0;
+        let mut scale = 1;
+        for _ in 0..digits {
+            let ch = *self.data.get(self.position).ok_or("fumen data ended unexpectedly")?;
+            let digit = ENCODE_TABLE.iter().position(|it| *it == ch).ok_or_else(|| format!("invalid fumen character {}", ch as char))?;
+            value += digit as u32 * scale;
+            scale *= 64;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::new();
+    for ch in text.chars() {
+        if ch.is_ascii_alphanumeric() || "@*_+-./".contains(ch) {
+            out.push(ch);
+        } else if (ch as u32) < 0x100 {
+            out.push_str(&format!("%{:02X}", ch as u32));
+        } else {
+            out.push_str(&format!("%u{:04X}", ch as u32));
+        }
+    }
+    out
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(idx) = rest.find('%') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+
+        let (digits, len) = if rest.starts_with('u') { (rest.get(1..5), 5) } else { (rest.get(..2), 2) };
+        match digits.and_then(|it| u32::from_str_radix(it, 16).ok()).and_then(char::from_u32) {
+            Some(ch) => {
+                out.push(ch);
+                rest = &rest[len..];
+            }
+            None => out.push('%'),
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+impl Fumen {
+    pub fn new(well: Well, current: Option<Piece>, next: Vec<Block>) -> Fumen {
+        Fumen { well, current, next, lock: true, mirror: false, rise: false }
+    }
+    pub fn from_field(field: &Field) -> Fumen {
+        let current = match field.state {
+            GameState::ActivePiece { piece } => Some(piece),
+            _ => None,
+        };
+        Fumen::new(field.well.clone(), current, vec![field.next.color])
+    }
+    fn cell(&self, index: usize) -> u32 {
+        let row = index / FIELD_WIDTH;
+        let col = index % FIELD_WIDTH;
+        if !(HIDDEN_ROWS..FIELD_TOP).contains(&row) {
+            0
+        } else {
+            block_to_kind(self.well.blocks[row - HIDDEN_ROWS][col].map(|it| it.color))
+        }
+    }
+    fn locate(piece: &Piece) -> Option<(u32, FumenRotation, usize)> {
+        let kind = block_to_kind(Some(piece.color));
+        let cells = piece_cells(piece);
+        FUMEN_ROTATIONS.iter()
+            .flat_map(|rotation| (0..FIELD_BLOCKS).map(move |location| (*rotation, location)))
+            .find(|(rotation, location)| fumen_cells(kind, *rotation, *location) == cells)
+            .map(|(rotation, location)| (kind, rotation, location))
+    }
+    fn comment(&self) -> Option<String> {
+        if self.next.is_empty() {
+            return None;
+        }
+        let current = self.current.map(|it| piece_letter(it.color).to_string()).unwrap_or_default();
+        let next = self.next.iter().map(|it| piece_letter(*it)).collect::<String>();
+        Some(format!("{}[]({}){}", QUIZ_PREFIX, current, next))
+    }
+    pub fn encode(&self) -> String {
+        let mut writer = Writer { out: String::new() };
+
+        let mut runs: Vec<(u32, u32)> = vec![];
+        for index in 0..FIELD_BLOCKS {
+            let diff = self.cell(index) + 8;
+            match runs.last_mut() {
+                Some((last, count)) if *last == diff => *count += 1,
+                _ => runs.push((diff, 0)),
+            }
+        }
+        for (diff, count) in &runs {
+            writer.push(diff * FIELD_BLOCKS as u32 + count, 2);
+        }
+        if runs.len() == 1 && runs[0].0 == 8 {
+            writer.push(0, 1);
+        }
+
+        let (kind, rotation, location) = self.current.as_ref().and_then(Fumen::locate).unwrap_or((0, FumenRotation::Reverse, 0));
+        let rotation = FUMEN_ROTATIONS.iter().position(|it| *it == rotation).unwrap() as u32;
+        let comment = self.comment().map(|it| escape(&it));
+
+        let mut flags = FLAG_COLORIZE;
+        if comment.is_some() {
+            flags |= FLAG_COMMENT;
+        }
+        if !self.lock {
+            flags |= FLAG_NO_LOCK;
+        }
+        if self.mirror {
+            flags |= FLAG_MIRROR;
+        }
+        if self.rise {
+            flags |= FLAG_RISE;
+        }
+        writer.push(kind + 8 * rotation + 32 * location as u32 + 32 * FIELD_BLOCKS as u32 * flags, 3);
+
+        if let Some(comment) = comment {
+            let chars = comment.chars().take(4095).collect::<Vec<_>>();
+            writer.push(chars.len() as u32, 2);
+            for chunk in chars.chunks(4) {
+                let value = chunk.iter().rev().fold(0, |acc, ch| acc * COMMENT_TABLE_LENGTH + (*ch as u32 - 32));
+                writer.push(value, 5);
+            }
+        }
+
+        format!("{}{}", PREFIX, writer.out)
+    }
+    pub fn decode(text: &str) -> Result<Fumen, String> {
+        let start = text.find(PREFIX).ok_or("only v115 fumen data is supported")?;
+        let data = text[start + PREFIX.len()..]
+            .chars()
+            .filter(|it| *it != '?' && !it.is_whitespace())
+            .collect::<String>();
+        let mut reader = Reader { data: data.as_bytes(), position: 0 };
+
+        let mut well = Well::new();
+        let mut index = 0;
+        while index < FIELD_BLOCKS {
+            let value = reader.poll(2)?;
+            let diff = value / FIELD_BLOCKS as u32;
+            let count = (value % FIELD_BLOCKS as u32) as usize + 1;
+            if diff > 16 || index + count > FIELD_BLOCKS {
+                return Err("fumen field data is malformed".to_string());
+            }
+            if diff == 8 && count == FIELD_BLOCKS {
+                reader.poll(1)?;
+            }
+
+            let kind = diff.checked_sub(8).ok_or("fumen field data is malformed")?;
+            let color = kind_to_block(kind)?;
+            for cell in index..index + count {
+                let row = cell / FIELD_WIDTH;
+                if color.is_none() || row >= FIELD_TOP {
+                    continue;
+                }
+                if row < HIDDEN_ROWS {
+                    return Err("fumen field is taller than the well".to_string());
+                }
+                well.blocks[row - HIDDEN_ROWS][cell % FIELD_WIDTH] = color.map(|color| Tile { color, directions: BlockDirections::NONE, jewel: false });
+            }
+            index += count;
+        }
+
+        let action = reader.poll(3)?;
+        let kind = action % 8;
+        let rotation = FUMEN_ROTATIONS[(action / 8 % 4) as usize];
+        let location = (action / 32 % FIELD_BLOCKS as u32) as usize;
+        let flags = action / (32 * FIELD_BLOCKS as u32);
+
+        let current = match kind_to_block(kind)? {
+            Some(Block::Gray) => return Err("fumen piece is not a tetromino".to_string()),
+            Some(color) => {
+                let cells = fumen_cells(kind, rotation, location);
+                let mut found = None;
+                'search: for rotation in ROTATIONS {
+                    for y in -4..WELL_ROWS as i32 {
+                        for x in -4..WELL_COLS as i32 {
+                            let mut piece = Piece::new(color);
+                            piece.rotation = *rotation;
+                            piece.x = x;
+                            piece.y = y;
+                            if piece_cells(&piece) == cells {
+                                found = Some(piece);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+                Some(found.ok_or("fumen piece does not fit in the well")?)
+            }
+            None => None,
+        };
+
+        let mut next = vec![];
+        if flags & FLAG_COMMENT != 0 {
+            let length = reader.poll(2)? as usize;
+            let mut comment = String::new();
+            while comment.len() < length {
+                let mut value = reader.poll(5)?;
+                for _ in 0..4 {
+                    if comment.len() < length {
+                        comment.push(char::from_u32(value % COMMENT_TABLE_LENGTH + 32).unwrap_or('?'));
+                    }
+                    value /= COMMENT_TABLE_LENGTH;
+                }
+            }
+
+            let comment = unescape(&comment);
+            if let Some(quiz) = comment.strip_prefix(QUIZ_PREFIX) {
+                let queue = quiz.rsplit(')').next().unwrap_or("");
+                next = queue.chars().filter_map(piece_from_letter).collect();
+            }
+        }
+
+        Ok(Fumen {
+            well,
+            current,
+            next,
+            lock: flags & FLAG_NO_LOCK == 0,
+            mirror: flags & FLAG_MIRROR != 0,
+            rise: flags & FLAG_RISE != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colors(well: &Well) -> Vec<Option<Block>> {
+        well.blocks.iter().flatten().map(|it| it.map(|tile| tile.color)).collect()
+    }
+
+    #[test]
+    fn empty_field() {
+        assert_eq!(Fumen::new(Well::new(), None, vec![]).encode(), "v115@vhAAgH");
+
+        let fumen = Fumen::decode("v115@vhAAgH").unwrap();
+        assert!(fumen.current.is_none() && fumen.next.is_empty());
+        assert!(fumen.lock && !fumen.mirror && !fumen.rise);
+        assert_eq!(colors(&fumen.well), colors(&Well::new()));
+    }
+
+    #[test]
+    fn quiz_comment() {
+        let text = "v115@vhAAgWVAFLDmClcJSAVDEHBEooRBFrQLCvAAAA";
+        assert_eq!(Fumen::new(Well::new(), None, vec![Block::Red, Block::Yellow]).encode(), text);
+        assert_eq!(Fumen::decode(text).unwrap().next, vec![Block::Red, Block::Yellow]);
+    }
+
+    #[test]
+    fn action_flags() {
+        let text = "v115@vhAAIr";
+        let fumen = Fumen::decode(text).unwrap();
+        assert!(!fumen.lock && fumen.mirror && fumen.rise);
+        assert_eq!(fumen.encode(), text);
+    }
+
+    #[test]
+    fn field_and_piece_round_trip() {
+        let mut well = Well::new();
+        for (col, color) in [Block::Red, Block::Orange, Block::Yellow, Block::Green, Block::Cyan, Block::Blue, Block::Purple, Block::Gray].into_iter().enumerate() {
+            well.blocks[WELL_ROWS - 1][col] = Some(Tile { color, directions: BlockDirections::NONE, jewel: false });
+            well.blocks[WELL_ROWS - 2][col + 1] = Some(Tile { color, directions: BlockDirections::NONE, jewel: false });
+        }
+
+        for color in [Block::Red, Block::Orange, Block::Yellow, Block::Green, Block::Cyan, Block::Blue, Block::Purple] {
+            for rotation in ROTATIONS {
+                let mut piece = Piece::new(color);
+                piece.rotation = *rotation;
+                piece.y = 5;
+                let fumen = Fumen::decode(&Fumen::new(well.clone(), Some(piece), vec![Block::Cyan, Block::Red]).encode()).unwrap();
+                assert_eq!(colors(&fumen.well), colors(&well));
+                assert_eq!(piece_cells(&fumen.current.unwrap()), piece_cells(&piece), "{:?} {:?}", color, rotation);
+                assert_eq!(fumen.next, vec![Block::Cyan, Block::Red]);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(Fumen::decode("v115@vh").is_err());
+        assert!(Fumen::decode("v115@!!!!").is_err());
+        assert!(Fumen::decode("v114@vhAAgH").is_err());
+    }
+}
@@ -0,0 +1,158 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use nanoserde::{DeBin, DeJson, SerBin, SerJson};
+
+use crate::well::{Block, BlockDirections, Tile, Well, WELL_COLS, WELL_ROWS};
+
+// nothing past a full well of garbage can matter, so attacks and pending garbage are capped there
+pub const MAX_GARBAGE: u32 = WELL_ROWS as u32;
+const MAX_COMBO_ENTRIES: usize = 64;
+
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone, Debug)]
+pub struct AttackTable {
+    pub lines: [u32; 4],
+    pub combo: Vec<u32>,
+}
+
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone, Debug, Default)]
+pub struct Garbage {
+    pub pending: Vec<u32>,
+    pub seed: u32,
+    pub combo: u32,
+}
+
+impl Default for AttackTable {
+    fn default() -> AttackTable {
+        AttackTable::new()
+    }
+}
+
+impl AttackTable {
+    pub fn new() -> AttackTable {
+        AttackTable {
+            lines: [0, 1, 2, 4],
+            combo: vec![0, 1, 1, 2, 2, 3],
+        }
+    }
+    pub fn validate(&self) -> Result<(), String> {
+        if self.combo.len() > MAX_COMBO_ENTRIES {
+            return Err(format!("attack table can have at most {} combo entries", MAX_COMBO_ENTRIES));
+        }
+        if self.lines.iter().chain(self.combo.iter()).any(|it| *it > MAX_GARBAGE) {
+            return Err(format!("attack table entries must be at most {}", MAX_GARBAGE));
+        }
+        Ok(())
+    }
+    pub fn attack(&self, lines: u32, combo: u32) -> u32 {
+        if lines == 0 {
+            return 0;
+        }
+        let base = self.lines[(lines.min(4) - 1) as usize];
+        let bonus = match self.combo.len() {
+        0 => 0,
+        len => self.combo[(combo.saturating_sub(1) as usize).min(len - 1)],
+        };
+        base.saturating_add(bonus).min(MAX_GARBAGE)
+    }
+}
+
+impl Garbage {
+    pub fn new(seed: u32) -> Garbage {
+        Garbage {
+            pending: vec![],
+            seed,
+            combo: 0,
+        }
+    }
+    pub fn total(&self) -> u32 {
+        self.pending.iter().fold(0, |total, it| total.saturating_add(*it))
+    }
+    pub fn queue(&mut self, lines: u32) {
+        let lines = lines.min(MAX_GARBAGE.saturating_sub(self.total()));
+        if lines > 0 {
+            self.pending.push(lines);
+        }
+    }
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.combo = 0;
+    }
+    fn cancel(&mut self, mut attack: u32) -> u32 {
+        while attack > 0 && !self.pending.is_empty() {
+            if self.pending[0] > attack {
+                self.pending[0] -= attack;
+                return 0;
+            }
+            attack -= self.pending.remove(0);
+        }
+        attack
+    }
+    pub fn attack(&mut self, table: &AttackTable, lines: u32) -> u32 {
+        if lines == 0 {
+            self.combo = 0;
+            return 0;
+        }
+        self.combo += 1;
+        self.cancel(table.attack(lines, self.combo))
+    }
+    fn next_hole(&mut self) -> usize {
+        self.seed = self.seed.wrapping_mul(0x41C64E6D).wrapping_add(0x3039);
+        ((self.seed >> 10) & 0x7FFF) as usize % WELL_COLS
+    }
+    pub fn insert_into(&mut self, well: &mut Well) -> bool {
+        let mut overflowed = false;
+        let mut remaining = MAX_GARBAGE;
+        for lines in std::mem::take(&mut self.pending) {
+            let hole = self.next_hole();
+            let lines = lines.min(remaining);
+            remaining -= lines;
+            for _ in 0..lines {
+                overflowed |= well.blocks[0].iter().any(|it| it.is_some());
+                well.blocks.rotate_left(1);
+                well.blocks[WELL_ROWS - 1] = std::array::from_fn(|col| {
+                    if col == hole {
+                        None
+                    } else {
+                        Some(Tile { color: Block::Gray, directions: BlockDirections::NONE, jewel: false })
+                    }
+                });
+            }
+        }
+        overflowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attacks_are_capped() {
+        let table = AttackTable { lines: [0, 1, 2, MAX_GARBAGE], combo: vec![0, MAX_GARBAGE] };
+        assert!(table.validate().is_ok());
+        assert_eq!(table.attack(4, 2), MAX_GARBAGE);
+
+        let table = AttackTable { lines: [0, 1, 2, u32::MAX], combo: vec![u32::MAX] };
+        assert!(table.validate().is_err());
+        assert_eq!(table.attack(4, 1), MAX_GARBAGE);
+        assert!(AttackTable { lines: [0, 1, 2, 4], combo: vec![0; MAX_COMBO_ENTRIES + 1] }.validate().is_err());
+    }
+
+    #[test]
+    fn pending_garbage_is_capped() {
+        let mut garbage = Garbage::new(1);
+        garbage.queue(4);
+        garbage.queue(u32::MAX);
+        garbage.queue(1);
+        assert_eq!(garbage.pending, vec![4, MAX_GARBAGE - 4]);
+
+        garbage.pending = vec![u32::MAX, u32::MAX];
+        assert_eq!(garbage.total(), u32::MAX);
+        let mut well = Well::new();
+        assert!(!garbage.insert_into(&mut well));
+        assert!(garbage.pending.is_empty());
+        assert!(well.blocks.iter().all(|row| row.iter().filter(|it| it.is_none()).count() == 1));
+    }
+}
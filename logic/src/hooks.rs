@@ -14,3 +14,17 @@ pub trait Sounds {
     fn lock(&mut self);
     fn land(&mut self);
 }
+
+// for simulating fields nobody is watching
+pub struct Silent;
+
+impl Sounds for Silent {
+    fn block_spawn(&mut self, _color: Block) {}
+    fn line_clear(&mut self) {}
+    fn lock(&mut self) {}
+    fn land(&mut self) {}
+}
+
+impl Cubes for Silent {
+    fn spawn_cube(&mut self, _x: i32, _y: i32, _color: Block) {}
+}
@@ -4,7 +4,7 @@
 
 use std::collections::HashMap;
 
-use nanoserde::{DeJson, SerJson};
+use nanoserde::{DeBin, DeJson, SerBin, SerJson};
 
 pub trait InputProvider {
     fn peek(&mut self);
@@ -13,7 +13,7 @@ pub trait InputProvider {
     fn key_down(&self, input: Input) -> bool;
 }
 
-#[derive(DeJson, SerJson, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(DeJson, DeBin, SerJson, SerBin, Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum Input {
     Up,
     Down,
@@ -23,6 +23,7 @@ pub enum Input {
     CCW,
 }
 
+#[derive(SerBin, DeBin, Clone)]
 pub struct Inputs {
     inputs: HashMap<Input, u16>,
     inputs_up: HashMap<Input, u16>,
@@ -2,10 +2,26 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+pub mod clock;
 pub mod field;
+pub mod finesse;
+pub mod fumen;
+pub mod garbage;
 pub mod input;
+pub mod netsim;
 pub mod piece;
+pub mod practice;
 pub mod proto;
+pub mod puzzle;
 pub mod randomizer;
+pub mod records;
+pub mod recording;
+pub mod rewind;
+pub mod rollback;
+pub mod snapshot;
+pub mod stats;
 pub mod well;
-pub mod hooks;
\ No newline at end of file
+pub mod wire;
+pub mod hooks;
+#[cfg(test)]
+mod testing;
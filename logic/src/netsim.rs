@@ -0,0 +1,238 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+// anything messages can be pushed through; dropping it should close the connection
+pub trait Transport {
+    fn send(&mut self, data: &[u8]);
+    fn try_recv(&mut self) -> Option<Vec<u8>>;
+}
+
+// all times are in seconds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NetConditions {
+    pub latency: f64,
+    pub jitter: f64,
+    pub reorder: bool,
+    pub outage_every: Option<f64>,
+    pub outage_length: f64,
+}
+
+impl Default for NetConditions {
+    fn default() -> NetConditions {
+        NetConditions::new()
+    }
+}
+
+impl NetConditions {
+    pub fn new() -> NetConditions {
+        NetConditions {
+            latency: 0.,
+            jitter: 0.,
+            reorder: false,
+            outage_every: None,
+            outage_length: 1.,
+        }
+    }
+    // every duration on the command line is in milliseconds
+    pub fn from_args(args: &[String]) -> Option<NetConditions> {
+        let arg = |name: &str| args.iter().skip_while(|it| *it != name).nth(1).and_then(|it| it.parse::<f64>().ok()).map(|it| it / 1000.);
+        let mut conditions = NetConditions::new();
+        conditions.latency = arg("--sim-latency").unwrap_or(0.);
+        conditions.jitter = arg("--sim-jitter").unwrap_or(0.);
+        conditions.reorder = args.iter().any(|it| it == "--sim-reorder");
+        conditions.outage_every = arg("--sim-outage-every");
+        conditions.outage_length = arg("--sim-outage-length").unwrap_or(1.);
+
+        if conditions.latency > 0. || conditions.jitter > 0. || conditions.reorder || conditions.outage_every.is_some() {
+            Some(conditions)
+        } else {
+            None
+        }
+    }
+}
+
+struct Lane {
+    queue: Vec<(f64, Vec<u8>)>,
+    last_due: f64,
+}
+
+impl Lane {
+    fn new() -> Lane {
+        Lane {
+            queue: vec![],
+            last_due: 0.,
+        }
+    }
+    fn push(&mut self, conditions: &NetConditions, jitter: f64, now: f64, data: Vec<u8>) {
+        let mut due = now + (conditions.latency + jitter).max(0.);
+        if !conditions.reorder {
+            due = due.max(self.last_due);
+        }
+        self.last_due = due;
+        let idx = self.queue.iter().position(|it| it.0 > due).unwrap_or(self.queue.len());
+        self.queue.insert(idx, (due, data));
+    }
+    fn pop(&mut self, now: f64) -> Option<Vec<u8>> {
+        if self.queue.first().map(|it| it.0 <= now).unwrap_or(false) {
+            Some(self.queue.remove(0).1)
+        } else {
+            None
+        }
+    }
+}
+
+// delays and reorders messages in both directions, and when an outage starts it drops the
+// transport along with everything in flight, like a link going down under the connection; the
+// owner notices through `connected` and can `reconnect` once the outage is over
+pub struct SimulatedLink<T: Transport> {
+    transport: Option<T>,
+    conditions: Option<NetConditions>,
+    outgoing: Lane,
+    incoming: Lane,
+    started: f64,
+    seed: u32,
+}
+
+impl<T: Transport> SimulatedLink<T> {
+    pub fn new(transport: T, conditions: Option<NetConditions>, seed: u32, now: f64) -> SimulatedLink<T> {
+        SimulatedLink {
+            transport: Some(transport),
+            conditions,
+            outgoing: Lane::new(),
+            incoming: Lane::new(),
+            started: now,
+            seed,
+        }
+    }
+    pub fn connected(&self) -> bool {
+        self.transport.is_some()
+    }
+    pub fn can_reconnect(&self, now: f64) -> bool {
+        self.transport.is_none() && !self.in_outage(now)
+    }
+    pub fn reconnect(&mut self, transport: T) {
+        self.transport = Some(transport);
+    }
+    fn jitter(&mut self, conditions: &NetConditions) -> f64 {
+        if conditions.jitter <= 0. {
+            return 0.;
+        }
+        self.seed = self.seed.wrapping_mul(0x41C64E6D).wrapping_add(0x3039);
+        let unit = ((self.seed >> 10) & 0x7FFF) as f64 / 0x7FFF as f64;
+        (unit * 2. - 1.) * conditions.jitter
+    }
+    fn in_outage(&self, now: f64) -> bool {
+        match self.conditions.and_then(|it| it.outage_every.map(|every| (every, it.outage_length))) {
+        Some((every, length)) if every > 0. => (now - self.started) % every >= every - length,
+        _ => false,
+        }
+    }
+    fn poll(&mut self, now: f64) {
+        if self.in_outage(now) && self.transport.take().is_some() {
+            self.outgoing = Lane::new();
+            self.incoming = Lane::new();
+        }
+        let Some(transport) = self.transport.as_mut() else {
+            return;
+        };
+        while let Some(data) = self.outgoing.pop(now) {
+            transport.send(&data);
+        }
+    }
+    pub fn send(&mut self, now: f64, data: &[u8]) {
+        let Some(conditions) = self.conditions else {
+            if let Some(transport) = self.transport.as_mut() {
+                transport.send(data);
+            }
+            return;
+        };
+        self.poll(now);
+        if self.transport.is_some() {
+            let jitter = self.jitter(&conditions);
+            self.outgoing.push(&conditions, jitter, now, data.to_vec());
+        }
+        self.poll(now);
+    }
+    pub fn try_recv(&mut self, now: f64) -> Option<Vec<u8>> {
+        let Some(conditions) = self.conditions else {
+            return self.transport.as_mut()?.try_recv();
+        };
+        self.poll(now);
+        while let Some(data) = self.transport.as_mut()?.try_recv() {
+            let jitter = self.jitter(&conditions);
+            self.incoming.push(&conditions, jitter, now, data);
+        }
+        self.incoming.pop(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    struct Loopback {
+        sent: VecDeque<Vec<u8>>,
+    }
+
+    impl Transport for Loopback {
+        fn send(&mut self, data: &[u8]) {
+            self.sent.push_back(data.to_vec());
+        }
+        fn try_recv(&mut self) -> Option<Vec<u8>> {
+            self.sent.pop_front()
+        }
+    }
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ').map(|it| it.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_are_all_milliseconds() {
+        let conditions = NetConditions::from_args(&args("client --sim-latency 100 --sim-jitter 20 --sim-outage-every 5000 --sim-outage-length 250")).unwrap();
+        assert_eq!(conditions.latency, 0.1);
+        assert_eq!(conditions.jitter, 0.02);
+        assert_eq!(conditions.outage_every, Some(5.));
+        assert_eq!(conditions.outage_length, 0.25);
+        assert_eq!(NetConditions::from_args(&args("client --json")), None);
+    }
+
+    #[test]
+    fn messages_arrive_after_the_latency_in_order() {
+        let conditions = NetConditions { latency: 0.1, jitter: 0.05, ..NetConditions::new() };
+        let mut link = SimulatedLink::new(Loopback { sent: VecDeque::new() }, Some(conditions), 1, 0.);
+        for idx in 0..20u8 {
+            link.send(idx as f64 * 0.01, &[idx]);
+        }
+        assert_eq!(link.try_recv(0.09), None);
+        let mut received = vec![];
+        for step in 0..100 {
+            while let Some(data) = link.try_recv(0.1 + step as f64 * 0.01) {
+                received.push(data[0]);
+            }
+        }
+        assert_eq!(received, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn outages_close_the_transport() {
+        let conditions = NetConditions { outage_every: Some(1.), outage_length: 0.25, ..NetConditions::new() };
+        let mut link = SimulatedLink::new(Loopback { sent: VecDeque::new() }, Some(conditions), 1, 0.);
+        link.send(0.5, b"before");
+        assert_eq!(link.try_recv(0.5), Some(b"before".to_vec()));
+        link.send(0.7, b"lost");
+        assert!(link.connected());
+
+        assert_eq!(link.try_recv(0.8), None);
+        assert!(!link.connected());
+        assert!(!link.can_reconnect(0.9));
+        assert!(link.can_reconnect(1.1));
+
+        link.reconnect(Loopback { sent: VecDeque::new() });
+        link.send(1.1, b"after");
+        assert_eq!(link.try_recv(1.1), Some(b"after".to_vec()));
+    }
+}
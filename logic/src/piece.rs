@@ -4,13 +4,13 @@
 
 use std::cmp::max;
 
-use nanoserde::{DeJson, SerJson};
+use nanoserde::{DeBin, DeJson, SerBin, SerJson};
 
 use crate::hooks::Sounds;
 use crate::well::{Block, BlockDirections, Tile, Well, WELL_COLS, WELL_ROWS};
 use crate::input::{Input, Inputs};
 
-#[derive(Copy, Clone, Debug, SerJson, DeJson)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, SerJson, SerBin, DeJson, DeBin)]
 pub enum Rotation {
     R0,
     R90,
@@ -18,7 +18,7 @@ pub enum Rotation {
     R270,
 }
 
-#[derive(Copy, Clone, SerJson, DeJson, Debug)]
+#[derive(Copy, Clone, SerJson, SerBin, DeJson, DeBin, Debug)]
 pub enum Rotations {
     IPiece,
     OPiece,
@@ -86,7 +86,7 @@ impl Rotation {
 }
 
 
-#[derive(Copy, Clone, Debug, SerJson, DeJson)]
+#[derive(Copy, Clone, Debug, SerJson, SerBin, DeJson, DeBin)]
 pub struct Piece {
     pub rotation: Rotation,
     pub rotations: Rotations,
@@ -108,6 +108,7 @@ impl Piece {
         Block::Cyan => Rotations::TPiece,
         Block::Blue => Rotations::JPiece,
         Block::Purple => Rotations::SPiece,
+        Block::Gray => unreachable!("garbage blocks are never pieces"),
         };
         Piece {
             rotation: Rotation::R0,
@@ -166,27 +167,21 @@ impl Piece {
             self.ticks_to_next_gravity = 256;
         }
     }
+    pub fn try_rotate(&mut self, well: &Well, rotation: Rotation) -> bool {
+        for dx in [0, 1, -1] {
+            if !self.collides_with(well, dx, 0, rotation) {
+                self.rotation = rotation;
+                self.x += dx;
+                return true;
+            }
+        }
+        false
+    }
     pub fn do_rotate(&mut self, well: &Well, inputs: &Inputs) {
         if inputs.key_just_pressed(Input::CW) {
-            if !self.collides_with(well, 0, 0, self.rotation.cw()) {
-                self.rotation = self.rotation.cw();
-            } else if !self.collides_with(well, 1, 0, self.rotation.cw()) {
-                self.rotation = self.rotation.cw();
-                self.x += 1;
-            } else if !self.collides_with(well, -1, 0, self.rotation.cw()) {
-                self.rotation = self.rotation.cw();
-                self.x -= 1;
-            }
+            self.try_rotate(well, self.rotation.cw());
         } else if inputs.key_just_pressed(Input::CCW) {
-            if !self.collides_with(well, 0, 0, self.rotation.ccw()) {
-                self.rotation = self.rotation.ccw();
-            } else if !self.collides_with(well, 1, 0, self.rotation.ccw()) {
-                self.rotation = self.rotation.ccw();
-                self.x += 1;
-            } else if !self.collides_with(well, -1, 0, self.rotation.ccw()) {
-                self.rotation = self.rotation.ccw();
-                self.x -= 1;
-            }
+            self.try_rotate(well, self.rotation.ccw());
         }
     }
     pub fn do_lock(&self, well: &mut Well, inputs: &Inputs, sounds: &mut dyn Sounds) -> bool {
@@ -220,7 +215,7 @@ impl Piece {
                     let left = check(-1, 0);
                     let right = check(1, 0);
 
-                    well.blocks[(self.y+ri as i32) as usize][(self.x+ci as i32) as usize] = Some(Tile { color: self.color, directions: BlockDirections::new(up, down, left, right) });
+                    well.blocks[(self.y+ri as i32) as usize][(self.x+ci as i32) as usize] = Some(Tile { color: self.color, directions: BlockDirections::new(up, down, left, right), jewel: false });
                 }
             }
         }
@@ -0,0 +1,149 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use nanoserde::{DeJson, SerJson};
+
+use crate::{field::{Field, GameState}, fumen::Fumen, garbage::Garbage, piece::Piece, randomizer::Randomizer, well::{Block, BlockDirections, Tile, Well, WELL_COLS, WELL_ROWS}};
+
+pub const GRAVITY_PRESETS: &[Option<i32>] = &[
+    None,
+    Some(4),
+    Some(32),
+    Some(128),
+    Some(256),
+    Some(768),
+    Some(5120),
+];
+
+#[derive(SerJson, DeJson, Clone)]
+pub struct PracticeSetup {
+    pub level: u32,
+    pub gravity: Option<i32>,
+    pub queue: Vec<Block>,
+    pub well: Well,
+}
+
+impl Default for PracticeSetup {
+    fn default() -> PracticeSetup {
+        PracticeSetup::new()
+    }
+}
+
+impl PracticeSetup {
+    pub fn new() -> PracticeSetup {
+        PracticeSetup {
+            level: 0,
+            gravity: None,
+            queue: vec![],
+            well: Well::new(),
+        }
+    }
+    pub fn from_field(field: &Field) -> PracticeSetup {
+        PracticeSetup {
+            level: field.level,
+            gravity: field.gravity_override,
+            queue: vec![],
+            well: field.well.clone(),
+        }
+    }
+    // setups come from files people edit by hand, and garbage can't be dealt as a piece
+    pub fn validate(&self) -> Result<(), String> {
+        if self.queue.contains(&Block::Gray) {
+            return Err("the piece queue can't contain garbage blocks".to_string());
+        }
+        Ok(())
+    }
+    pub fn to_field(&self) -> Result<Field, String> {
+        self.validate()?;
+        let mut randomizer = if self.queue.is_empty() {
+            Randomizer::new()
+        } else {
+            Randomizer::Sequence { pieces: self.queue.clone(), index: 0 }
+        };
+        let piece = randomizer.next_piece();
+
+        Ok(Field {
+            well: self.well.clone(),
+            next: randomizer.next_piece(),
+            level: self.level,
+            gravity_override: self.gravity,
+            garbage: Garbage::new(10),
+            state: GameState::ActivePiece { piece },
+
+            randomizer,
+        })
+    }
+    pub fn to_fumen(&self) -> Fumen {
+        let current = self.queue.first().map(|it| Piece::new(*it));
+        Fumen::new(self.well.clone(), current, self.queue.iter().skip(1).cloned().collect())
+    }
+    pub fn from_fumen(fumen: Fumen, level: u32, gravity: Option<i32>) -> PracticeSetup {
+        PracticeSetup {
+            level,
+            gravity,
+            queue: fumen.current.map(|it| it.color).into_iter().chain(fumen.next).collect(),
+            well: fumen.well,
+        }
+    }
+    pub fn cycle_gravity(&mut self) {
+        let current = GRAVITY_PRESETS.iter().position(|it| *it == self.gravity).unwrap_or(0);
+        self.gravity = GRAVITY_PRESETS[(current + 1) % GRAVITY_PRESETS.len()];
+    }
+    pub fn paint(&mut self, row: usize, col: usize, color: Option<Block>) {
+        if row < WELL_ROWS && col < WELL_COLS {
+            self.well.blocks[row][col] = color.map(|color| Tile { color, directions: BlockDirections::NONE, jewel: false });
+        }
+    }
+}
+
+pub fn piece_letter(color: Block) -> char {
+    match color {
+    Block::Red => 'I',
+    Block::Orange => 'L',
+    Block::Yellow => 'O',
+    Block::Green => 'Z',
+    Block::Cyan => 'T',
+    Block::Blue => 'J',
+    Block::Purple => 'S',
+    Block::Gray => 'G',
+    }
+}
+
+pub fn piece_from_letter(letter: char) -> Option<Block> {
+    match letter.to_ascii_uppercase() {
+    'I' => Some(Block::Red),
+    'L' => Some(Block::Orange),
+    'O' => Some(Block::Yellow),
+    'Z' => Some(Block::Green),
+    'T' => Some(Block::Cyan),
+    'J' => Some(Block::Blue),
+    'S' => Some(Block::Purple),
+    _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn garbage_in_the_queue_is_rejected() {
+        let setup = PracticeSetup::deserialize_json(&PracticeSetup { queue: vec![Block::Red, Block::Gray], ..PracticeSetup::new() }.serialize_json()).unwrap();
+        assert!(setup.validate().is_err());
+        assert!(setup.to_field().is_err());
+        assert!(PracticeSetup { queue: vec![Block::Red, Block::Cyan], ..PracticeSetup::new() }.validate().is_ok());
+
+        let fumen = PracticeSetup { queue: vec![Block::Red, Block::Gray], ..PracticeSetup::new() }.to_fumen();
+        assert!(PracticeSetup::from_fumen(fumen, 0, None).to_field().is_err());
+    }
+
+    #[test]
+    fn empty_queue_deals_randomly() {
+        let mut field = PracticeSetup::new().to_field().unwrap();
+        assert!(matches!(field.randomizer, Randomizer::TTATGM2P { .. }));
+        for _ in 0..20 {
+            field.randomizer.next_piece();
+        }
+    }
+}
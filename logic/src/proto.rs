@@ -2,21 +2,202 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use nanoserde::{DeJson, SerJson};
+use nanoserde::{DeBin, DeJson, SerBin, SerJson};
 
-use crate::{field::Field, input::Input};
+use crate::{field::Field, garbage::AttackTable, input::Input, recording::{MatchRecord, MatchSummary}, snapshot::Snapshot, wire::{self, WireFormat}};
+
+pub const PROTOCOL_VERSION: u32 = 10;
+
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone, Debug, PartialEq, Eq)]
+pub enum ProtocolError {
+    InvalidUtf8 {},
+    Malformed { message: String },
+    HandshakeRequired {},
+    VersionMismatch { server: u32, client: u32 },
+    Timeout {},
+    RateLimited {},
+    MessageTooLarge { size: u32, limit: u32 },
+    Kicked {},
+    ShuttingDown {},
+}
+
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone, Debug)]
+pub struct RoomInfo {
+    pub name: String,
+    pub players: u32,
+    pub capacity: u32,
+    pub spectators: u32,
+    pub playing: bool,
+}
+
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone, Debug)]
+pub struct PlayerRating {
+    pub player: String,
+    pub rating: f64,
+    pub deviation: f64,
+    pub games: u32,
+}
 
 #[derive(SerJson, DeJson, Clone)]
 pub enum ClientToServer {
-    Join { client_id: u32 },
-    Input { input: Input, up: bool },
-    Tick {},
+    Hello { version: u32, format: WireFormat },
+    Join { token: Option<String> },
+    Input { input: Input, up: bool, tick: u64 },
+    CreateRoom { name: String, capacity: u32, attack_table: Option<AttackTable> },
+    JoinRoom { name: String },
+    LeaveRoom {},
+    Spectate { name: String },
+    ListRooms {},
+    Ready { ready: bool },
+    Ping { nonce: u32 },
+    Pong { nonce: u32 },
+    TimeSync { client_time: u64 },
+    Identify { player: String, key: Option<String> },
+    QueueRanked {},
+    LeaveQueue {},
+    GetRating { player: String },
+    ListMatches {},
+    GetMatch { id: u64 },
+    Chat { message: String },
 }
 
 #[derive(SerJson, DeJson, Clone)]
 pub enum ServerToClient {
+    Hello { version: u32, format: WireFormat },
+    Error { error: ProtocolError },
     Join { client_id: u32, field: Field },
     Leave { client_id: u32 },
-    Input { client_id: u32, input: Input, up: bool },
-    Tick { client_id: u32 },
-}
\ No newline at end of file
+    Input { client_id: u32, input: Input, up: bool, tick: u64 },
+    Tick { client_id: u32, tick: u64 },
+    RoomList { rooms: Vec<RoomInfo> },
+    RoomJoined { room: RoomInfo },
+    RoomLeft {},
+    Spectating { room: RoomInfo },
+    Snapshot { client_id: u32, tick: u64, snapshot: Snapshot },
+    RoomError { message: String },
+    Ready { client_id: u32, ready: bool },
+    Countdown { start_tick: u64, now: u64 },
+    CountdownCancelled {},
+    Start { seed: u32, start_tick: u64 },
+    Garbage { client_id: u32, lines: u32, tick: u64 },
+    RoomFinished { winner: Option<u32> },
+    Welcome { client_id: u32, token: String, resumed: bool },
+    Ping { nonce: u32 },
+    Pong { nonce: u32 },
+    TimeSync { client_time: u64, server_time: u64, tick: u64 },
+    Queued { rating: PlayerRating },
+    QueueLeft {},
+    MatchFound { room: String, opponent: PlayerRating },
+    Rating { rating: PlayerRating },
+    RatingChanged { rating: PlayerRating, change: f64 },
+    MatchList { matches: Vec<MatchSummary> },
+    MatchRecording { record: MatchRecord },
+    Announcement { message: String },
+    Chat { client_id: u32, name: String, message: String },
+    Identified { player: String, key: String },
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+        ProtocolError::InvalidUtf8 {} => write!(f, "message is not valid utf-8"),
+        ProtocolError::Malformed { message } => write!(f, "malformed message: {}", message),
+        ProtocolError::HandshakeRequired {} => write!(f, "expected a hello message first"),
+        ProtocolError::VersionMismatch { server, client } => write!(f, "protocol version mismatch: server speaks {}, client speaks {}", server, client),
+        ProtocolError::Timeout {} => write!(f, "connection timed out"),
+        ProtocolError::RateLimited {} => write!(f, "too many messages"),
+        ProtocolError::MessageTooLarge { size, limit } => write!(f, "message of {} bytes exceeds the limit of {}", size, limit),
+        ProtocolError::Kicked {} => write!(f, "kicked by the server"),
+        ProtocolError::ShuttingDown {} => write!(f, "server is shutting down"),
+        }
+    }
+}
+
+fn decode_json<T: DeJson>(bytes: &[u8]) -> Result<T, ProtocolError> {
+    let text = std::str::from_utf8(bytes).map_err(|_| ProtocolError::InvalidUtf8 {})?;
+    T::deserialize_json(text).map_err(|e| ProtocolError::Malformed { message: e.to_string() })
+}
+
+pub fn encode_client(msg: &ClientToServer, format: WireFormat) -> Vec<u8> {
+    match format {
+    WireFormat::Json => msg.serialize_json().into_bytes(),
+    WireFormat::Binary => {
+        let mut output = vec![];
+        wire::ser_client(msg, &mut output);
+        output
+    }
+    }
+}
+
+pub fn decode_client(bytes: &[u8], format: WireFormat) -> Result<ClientToServer, ProtocolError> {
+    match format {
+    WireFormat::Json => decode_json(bytes),
+    WireFormat::Binary => wire::de_client(bytes),
+    }
+}
+
+pub fn encode_server(msg: &ServerToClient, format: WireFormat) -> Vec<u8> {
+    match format {
+    WireFormat::Json => msg.serialize_json().into_bytes(),
+    WireFormat::Binary => {
+        let mut output = vec![];
+        wire::ser_server(msg, &mut output);
+        output
+    }
+    }
+}
+
+pub fn decode_server(bytes: &[u8], format: WireFormat) -> Result<ServerToClient, ProtocolError> {
+    match format {
+    WireFormat::Json => decode_json(bytes),
+    WireFormat::Binary => wire::de_server(bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{client_messages, server_messages};
+
+    // fixtures hold one message per line; run with UPDATE_FIXTURES=1 to rewrite them after an
+    // intentional protocol change, and bump PROTOCOL_VERSION with it
+    fn check_golden(name: &str, golden: &str, encoded: Vec<String>) {
+        if std::env::var_os("UPDATE_FIXTURES").is_some() {
+            let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(name);
+            std::fs::write(path, encoded.join("\n") + "\n").unwrap();
+            return;
+        }
+        let golden = golden.lines().collect::<Vec<_>>();
+        assert_eq!(golden.len(), encoded.len(), "{} has the wrong number of messages", name);
+        for (line, (golden, encoded)) in golden.iter().zip(encoded.iter()).enumerate() {
+            assert_eq!(golden, encoded, "{} line {} changed", name, line + 1);
+        }
+    }
+
+    #[test]
+    fn client_messages_match_fixtures() {
+        let golden = include_str!("../fixtures/client_to_server.jsonl");
+        check_golden("client_to_server.jsonl", golden, client_messages().iter().map(|it| it.serialize_json()).collect());
+        for line in golden.lines() {
+            let decoded = decode_client(line.as_bytes(), WireFormat::Json).unwrap();
+            assert_eq!(decoded.serialize_json(), line);
+        }
+    }
+
+    #[test]
+    fn server_messages_match_fixtures() {
+        let golden = include_str!("../fixtures/server_to_client.jsonl");
+        check_golden("server_to_client.jsonl", golden, server_messages().iter().map(|it| it.serialize_json()).collect());
+        for line in golden.lines() {
+            let decoded = decode_server(line.as_bytes(), WireFormat::Json).unwrap();
+            assert_eq!(decoded.serialize_json(), line);
+        }
+    }
+
+    #[test]
+    fn bad_json_is_a_typed_error() {
+        assert_eq!(decode_client(&[0xff, 0xfe], WireFormat::Json).err(), Some(ProtocolError::InvalidUtf8 {}));
+        assert!(matches!(decode_client(b"{\"Nope\":{}}", WireFormat::Json), Err(ProtocolError::Malformed { .. })));
+        assert!(matches!(decode_server(b"{\"Tick\":{\"client_id\":1", WireFormat::Json), Err(ProtocolError::Malformed { .. })));
+    }
+}
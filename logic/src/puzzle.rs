@@ -0,0 +1,283 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use nanoserde::{DeJson, SerJson};
+
+use crate::{field::{Field, GameState}, hooks::{Cubes, Sounds}, input::Inputs, practice::{piece_from_letter, PracticeSetup}, well::{Block, BlockDirections, Tile, Well, WELL_COLS, WELL_ROWS}};
+
+#[derive(SerJson, DeJson, Clone, Debug)]
+pub enum Goal {
+    ClearJewels {},
+    ClearLines { lines: u32, pieces: u32 },
+}
+
+#[derive(SerJson, DeJson, Clone)]
+pub struct Stage {
+    pub name: String,
+    pub layout: Vec<String>,
+    pub pieces: String,
+    pub goal: Goal,
+    pub time_limit: u32,
+    pub level: u32,
+}
+
+#[derive(SerJson, DeJson, Clone)]
+pub struct StageList {
+    pub stages: Vec<Stage>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PuzzleState {
+    Playing,
+    Cleared { ticks_remaining: i32 },
+    Failed { ticks_remaining: i32 },
+    Finished,
+}
+
+pub struct Puzzle {
+    pub stages: Vec<Stage>,
+    pub stage: usize,
+    pub field: Field,
+    pub ticks: u32,
+    pub pieces_used: u32,
+    pub lines_cleared: u32,
+    pub state: PuzzleState,
+}
+
+const RESULT_DELAY: i32 = 60 * 2;
+
+impl Stage {
+    pub fn well(&self) -> Result<Well, String> {
+        if self.layout.len() > WELL_ROWS {
+            return Err(format!("stage {} has {} rows, but the well only has {}", self.name, self.layout.len(), WELL_ROWS));
+        }
+
+        let mut well = Well::new();
+        let top = WELL_ROWS - self.layout.len();
+        for (ri, row) in self.layout.iter().enumerate() {
+            if row.chars().count() > WELL_COLS {
+                return Err(format!("stage {} has a row wider than the well: {}", self.name, row));
+            }
+            for (ci, ch) in row.chars().enumerate() {
+                if ch == '.' || ch == ' ' {
+                    continue;
+                }
+                let color = if ch.eq_ignore_ascii_case(&'G') {
+                    Block::Gray
+                } else {
+                    piece_from_letter(ch).ok_or_else(|| format!("stage {} has an unknown block: {}", self.name, ch))?
+                };
+                well.blocks[top + ri][ci] = Some(Tile {
+                    color,
+                    directions: BlockDirections::NONE,
+                    jewel: ch.is_ascii_lowercase(),
+                });
+            }
+        }
+        Ok(well)
+    }
+    pub fn setup(&self) -> Result<PracticeSetup, String> {
+        let queue = self.pieces.chars()
+            .map(|ch| piece_from_letter(ch).ok_or_else(|| format!("stage {} has an unknown piece: {}", self.name, ch)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if queue.is_empty() {
+            return Err(format!("stage {} has no pieces", self.name));
+        }
+
+        Ok(PracticeSetup {
+            level: self.level,
+            gravity: None,
+            queue,
+            well: self.well()?,
+        })
+    }
+    fn piece_limit(&self) -> u32 {
+        match self.goal {
+            Goal::ClearJewels {} => self.pieces.chars().count() as u32,
+            Goal::ClearLines { pieces, .. } => pieces,
+        }
+    }
+}
+
+impl Puzzle {
+    pub fn new(stages: Vec<Stage>) -> Result<Puzzle, String> {
+        if stages.is_empty() {
+            return Err("stage list is empty".to_string());
+        }
+        for stage in &stages {
+            stage.setup()?.validate()?;
+        }
+
+        let field = stages[0].setup()?.to_field()?;
+        Ok(Puzzle {
+            stages,
+            stage: 0,
+            field,
+            ticks: 0,
+            pieces_used: 0,
+            lines_cleared: 0,
+            state: PuzzleState::Playing,
+        })
+    }
+    fn start_stage(&mut self, stage: usize) {
+        self.stage = stage;
+        self.field = self.stages[stage].setup().and_then(|it| it.to_field()).expect("stages are validated on load");
+        self.ticks = 0;
+        self.pieces_used = 0;
+        self.lines_cleared = 0;
+        self.state = PuzzleState::Playing;
+    }
+    fn jewels_remaining(&self) -> usize {
+        self.field.well.blocks.iter()
+            .flatten()
+            .filter(|it| it.map(|tile| tile.jewel).unwrap_or(false))
+            .count()
+    }
+    fn goal_met(&self) -> bool {
+        match self.stages[self.stage].goal {
+            Goal::ClearJewels {} => self.jewels_remaining() == 0,
+            Goal::ClearLines { lines, .. } => self.lines_cleared >= lines,
+        }
+    }
+    pub fn time_remaining(&self) -> Option<u32> {
+        let limit = self.stages[self.stage].time_limit * 60;
+        if limit == 0 {
+            None
+        } else {
+            Some(limit.saturating_sub(self.ticks))
+        }
+    }
+    pub fn update(&mut self, inputs: &Inputs, sounds: &mut dyn Sounds, cubes: &mut dyn Cubes) {
+        match self.state {
+            PuzzleState::Playing => {
+                self.ticks += 1;
+                if let Some(locked) = self.field.update(inputs, sounds, cubes) {
+                    self.pieces_used += 1;
+                    self.lines_cleared += locked.lines;
+
+                    if self.goal_met() {
+                        self.state = PuzzleState::Cleared { ticks_remaining: RESULT_DELAY };
+                        return;
+                    }
+                }
+
+                let out_of_pieces = self.pieces_used >= self.stages[self.stage].piece_limit();
+                let out_of_time = self.time_remaining() == Some(0);
+                let topped_out = matches!(self.field.state, GameState::GameOver { .. });
+                if out_of_pieces || out_of_time || topped_out {
+                    self.state = PuzzleState::Failed { ticks_remaining: RESULT_DELAY };
+                }
+            }
+            PuzzleState::Cleared { ref mut ticks_remaining } => {
+                *ticks_remaining -= 1;
+                if *ticks_remaining == 0 {
+                    if self.stage + 1 < self.stages.len() {
+                        self.start_stage(self.stage + 1);
+                    } else {
+                        self.state = PuzzleState::Finished;
+                    }
+                }
+            }
+            PuzzleState::Failed { ref mut ticks_remaining } => {
+                *ticks_remaining -= 1;
+                if *ticks_remaining == 0 {
+                    self.start_stage(self.stage);
+                }
+            }
+            PuzzleState::Finished => {}
+        }
+    }
+    pub fn status(&self) -> Vec<String> {
+        let stage = &self.stages[self.stage];
+        let mut lines = vec![
+            format!("Stage {}/{}: {}", self.stage + 1, self.stages.len(), stage.name),
+            match stage.goal {
+                Goal::ClearJewels {} => format!("Jewels left: {}", self.jewels_remaining()),
+                Goal::ClearLines { lines, .. } => format!("Lines: {}/{}", self.lines_cleared, lines),
+            },
+            format!("Pieces: {}/{}", self.pieces_used, stage.piece_limit()),
+        ];
+        if let Some(remaining) = self.time_remaining() {
+            lines.push(format!("Time: {}.{:02}", remaining / 60, (remaining % 60) * 100 / 60));
+        }
+        match self.state {
+            PuzzleState::Playing => {}
+            PuzzleState::Cleared { .. } => lines.push("Clear!".to_string()),
+            PuzzleState::Failed { .. } => lines.push("Failed".to_string()),
+            PuzzleState::Finished => lines.push("All stages clear!".to_string()),
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hooks::Silent, input::Input, testing::Held};
+
+    struct Solver {
+        puzzle: Puzzle,
+        inputs: Inputs,
+        held: Held,
+        tick: u64,
+    }
+
+    impl Solver {
+        fn step(&mut self, down: &[Input]) {
+            self.held.hold(down);
+            self.tick += 1;
+            self.inputs.tick(self.tick, &mut self.held);
+            self.puzzle.update(&self.inputs, &mut Silent, &mut Silent);
+        }
+        fn wait_for_piece(&mut self) {
+            while !(self.puzzle.state == PuzzleState::Playing && matches!(self.puzzle.field.state, GameState::ActivePiece { .. })) {
+                self.step(&[]);
+            }
+        }
+        // turns the piece clockwise, taps it sideways one column at a time, then drops and locks it
+        fn place(&mut self, turns: usize, shift: i32) {
+            self.wait_for_piece();
+            for _ in 0..turns {
+                self.step(&[Input::CW]);
+                self.step(&[]);
+            }
+            let direction = if shift < 0 { Input::Left } else { Input::Right };
+            for _ in 0..shift.abs() {
+                self.step(&[direction]);
+                self.step(&[]);
+            }
+            self.step(&[Input::Up]);
+            self.step(&[Input::Down]);
+            self.step(&[]);
+        }
+    }
+
+    #[test]
+    fn sample_stages_can_be_cleared() {
+        let list = StageList::deserialize_json(include_str!("../../app/puzzles/sample.json")).unwrap();
+        let mut solver = Solver {
+            puzzle: Puzzle::new(list.stages).unwrap(),
+            inputs: Inputs::new(),
+            held: Held::new(),
+            tick: 0,
+        };
+        let solutions: &[&[(usize, i32)]] = &[
+            &[(0, 0), (1, 4)],
+            &[(0, -2), (1, 4)],
+            &[(0, -3), (0, 4), (1, -1)],
+        ];
+
+        for (stage, solution) in solutions.iter().enumerate() {
+            assert_eq!(solver.puzzle.stage, stage);
+            for (turns, shift) in solution.iter() {
+                solver.place(*turns, *shift);
+            }
+            assert!(matches!(solver.puzzle.state, PuzzleState::Cleared { .. }), "stage {} wasn't cleared: {:?}", stage, solver.puzzle.status());
+            while matches!(solver.puzzle.state, PuzzleState::Cleared { .. }) {
+                solver.step(&[]);
+            }
+        }
+        assert_eq!(solver.puzzle.state, PuzzleState::Finished);
+    }
+}
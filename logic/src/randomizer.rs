@@ -2,19 +2,23 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use nanoserde::{DeJson, SerJson};
+use nanoserde::{DeBin, DeJson, SerBin, SerJson};
 
 use crate::{piece::Piece, well::Block};
 
-#[derive(SerJson, DeJson, Clone)]
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone)]
 pub enum Randomizer {
-    TTATGM2P { seed: u32, history: [u8; 4] }
+    TTATGM2P { seed: u32, history: [u8; 4] },
+    Sequence { pieces: Vec<Block>, index: u32 },
 }
 
 impl Randomizer {
     pub fn new() -> Randomizer {
+        Randomizer::seeded(10)
+    }
+    pub fn seeded(seed: u32) -> Randomizer {
         Randomizer::TTATGM2P {
-            seed: 10,
+            seed,
             history: [1, 1, 2, 2]
         }
     }
@@ -57,6 +61,11 @@ impl Randomizer {
             _ => unreachable!("invalid piece")
             }
         }
+        Randomizer::Sequence { ref pieces, ref mut index } => {
+            let piece = Piece::new(pieces[*index as usize % pieces.len()]);
+            *index += 1;
+            piece
+        }
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,130 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use nanoserde::{DeBin, DeJson, SerBin, SerJson};
+
+use crate::{field::Field, garbage::AttackTable, input::{Input, Inputs, RECORDABLE_INPUTS}};
+
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone, Debug)]
+pub struct RecordedInput {
+    pub tick: u64,
+    pub input: Input,
+    pub up: bool,
+}
+
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone, Debug)]
+pub struct RecordedGarbage {
+    pub tick: u64,
+    pub lines: u32,
+}
+
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone, Debug)]
+pub struct RecordedPlayer {
+    pub client_id: u32,
+    pub player: Option<String>,
+    pub inputs: Vec<RecordedInput>,
+    pub garbage: Vec<RecordedGarbage>,
+}
+
+// every field in a match starts from Field::seeded(seed), so replaying each player's inputs and
+// garbage at their ticks reproduces the whole match
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone, Debug)]
+pub struct MatchRecord {
+    pub id: u64,
+    pub room: String,
+    pub date: u64,
+    pub seed: u32,
+    pub attack_table: AttackTable,
+    pub ranked: bool,
+    pub players: Vec<RecordedPlayer>,
+    pub winner: Option<u32>,
+    pub ticks: u64,
+}
+
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone, Debug)]
+pub struct MatchSummary {
+    pub id: u64,
+    pub room: String,
+    pub date: u64,
+    pub ranked: bool,
+    pub players: Vec<String>,
+    pub winner: Option<String>,
+    pub ticks: u64,
+}
+
+// a single-player game is the field as it was when the game started plus every input after that
+#[derive(SerJson, DeJson, Clone)]
+pub struct GameRecord {
+    pub start: Field,
+    pub inputs: Vec<RecordedInput>,
+    pub ticks: u64,
+}
+
+impl RecordedPlayer {
+    fn name(&self) -> String {
+        self.player.clone().unwrap_or_else(|| format!("guest {}", self.client_id))
+    }
+}
+
+impl MatchRecord {
+    pub fn new(room: &str, date: u64, seed: u32, attack_table: AttackTable, ranked: bool, players: Vec<(u32, Option<String>)>) -> MatchRecord {
+        MatchRecord {
+            id: 0,
+            room: room.to_string(),
+            date,
+            seed,
+            attack_table,
+            ranked,
+            players: players.into_iter()
+                .map(|(client_id, player)| RecordedPlayer { client_id, player, inputs: vec![], garbage: vec![] })
+                .collect(),
+            winner: None,
+            ticks: 0,
+        }
+    }
+    fn player_mut(&mut self, client_id: u32) -> Option<&mut RecordedPlayer> {
+        self.players.iter_mut().find(|it| it.client_id == client_id)
+    }
+    pub fn record_input(&mut self, client_id: u32, tick: u64, input: Input, up: bool) {
+        if let Some(player) = self.player_mut(client_id) {
+            player.inputs.push(RecordedInput { tick, input, up });
+        }
+    }
+    pub fn record_garbage(&mut self, client_id: u32, tick: u64, lines: u32) {
+        if let Some(player) = self.player_mut(client_id) {
+            player.garbage.push(RecordedGarbage { tick, lines });
+        }
+    }
+    pub fn summary(&self) -> MatchSummary {
+        MatchSummary {
+            id: self.id,
+            room: self.room.clone(),
+            date: self.date,
+            ranked: self.ranked,
+            players: self.players.iter().map(|it| it.name()).collect(),
+            winner: self.winner.and_then(|winner| self.players.iter().find(|it| it.client_id == winner)).map(|it| it.name()),
+            ticks: self.ticks,
+        }
+    }
+}
+
+impl GameRecord {
+    pub fn new(start: &Field) -> GameRecord {
+        GameRecord {
+            start: start.clone(),
+            inputs: vec![],
+            ticks: 0,
+        }
+    }
+    pub fn record(&mut self, inputs: &Inputs) {
+        for input in RECORDABLE_INPUTS {
+            if inputs.key_just_pressed(*input) {
+                self.inputs.push(RecordedInput { tick: self.ticks, input: *input, up: false });
+            } else if inputs.key_just_released(*input) {
+                self.inputs.push(RecordedInput { tick: self.ticks, input: *input, up: true });
+            }
+        }
+        self.ticks += 1;
+    }
+}
@@ -0,0 +1,214 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use nanoserde::{DeJson, SerJson};
+
+use crate::stats::{format_ticks, GameResult};
+
+pub const MAX_ENTRIES: usize = 10;
+pub const NORMAL_MODE: &str = "Normal";
+
+const GRADES: &[&str] = &[
+    "9", "8", "7", "6", "5", "4", "3", "2", "1",
+    "S1", "S2", "S3", "S4", "S5", "S6", "S7", "S8", "S9",
+];
+
+#[derive(SerJson, DeJson, Clone, Debug)]
+pub struct RecordEntry {
+    pub level: u32,
+    pub ticks: u64,
+    pub grade: String,
+    pub date: u64,
+    #[nserde(default)]
+    pub replay: Option<String>,
+    #[nserde(default)]
+    pub splits: Vec<u64>,
+}
+
+#[derive(SerJson, DeJson, Clone, Debug)]
+pub struct ModeRecords {
+    pub mode: String,
+    pub entries: Vec<RecordEntry>,
+    #[nserde(default)]
+    pub best_splits: Vec<u64>,
+}
+
+#[derive(SerJson, DeJson, Clone, Debug)]
+pub struct Records {
+    pub modes: Vec<ModeRecords>,
+}
+
+pub fn grade(level: u32) -> String {
+    if level >= 999 {
+        "GM".to_string()
+    } else {
+        GRADES[(level as usize / 50).min(GRADES.len() - 1)].to_string()
+    }
+}
+
+fn format_date(date: u64) -> String {
+    let days = date / 86400;
+    let era = (days as i64 + 719468).div_euclid(146097);
+    let day_of_era = days as i64 + 719468 - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+impl RecordEntry {
+    pub fn new(result: &GameResult, date: u64) -> RecordEntry {
+        RecordEntry {
+            level: result.level,
+            ticks: result.ticks,
+            grade: grade(result.level),
+            date,
+            replay: None,
+            splits: result.stats.section_ticks.clone(),
+        }
+    }
+    fn beats(&self, other: &RecordEntry) -> bool {
+        self.level > other.level || (self.level == other.level && self.ticks < other.ticks)
+    }
+}
+
+impl ModeRecords {
+    fn new(mode: &str) -> ModeRecords {
+        ModeRecords {
+            mode: mode.to_string(),
+            entries: vec![],
+            best_splits: vec![],
+        }
+    }
+    fn section_times(splits: &[u64]) -> Vec<u64> {
+        splits.iter().enumerate()
+            .map(|(idx, split)| split - if idx == 0 { 0 } else { splits[idx - 1] })
+            .collect()
+    }
+    fn submit_splits(&mut self, splits: &[u64]) {
+        for (idx, time) in ModeRecords::section_times(splits).into_iter().enumerate() {
+            match self.best_splits.get_mut(idx) {
+                Some(best) => *best = (*best).min(time),
+                None => self.best_splits.push(time),
+            }
+        }
+    }
+}
+
+impl Default for Records {
+    fn default() -> Records {
+        Records::new()
+    }
+}
+
+impl Records {
+    pub fn new() -> Records {
+        Records {
+            modes: vec![],
+        }
+    }
+    pub fn mode(&self, mode: &str) -> Option<&ModeRecords> {
+        self.modes.iter().find(|it| it.mode == mode)
+    }
+    fn mode_mut(&mut self, mode: &str) -> &mut ModeRecords {
+        match self.modes.iter().position(|it| it.mode == mode) {
+            Some(idx) => &mut self.modes[idx],
+            None => {
+                self.modes.push(ModeRecords::new(mode));
+                self.modes.last_mut().unwrap()
+            }
+        }
+    }
+    fn rank(&self, mode: &str, entry: &RecordEntry) -> Option<usize> {
+        let entries = self.mode(mode).map(|it| it.entries.as_slice()).unwrap_or(&[]);
+        let rank = entries.iter().position(|it| entry.beats(it)).unwrap_or(entries.len());
+        (rank < MAX_ENTRIES).then_some(rank)
+    }
+    pub fn qualifies(&self, mode: &str, entry: &RecordEntry) -> bool {
+        self.rank(mode, entry).is_some()
+    }
+    pub fn submit(&mut self, mode: &str, entry: RecordEntry) -> Option<usize> {
+        let rank = self.rank(mode, &entry);
+        let records = self.mode_mut(mode);
+        records.submit_splits(&entry.splits);
+
+        let rank = rank?;
+        records.entries.insert(rank, entry);
+        records.entries.truncate(MAX_ENTRIES);
+        Some(rank)
+    }
+    pub fn table(&self, mode: &str, highlight: Option<usize>) -> Vec<String> {
+        let mut lines = vec![format!("{} records", mode)];
+        let records = match self.mode(mode) {
+            Some(records) if !records.entries.is_empty() => records,
+            _ => {
+                lines.push("No records yet".to_string());
+                return lines;
+            }
+        };
+
+        for (idx, entry) in records.entries.iter().enumerate() {
+            lines.push(format!(
+                "{}{}. {} Lv{} {} {}",
+                if highlight == Some(idx) { "> " } else { "" },
+                idx + 1,
+                entry.grade,
+                entry.level,
+                format_ticks(entry.ticks),
+                format_date(entry.date),
+            ));
+        }
+        if !records.best_splits.is_empty() {
+            lines.push("Best sections".to_string());
+            for (idx, time) in records.best_splits.iter().enumerate() {
+                lines.push(format!("{:03}-{:03} {}", idx * 100, idx * 100 + 99, format_ticks(*time)));
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(level: u32, ticks: u64) -> RecordEntry {
+        RecordEntry {
+            level,
+            ticks,
+            grade: grade(level),
+            date: 0,
+            replay: None,
+            splits: vec![],
+        }
+    }
+
+    #[test]
+    fn only_entries_that_make_the_table_qualify() {
+        let mut records = Records::new();
+        for level in 0..MAX_ENTRIES as u32 {
+            assert!(records.qualifies(NORMAL_MODE, &entry(100 + level, 6000)));
+            records.submit(NORMAL_MODE, entry(100 + level, 6000));
+        }
+        assert!(!records.qualifies(NORMAL_MODE, &entry(50, 6000)));
+        assert_eq!(records.submit(NORMAL_MODE, entry(50, 6000)), None);
+        assert!(records.qualifies(NORMAL_MODE, &entry(100, 5000)));
+        assert_eq!(records.submit(NORMAL_MODE, entry(100, 5000)), Some(MAX_ENTRIES - 1));
+    }
+
+    #[test]
+    fn replay_references_survive_a_round_trip() {
+        let mut records = Records::new();
+        let mut saved = entry(120, 6000);
+        saved.replay = Some("replays/1700000000.json".to_string());
+        records.submit(NORMAL_MODE, saved);
+
+        let loaded = Records::deserialize_json(&records.serialize_json()).unwrap();
+        let entries = &loaded.mode(NORMAL_MODE).unwrap().entries;
+        assert_eq!(entries[0].replay.as_deref(), Some("replays/1700000000.json"));
+    }
+}
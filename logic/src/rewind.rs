@@ -0,0 +1,132 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::VecDeque;
+
+use nanoserde::{DeBin, SerBin};
+
+use crate::{field::Field, input::Inputs, stats::Stats};
+
+// the field and input timers are kept in their packed binary form, which is a small fraction of
+// the size of a cloned field; stats only change a few counters per tick so they stay as they are
+struct Snapshot {
+    state: Vec<u8>,
+    stats: Stats,
+    tick: u64,
+}
+
+pub struct Rewind {
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize,
+    rewound: bool,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Rewind {
+        let capacity = capacity.max(1);
+        Rewind {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+            rewound: false,
+        }
+    }
+    pub fn record(&mut self, tick: u64, field: &Field, inputs: &Inputs, stats: &Stats) {
+        while self.snapshots.len() >= self.capacity {
+            self.snapshots.pop_front();
+        }
+        let mut state = Vec::new();
+        field.ser_bin(&mut state);
+        inputs.ser_bin(&mut state);
+        self.snapshots.push_back(Snapshot { state, stats: stats.clone(), tick });
+    }
+    pub fn step_back(&mut self, tick: &mut u64, field: &mut Field, inputs: &mut Inputs, stats: &mut Stats) -> bool {
+        let Some(snapshot) = self.snapshots.pop_back() else {
+            return false;
+        };
+        let mut offset = 0;
+        let Ok(old_field) = Field::de_bin(&mut offset, &snapshot.state) else {
+            return false;
+        };
+        let Ok(old_inputs) = Inputs::de_bin(&mut offset, &snapshot.state) else {
+            return false;
+        };
+        *field = old_field;
+        *inputs = old_inputs;
+        *stats = snapshot.stats;
+        *tick = snapshot.tick;
+        self.rewound = true;
+        true
+    }
+    pub fn is_practice(&self) -> bool {
+        self.rewound
+    }
+    pub fn reset(&mut self) {
+        self.snapshots.clear();
+        self.rewound = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input::Input, testing::Player};
+
+    #[test]
+    fn step_back_restores_everything_recorded() {
+        let mut player = Player::new(Field::seeded(7));
+        let mut stats = Stats::new();
+        let mut rewind = Rewind::new(600);
+        let mut history = vec![];
+        for tick in 0..300 {
+            history.push((player.bytes(), stats.ticks, player.tick));
+            rewind.record(player.tick, &player.field, &player.inputs, &stats);
+            let down = if tick % 20 < 3 { vec![Input::Left, Input::Up] } else { vec![Input::CW] };
+            let locked = player.step(&down);
+            stats.update(&player.field, &player.inputs, locked);
+        }
+
+        while let Some((bytes, ticks, tick)) = history.pop() {
+            assert!(rewind.step_back(&mut player.tick, &mut player.field, &mut player.inputs, &mut stats));
+            assert_eq!(player.bytes(), bytes);
+            assert_eq!(stats.ticks, ticks);
+            assert_eq!(player.tick, tick);
+        }
+        assert!(!rewind.step_back(&mut player.tick, &mut player.field, &mut player.inputs, &mut stats));
+        assert!(rewind.is_practice());
+    }
+
+    #[test]
+    fn only_keeps_capacity_snapshots() {
+        let mut player = Player::new(Field::seeded(1));
+        let stats = Stats::new();
+        let mut rewind = Rewind::new(10);
+        for _ in 0..50 {
+            rewind.record(player.tick, &player.field, &player.inputs, &stats);
+            player.step(&[]);
+        }
+        let mut stats = stats;
+        let mut steps = 0;
+        while rewind.step_back(&mut player.tick, &mut player.field, &mut player.inputs, &mut stats) {
+            steps += 1;
+        }
+        assert_eq!(steps, 10);
+        assert_eq!(player.tick, 40);
+    }
+
+    #[test]
+    fn a_rewind_without_room_still_keeps_the_latest_snapshot() {
+        for capacity in [0, 1] {
+            let mut player = Player::new(Field::seeded(3));
+            let mut stats = Stats::new();
+            let mut rewind = Rewind::new(capacity);
+            for _ in 0..5 {
+                rewind.record(player.tick, &player.field, &player.inputs, &stats);
+                player.step(&[]);
+            }
+            assert!(rewind.step_back(&mut player.tick, &mut player.field, &mut player.inputs, &mut stats));
+            assert_eq!(player.tick, 4);
+            assert!(!rewind.step_back(&mut player.tick, &mut player.field, &mut player.inputs, &mut stats));
+        }
+    }
+}
@@ -0,0 +1,267 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+use crate::{field::Field, hooks::{Cubes, Silent, Sounds}, input::{Input, InputProvider, Inputs}, stats::Stats};
+
+#[derive(Clone, Copy, Debug)]
+pub struct RollbackConfig {
+    pub input_delay: u64,
+    pub max_rollback: u64,
+}
+
+impl Default for RollbackConfig {
+    fn default() -> RollbackConfig {
+        RollbackConfig::new()
+    }
+}
+
+impl RollbackConfig {
+    pub fn new() -> RollbackConfig {
+        RollbackConfig {
+            input_delay: 2,
+            max_rollback: 30,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum RemoteEvent {
+    Input { input: Input, up: bool },
+    Garbage { lines: u32 },
+}
+
+#[derive(Clone)]
+struct HeldInputs {
+    just_pressed: HashSet<Input>,
+    current: HashSet<Input>,
+}
+
+impl InputProvider for HeldInputs {
+    fn peek(&mut self) {
+    }
+    fn consume(&mut self) {
+        self.just_pressed.clear();
+    }
+    fn key_just_pressed(&self, input: Input) -> bool {
+        self.just_pressed.contains(&input)
+    }
+    fn key_down(&self, input: Input) -> bool {
+        self.current.contains(&input)
+    }
+}
+
+#[derive(Clone)]
+struct SavedState {
+    field: Field,
+    inputs: Inputs,
+    held: HeldInputs,
+    stats: Stats,
+}
+
+// states[0] is the field before simulating base_tick; remote keys are predicted to stay held
+// until the server says otherwise
+pub struct Rollback {
+    config: RollbackConfig,
+    states: VecDeque<SavedState>,
+    base_tick: u64,
+    confirmed_tick: u64,
+    simulated_tick: u64,
+    events: BTreeMap<u64, Vec<RemoteEvent>>,
+    rollback_from: Option<u64>,
+    rollbacks: u64,
+}
+
+impl Rollback {
+    pub fn new(field: Field, start_tick: u64, config: RollbackConfig) -> Rollback {
+        let mut states = VecDeque::new();
+        states.push_back(SavedState {
+            field,
+            inputs: Inputs::new(),
+            held: HeldInputs {
+                just_pressed: HashSet::new(),
+                current: HashSet::new(),
+            },
+            stats: Stats::new(),
+        });
+        Rollback {
+            config,
+            states,
+            base_tick: start_tick,
+            confirmed_tick: start_tick,
+            simulated_tick: start_tick,
+            events: BTreeMap::new(),
+            rollback_from: None,
+            rollbacks: 0,
+        }
+    }
+    pub fn field(&self) -> &Field {
+        &self.states.back().unwrap().field
+    }
+    pub fn stats(&self) -> &Stats {
+        &self.states.back().unwrap().stats
+    }
+    pub fn tick(&self) -> u64 {
+        self.base_tick + self.states.len() as u64 - 1
+    }
+    pub fn confirmed_tick(&self) -> u64 {
+        self.confirmed_tick
+    }
+    pub fn rollbacks(&self) -> u64 {
+        self.rollbacks
+    }
+    pub fn config(&self) -> RollbackConfig {
+        self.config
+    }
+    pub fn receive(&mut self, tick: u64, event: RemoteEvent) {
+        let tick = tick.max(self.base_tick);
+        self.events.entry(tick).or_default().push(event);
+        if tick < self.tick() {
+            self.rollback_from = Some(self.rollback_from.map(|it| it.min(tick)).unwrap_or(tick));
+        }
+    }
+    // every event for ticks up to and including `tick` has been received
+    pub fn confirm(&mut self, tick: u64) {
+        self.confirmed_tick = self.confirmed_tick.max(tick + 1);
+    }
+    pub fn advance(&mut self, target: u64, sounds: &mut dyn Sounds, cubes: &mut dyn Cubes) {
+        if let Some(tick) = self.rollback_from.take() {
+            self.states.truncate((tick - self.base_tick) as usize + 1);
+            self.rollbacks += 1;
+        }
+
+        let limit = target
+            .saturating_sub(self.config.input_delay)
+            .min(self.confirmed_tick + self.config.max_rollback);
+        while self.tick() < limit {
+            let tick = self.tick();
+            let mut state = self.states.back().unwrap().clone();
+            for event in self.events.get(&tick).into_iter().flatten() {
+                match *event {
+                RemoteEvent::Input { input, up: true } => {
+                    state.held.just_pressed.insert(input);
+                    state.held.current.insert(input);
+                }
+                RemoteEvent::Input { input, up: false } => {
+                    state.held.just_pressed.remove(&input);
+                    state.held.current.remove(&input);
+                }
+                RemoteEvent::Garbage { lines } => {
+                    state.field.garbage.queue(lines);
+                }
+                }
+            }
+            state.inputs.tick(tick, &mut state.held);
+            let locked = if tick >= self.simulated_tick {
+                self.simulated_tick = tick + 1;
+                state.field.update(&state.inputs, sounds, cubes)
+            } else {
+                state.field.update(&state.inputs, &mut Silent, &mut Silent)
+            };
+            state.stats.update(&state.field, &state.inputs, locked);
+            self.states.push_back(state);
+        }
+
+        while self.base_tick < self.confirmed_tick && self.states.len() > 1 {
+            self.states.pop_front();
+            self.base_tick += 1;
+        }
+        self.events = self.events.split_off(&self.base_tick);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nanoserde::{SerBin, SerJson};
+
+    use super::*;
+
+    const END: u64 = 240;
+
+    fn script() -> Vec<(u64, RemoteEvent)> {
+        let press = |tick, input| (tick, RemoteEvent::Input { input, up: true });
+        let release = |tick, input| (tick, RemoteEvent::Input { input, up: false });
+        vec![
+            press(3, Input::Left), release(9, Input::Left),
+            press(20, Input::CW), release(22, Input::CW),
+            press(30, Input::Up), release(31, Input::Up),
+            press(32, Input::Down), release(34, Input::Down),
+            (50, RemoteEvent::Garbage { lines: 2 }),
+            press(70, Input::Right), release(71, Input::Right),
+            press(90, Input::Up), release(91, Input::Up),
+            press(92, Input::Down), release(94, Input::Down),
+            (120, RemoteEvent::Garbage { lines: 1 }),
+        ]
+    }
+
+    fn config() -> RollbackConfig {
+        RollbackConfig { input_delay: 0, max_rollback: 30 }
+    }
+
+    fn in_order() -> Rollback {
+        let mut rollback = Rollback::new(Field::seeded(3), 0, config());
+        let script = script();
+        for tick in 0..END {
+            for (_, event) in script.iter().filter(|it| it.0 == tick) {
+                rollback.receive(tick, *event);
+            }
+            rollback.confirm(tick);
+            rollback.advance(tick + 1, &mut Silent, &mut Silent);
+        }
+        rollback
+    }
+
+    #[test]
+    fn late_inputs_resimulate_to_the_same_field() {
+        const LATENESS: u64 = 6;
+        let expected = in_order();
+        let mut rollback = Rollback::new(Field::seeded(3), 0, config());
+        let script = script();
+        for tick in 0..END {
+            for (at, event) in script.iter().filter(|it| it.0 + LATENESS == tick) {
+                rollback.receive(*at, *event);
+            }
+            if tick >= LATENESS {
+                rollback.confirm(tick - LATENESS);
+            }
+            rollback.advance(tick + 1, &mut Silent, &mut Silent);
+        }
+        for tick in END - LATENESS..END {
+            rollback.confirm(tick);
+        }
+        rollback.advance(END, &mut Silent, &mut Silent);
+
+        assert_eq!(rollback.tick(), expected.tick());
+        assert!(rollback.rollbacks() > 0);
+        assert_eq!(rollback.field().serialize_bin(), expected.field().serialize_bin());
+        assert_eq!(rollback.stats().serialize_json(), expected.stats().serialize_json());
+        assert!(expected.stats().pieces > 0);
+    }
+
+    #[test]
+    fn prediction_stops_at_max_rollback() {
+        let mut rollback = Rollback::new(Field::seeded(3), 0, RollbackConfig { input_delay: 0, max_rollback: 10 });
+        rollback.advance(100, &mut Silent, &mut Silent);
+        assert_eq!(rollback.tick(), 10);
+
+        rollback.confirm(40);
+        rollback.advance(100, &mut Silent, &mut Silent);
+        assert_eq!(rollback.tick(), 51);
+    }
+
+    #[test]
+    fn inputs_older_than_the_window_apply_at_the_oldest_state() {
+        let mut rollback = Rollback::new(Field::seeded(3), 0, config());
+        rollback.confirm(40);
+        rollback.advance(60, &mut Silent, &mut Silent);
+        let before = rollback.field().serialize_bin();
+
+        rollback.receive(5, RemoteEvent::Input { input: Input::Left, up: true });
+        rollback.advance(60, &mut Silent, &mut Silent);
+        assert_eq!(rollback.rollbacks(), 1);
+        assert_eq!(rollback.tick(), 60);
+        assert_ne!(rollback.field().serialize_bin(), before);
+    }
+}
@@ -0,0 +1,124 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use nanoserde::{DeBin, DeJson, SerBin, SerJson};
+
+use crate::{field::{Field, GameState}, garbage::Garbage, piece::Piece, randomizer::Randomizer, well::{Tile, WELL_COLS}};
+
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone)]
+pub struct WellRow {
+    pub row: u32,
+    pub tiles: [Option<Tile>; WELL_COLS],
+}
+
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone)]
+pub struct FieldDelta {
+    pub base_tick: u64,
+    pub rows: Vec<WellRow>,
+    pub next: Piece,
+    pub level: u32,
+    pub gravity_override: Option<i32>,
+    pub garbage: Garbage,
+    pub state: GameState,
+    pub randomizer: Randomizer,
+}
+
+#[derive(SerJson, SerBin, DeJson, DeBin, Clone)]
+pub enum Snapshot {
+    Full { field: Box<Field> },
+    Delta { delta: FieldDelta },
+}
+
+impl FieldDelta {
+    pub fn between(base_tick: u64, base: &Field, current: &Field) -> FieldDelta {
+        let rows = base.well.blocks.iter().zip(current.well.blocks.iter()).enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(row, (_, new))| WellRow { row: row as u32, tiles: *new })
+            .collect();
+
+        FieldDelta {
+            base_tick,
+            rows,
+            next: current.next,
+            level: current.level,
+            gravity_override: current.gravity_override,
+            garbage: current.garbage.clone(),
+            state: current.state.clone(),
+            randomizer: current.randomizer.clone(),
+        }
+    }
+    pub fn apply(&self, base: &Field) -> Field {
+        let mut well = base.well.clone();
+        for row in &self.rows {
+            if let Some(tiles) = well.blocks.get_mut(row.row as usize) {
+                *tiles = row.tiles;
+            }
+        }
+        Field {
+            randomizer: self.randomizer.clone(),
+            well,
+            next: self.next,
+            level: self.level,
+            gravity_override: self.gravity_override,
+            garbage: self.garbage.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn apply(&self, base: Option<(u64, &Field)>) -> Option<Field> {
+        match self {
+        Snapshot::Full { field } => Some(field.as_ref().clone()),
+        Snapshot::Delta { delta } => match base {
+            Some((tick, field)) if tick == delta.base_tick => Some(delta.apply(field)),
+            _ => None,
+        },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input::Input, testing::Player};
+
+    fn stacked_field() -> Field {
+        let mut player = Player::new(Field::seeded(7));
+        for tick in 0..300 {
+            player.step(if tick % 20 < 3 { &[Input::Left, Input::Up] } else { &[Input::Down] });
+        }
+        player.field
+    }
+
+    #[test]
+    fn a_delta_applied_to_its_base_gives_the_current_field() {
+        let base = Field::seeded(7);
+        let current = stacked_field();
+        let delta = FieldDelta::between(120, &base, &current);
+        assert!(!delta.rows.is_empty());
+        assert_eq!(delta.apply(&base).serialize_bin(), current.serialize_bin());
+
+        let snapshot = Snapshot::Delta { delta };
+        let applied = snapshot.apply(Some((120, &base))).unwrap();
+        assert_eq!(applied.serialize_bin(), current.serialize_bin());
+    }
+
+    #[test]
+    fn a_delta_without_its_base_is_refused() {
+        let base = Field::seeded(7);
+        let snapshot = Snapshot::Delta { delta: FieldDelta::between(120, &base, &stacked_field()) };
+        assert!(snapshot.apply(Some((119, &base))).is_none());
+        assert!(snapshot.apply(Some((121, &base))).is_none());
+        assert!(snapshot.apply(None).is_none());
+    }
+
+    #[test]
+    fn a_full_snapshot_needs_no_base() {
+        let current = stacked_field();
+        let snapshot = Snapshot::Full { field: Box::new(current.clone()) };
+        assert_eq!(snapshot.apply(None).unwrap().serialize_bin(), current.serialize_bin());
+        assert_eq!(snapshot.apply(Some((3, &Field::seeded(1)))).unwrap().serialize_bin(), current.serialize_bin());
+    }
+}
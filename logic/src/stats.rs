@@ -0,0 +1,188 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use nanoserde::{DeJson, SerJson};
+
+use crate::{field::{Field, GameState, Locked}, finesse, input::{Inputs, INPUTS}, well::WELL_ROWS};
+
+pub const TWENTY_G: i32 = 5120;
+
+#[derive(SerJson, DeJson, Clone, Debug, Default)]
+pub struct Stats {
+    pub ticks: u64,
+    pub pieces: u32,
+    pub keys: u32,
+    pub clears: [u32; 4],
+    pub combo: u32,
+    pub max_combo: u32,
+    pub highest_stack: u32,
+    pub ticks_at_20g: u64,
+    pub piece_presses: u32,
+    pub finesse_faults: u32,
+    pub section_ticks: Vec<u64>,
+}
+
+#[derive(SerJson, DeJson, Clone, Debug)]
+pub struct GameResult {
+    pub level: u32,
+    pub ticks: u64,
+    pub stats: Stats,
+}
+
+fn stack_height(field: &Field) -> u32 {
+    field.well.blocks.iter()
+        .position(|row| row.iter().any(|it| it.is_some()))
+        .map(|top| (WELL_ROWS - top) as u32)
+        .unwrap_or(0)
+}
+
+pub fn format_ticks(ticks: u64) -> String {
+    format!("{}:{:02}.{:02}", ticks / 3600, ticks / 60 % 60, ticks % 60 * 100 / 60)
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+    pub fn update(&mut self, field: &Field, inputs: &Inputs, locked: Option<Locked>) {
+        if let GameState::GameOver { .. } = field.state {
+            return;
+        }
+
+        self.ticks += 1;
+        while field.level / 100 > self.section_ticks.len() as u32 {
+            self.section_ticks.push(self.ticks);
+        }
+        if field.gravity() >= TWENTY_G {
+            self.ticks_at_20g += 1;
+        }
+        self.keys += INPUTS.iter().filter(|it| inputs.key_just_pressed(**it)).count() as u32;
+        self.piece_presses += finesse::presses(inputs);
+
+        if let Some(locked) = locked {
+            self.pieces += 1;
+            if finesse::is_fault(&field.well, &locked.piece, self.piece_presses) {
+                self.finesse_faults += 1;
+            }
+            self.piece_presses = 0;
+            if locked.lines > 0 {
+                self.clears[(locked.lines.min(4) - 1) as usize] += 1;
+                self.combo += 1;
+                self.max_combo = self.max_combo.max(self.combo);
+            } else {
+                self.combo = 0;
+            }
+            self.highest_stack = self.highest_stack.max(stack_height(field));
+        }
+    }
+    pub fn lines(&self) -> u32 {
+        self.clears.iter().enumerate().map(|(idx, count)| (idx as u32 + 1) * count).sum()
+    }
+    pub fn pieces_per_second(&self) -> f32 {
+        if self.ticks == 0 {
+            0.
+        } else {
+            self.pieces as f32 / (self.ticks as f32 / 60.)
+        }
+    }
+    pub fn keys_per_piece(&self) -> f32 {
+        if self.pieces == 0 {
+            0.
+        } else {
+            self.keys as f32 / self.pieces as f32
+        }
+    }
+    pub fn summary(&self) -> Vec<String> {
+        vec![
+            format!("Time {}", format_ticks(self.ticks)),
+            format!("PPS {:.2}", self.pieces_per_second()),
+            format!("KPP {:.2}", self.keys_per_piece()),
+            format!("Finesse faults {}", self.finesse_faults),
+            format!("Singles {}", self.clears[0]),
+            format!("Doubles {}", self.clears[1]),
+            format!("Triples {}", self.clears[2]),
+            format!("Tetrises {}", self.clears[3]),
+            format!("Max combo {}", self.max_combo),
+            format!("Highest stack {}", self.highest_stack),
+            format!("20G {}", format_ticks(self.ticks_at_20g)),
+        ]
+    }
+}
+
+impl GameResult {
+    pub fn new(field: &Field, stats: &Stats) -> GameResult {
+        GameResult {
+            level: field.level,
+            ticks: stats.ticks,
+            stats: stats.clone(),
+        }
+    }
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![
+            "Results".to_string(),
+            format!("Level {}", self.level),
+            format!("Lines {}", self.stats.lines()),
+        ];
+        lines.extend(self.stats.summary());
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input::Input, piece::Piece, testing::Held, well::Block};
+
+    fn locked(lines: u32) -> Option<Locked> {
+        Some(Locked { piece: Piece::new(Block::Red), lines })
+    }
+
+    #[test]
+    fn pieces_and_keys_per_second() {
+        let field = Field::seeded(3);
+        let mut inputs = Inputs::new();
+        let mut held = Held::new();
+        let mut stats = Stats::new();
+        for tick in 0..120 {
+            held.hold(if tick % 10 == 0 { &[Input::Left] } else { &[] });
+            inputs.tick(tick, &mut held);
+            stats.update(&field, &inputs, if tick % 30 == 29 { locked(0) } else { None });
+        }
+        assert_eq!(stats.ticks, 120);
+        assert_eq!(stats.pieces, 4);
+        assert_eq!(stats.keys, 12);
+        assert_eq!(stats.pieces_per_second(), 2.);
+        assert_eq!(stats.keys_per_piece(), 3.);
+    }
+
+    #[test]
+    fn clears_and_combos() {
+        let field = Field::seeded(3);
+        let inputs = Inputs::new();
+        let mut stats = Stats::new();
+        for lines in [1, 2, 0, 4, 3, 3, 0, 1] {
+            stats.update(&field, &inputs, locked(lines));
+        }
+        assert_eq!(stats.clears, [2, 1, 2, 1]);
+        assert_eq!(stats.lines(), 14);
+        assert_eq!(stats.max_combo, 3);
+        assert_eq!(stats.combo, 1);
+    }
+
+    #[test]
+    fn time_at_twenty_g() {
+        let mut field = Field::seeded(3);
+        let inputs = Inputs::new();
+        let mut stats = Stats::new();
+        for tick in 0..90 {
+            field.gravity_override = if tick < 30 { None } else { Some(TWENTY_G) };
+            stats.update(&field, &inputs, None);
+        }
+        field.state = GameState::GameOver { ticks_remaining: 10 };
+        stats.update(&field, &inputs, None);
+        assert_eq!(stats.ticks, 90);
+        assert_eq!(stats.ticks_at_20g, 60);
+        assert_eq!(format_ticks(stats.ticks_at_20g), "0:01.00");
+    }
+}
@@ -0,0 +1,167 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use nanoserde::SerBin;
+
+use crate::{field::{Field, Locked}, garbage::AttackTable, hooks::Silent, input::{Input, InputProvider, Inputs}, proto::{ClientToServer, PlayerRating, ProtocolError, RoomInfo, ServerToClient, PROTOCOL_VERSION}, recording::MatchRecord, snapshot::{FieldDelta, Snapshot}, wire::WireFormat};
+
+// holds down whatever it's told to for the next tick
+pub struct Held {
+    down: Vec<Input>,
+    before: Vec<Input>,
+}
+
+impl Held {
+    pub fn new() -> Held {
+        Held { down: vec![], before: vec![] }
+    }
+    pub fn hold(&mut self, down: &[Input]) {
+        self.down = down.to_vec();
+    }
+}
+
+impl InputProvider for Held {
+    fn peek(&mut self) {}
+    fn consume(&mut self) {
+        self.before = self.down.clone();
+    }
+    fn key_just_pressed(&self, input: Input) -> bool {
+        self.down.contains(&input) && !self.before.contains(&input)
+    }
+    fn key_down(&self, input: Input) -> bool {
+        self.down.contains(&input)
+    }
+}
+
+// a field plus everything needed to drive it a tick at a time
+pub struct Player {
+    pub field: Field,
+    pub inputs: Inputs,
+    pub held: Held,
+    pub tick: u64,
+}
+
+impl Player {
+    pub fn new(field: Field) -> Player {
+        Player { field, inputs: Inputs::new(), held: Held::new(), tick: 0 }
+    }
+    pub fn step(&mut self, down: &[Input]) -> Option<Locked> {
+        self.held.hold(down);
+        self.tick += 1;
+        self.inputs.tick(self.tick, &mut self.held);
+        self.field.update(&self.inputs, &mut Silent, &mut Silent)
+    }
+    pub fn bytes(&self) -> Vec<u8> {
+        self.field.serialize_bin()
+    }
+}
+
+// a field a few pieces into a game, so it has blocks in the well and garbage pending
+pub fn played_field() -> Field {
+    let mut player = Player::new(Field::seeded(7));
+    player.field.garbage.queue(2);
+    for tick in 0..120 {
+        player.step(if tick % 20 == 0 { &[Input::Down] } else { &[] });
+    }
+    player.field
+}
+
+fn room() -> RoomInfo {
+    RoomInfo { name: "lobby".to_string(), players: 2, capacity: 4, spectators: 1, playing: false }
+}
+
+fn rating() -> PlayerRating {
+    PlayerRating { player: "janet".to_string(), rating: 1612.5, deviation: 87.25, games: 12 }
+}
+
+fn record() -> MatchRecord {
+    let mut record = MatchRecord::new("lobby", 1_700_000_000, 99, AttackTable::new(), true, vec![(1, Some("janet".to_string())), (2, None)]);
+    record.id = 4;
+    record.record_input(1, 10, Input::Left, true);
+    record.record_input(1, 14, Input::Left, false);
+    record.record_garbage(2, 30, 3);
+    record.winner = Some(1);
+    record.ticks = 600;
+    record
+}
+
+// one of every message, in declaration order
+pub fn client_messages() -> Vec<ClientToServer> {
+    vec![
+        ClientToServer::Hello { version: PROTOCOL_VERSION, format: WireFormat::Binary },
+        ClientToServer::Join { token: Some("00000000000000010000000000000002".to_string()) },
+        ClientToServer::Input { input: Input::CW, up: true, tick: 1234 },
+        ClientToServer::CreateRoom { name: "lobby".to_string(), capacity: 4, attack_table: Some(AttackTable::new()) },
+        ClientToServer::JoinRoom { name: "lobby".to_string() },
+        ClientToServer::LeaveRoom {},
+        ClientToServer::Spectate { name: "lobby".to_string() },
+        ClientToServer::ListRooms {},
+        ClientToServer::Ready { ready: true },
+        ClientToServer::Ping { nonce: 5 },
+        ClientToServer::Pong { nonce: 6 },
+        ClientToServer::TimeSync { client_time: 123_456_789 },
+        ClientToServer::Identify { player: "janet".to_string(), key: Some("0123456789abcdef0123456789abcdef".to_string()) },
+        ClientToServer::QueueRanked {},
+        ClientToServer::LeaveQueue {},
+        ClientToServer::GetRating { player: "janet".to_string() },
+        ClientToServer::ListMatches {},
+        ClientToServer::GetMatch { id: 4 },
+        ClientToServer::Chat { message: "gg".to_string() },
+    ]
+}
+
+pub fn protocol_errors() -> Vec<ProtocolError> {
+    vec![
+        ProtocolError::InvalidUtf8 {},
+        ProtocolError::Malformed { message: "expected {".to_string() },
+        ProtocolError::HandshakeRequired {},
+        ProtocolError::VersionMismatch { server: PROTOCOL_VERSION, client: 1 },
+        ProtocolError::Timeout {},
+        ProtocolError::RateLimited {},
+        ProtocolError::MessageTooLarge { size: 70000, limit: 65536 },
+        ProtocolError::Kicked {},
+        ProtocolError::ShuttingDown {},
+    ]
+}
+
+// one of every message, in declaration order, with an error reply for every protocol error
+pub fn server_messages() -> Vec<ServerToClient> {
+    let field = played_field();
+    let mut messages = vec![ServerToClient::Hello { version: PROTOCOL_VERSION, format: WireFormat::Json }];
+    messages.extend(protocol_errors().into_iter().map(|error| ServerToClient::Error { error }));
+    messages.extend([
+        ServerToClient::Join { client_id: 2, field: field.clone() },
+        ServerToClient::Leave { client_id: 2 },
+        ServerToClient::Input { client_id: 2, input: Input::Right, up: false, tick: 77 },
+        ServerToClient::Tick { client_id: 2, tick: 78 },
+        ServerToClient::RoomList { rooms: vec![room()] },
+        ServerToClient::RoomJoined { room: room() },
+        ServerToClient::RoomLeft {},
+        ServerToClient::Spectating { room: room() },
+        ServerToClient::Snapshot { client_id: 2, tick: 120, snapshot: Snapshot::Full { field: Box::new(field.clone()) } },
+        ServerToClient::Snapshot { client_id: 2, tick: 126, snapshot: Snapshot::Delta { delta: FieldDelta::between(120, &Field::seeded(7), &field) } },
+        ServerToClient::RoomError { message: "room is full".to_string() },
+        ServerToClient::Ready { client_id: 2, ready: true },
+        ServerToClient::Countdown { start_tick: 400, now: 220 },
+        ServerToClient::CountdownCancelled {},
+        ServerToClient::Start { seed: 99, start_tick: 400 },
+        ServerToClient::Garbage { client_id: 1, lines: 4, tick: 88 },
+        ServerToClient::RoomFinished { winner: Some(1) },
+        ServerToClient::Welcome { client_id: 1, token: "00000000000000010000000000000002".to_string(), resumed: false },
+        ServerToClient::Ping { nonce: 5 },
+        ServerToClient::Pong { nonce: 6 },
+        ServerToClient::TimeSync { client_time: 123_456_789, server_time: 5_000_000, tick: 300 },
+        ServerToClient::Queued { rating: rating() },
+        ServerToClient::QueueLeft {},
+        ServerToClient::MatchFound { room: "ranked 1".to_string(), opponent: rating() },
+        ServerToClient::Rating { rating: rating() },
+        ServerToClient::RatingChanged { rating: rating(), change: -12.5 },
+        ServerToClient::MatchList { matches: vec![record().summary()] },
+        ServerToClient::MatchRecording { record: record() },
+        ServerToClient::Announcement { message: "restarting soon".to_string() },
+        ServerToClient::Chat { client_id: 1, name: "janet".to_string(), message: "gg".to_string() },
+        ServerToClient::Identified { player: "janet".to_string(), key: "0123456789abcdef0123456789abcdef".to_string() },
+    ]);
+    messages
+}
@@ -2,9 +2,9 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use nanoserde::{DeJson, SerJson};
+use nanoserde::{DeBin, DeJson, SerBin, SerJson};
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, SerJson, DeJson)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, SerJson, SerBin, DeJson, DeBin)]
 pub enum Block {
     Red,
     Orange,
@@ -13,10 +13,11 @@ pub enum Block {
     Cyan,
     Blue,
     Purple,
+    Gray,
 }
 
 #[repr(transparent)]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, SerJson, DeJson)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, SerJson, SerBin, DeJson, DeBin)]
 pub struct BlockDirections(u8);
 
 impl BlockDirections {
@@ -64,10 +65,12 @@ impl BlockDirections {
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, SerJson, DeJson)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, SerJson, SerBin, DeJson, DeBin)]
 pub struct Tile {
     pub color: Block,
     pub directions: BlockDirections,
+    #[nserde(default)]
+    pub jewel: bool,
 }
 
 pub const WELL_COLS: usize = 10;
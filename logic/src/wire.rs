@@ -0,0 +1,733 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use nanoserde::{DeBin, DeBinErr, DeJson, SerBin, SerJson};
+
+use crate::{field::{Field, GameState}, garbage::{AttackTable, Garbage}, input::Input, proto::{ClientToServer, PlayerRating, ProtocolError, RoomInfo, ServerToClient}, randomizer::Randomizer, recording::{MatchRecord, MatchSummary, RecordedPlayer}, snapshot::{FieldDelta, Snapshot}, well::{Block, BlockDirections, Tile, Well, WELL_COLS, WELL_ROWS}};
+
+#[derive(SerJson, DeJson, SerBin, DeBin, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    Binary,
+}
+
+const INPUT_CODES: [Input; 6] = [Input::Up, Input::Down, Input::Left, Input::Right, Input::CW, Input::CCW];
+const COLOR_CODES: [Block; 7] = [Block::Red, Block::Orange, Block::Yellow, Block::Green, Block::Cyan, Block::Blue, Block::Purple];
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    count: u32,
+}
+
+struct BitReader {
+    acc: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { bytes: vec![], acc: 0, count: 0 }
+    }
+    fn write(&mut self, value: u8, bits: u32) {
+        self.acc |= (value as u32 & ((1 << bits) - 1)) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.count -= 8;
+        }
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+impl BitReader {
+    fn new() -> BitReader {
+        BitReader { acc: 0, count: 0 }
+    }
+    fn read(&mut self, bits: u32, offset: &mut usize, bytes: &[u8]) -> Result<u8, DeBinErr> {
+        while self.count < bits {
+            self.acc |= (u8::de_bin(offset, bytes)? as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.acc & ((1 << bits) - 1);
+        self.acc >>= bits;
+        self.count -= bits;
+        Ok(value as u8)
+    }
+}
+
+pub fn ser_varint(mut value: u64, output: &mut Vec<u8>) {
+    while value >= 0x80 {
+        output.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+pub fn de_varint(offset: &mut usize, bytes: &[u8]) -> Result<u64, DeBinErr> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = u8::de_bin(offset, bytes)?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok(value)
+}
+
+fn color_code(tile: &Option<Tile>) -> u8 {
+    match tile {
+    Some(tile) => COLOR_CODES.iter().position(|it| *it == tile.color).map(|it| it as u8 + 1).unwrap_or(0),
+    None => 0,
+    }
+}
+
+fn ser_cells(cells: impl Iterator<Item = usize>, output: &mut Vec<u8>) {
+    let cells = cells.collect::<Vec<_>>();
+    ser_varint(cells.len() as u64, output);
+    for cell in cells {
+        ser_varint(cell as u64, output);
+    }
+}
+
+fn de_cells(offset: &mut usize, bytes: &[u8]) -> Result<Vec<usize>, DeBinErr> {
+    let count = de_varint(offset, bytes)?;
+    let mut cells = vec![];
+    for _ in 0..count {
+        let cell = de_varint(offset, bytes)? as usize;
+        if cell < WELL_ROWS * WELL_COLS {
+            cells.push(cell);
+        }
+    }
+    Ok(cells)
+}
+
+// 3 bits of colour per cell, with gray garbage and jewels listed separately,
+// followed by 4 bits of connection directions for each occupied cell
+impl SerBin for Well {
+    fn ser_bin(&self, output: &mut Vec<u8>) {
+        let cells = self.blocks.iter().flatten().collect::<Vec<_>>();
+        ser_cells(cells.iter().enumerate().filter(|(_, it)| it.map(|tile| tile.color == Block::Gray).unwrap_or(false)).map(|(idx, _)| idx), output);
+        ser_cells(cells.iter().enumerate().filter(|(_, it)| it.map(|tile| tile.jewel).unwrap_or(false)).map(|(idx, _)| idx), output);
+
+        let mut bits = BitWriter::new();
+        for cell in &cells {
+            bits.write(color_code(cell), 3);
+        }
+        for tile in cells.iter().copied().flatten() {
+            bits.write(tile.directions.bits(), 4);
+        }
+        output.extend(bits.finish());
+    }
+}
+
+impl DeBin for Well {
+    fn de_bin(offset: &mut usize, bytes: &[u8]) -> Result<Well, DeBinErr> {
+        let gray = de_cells(offset, bytes)?;
+        let jewels = de_cells(offset, bytes)?;
+
+        let mut bits = BitReader::new();
+        let mut colors = vec![];
+        for idx in 0..WELL_ROWS * WELL_COLS {
+            let code = bits.read(3, offset, bytes)?;
+            colors.push(match code {
+            0 if gray.contains(&idx) => Some(Block::Gray),
+            0 => None,
+            code => Some(COLOR_CODES[code as usize - 1]),
+            });
+        }
+
+        let mut well = Well::new();
+        for (idx, color) in colors.into_iter().enumerate() {
+            if let Some(color) = color {
+                let dirs = bits.read(4, offset, bytes)?;
+                well.blocks[idx / WELL_COLS][idx % WELL_COLS] = Some(Tile {
+                    color,
+                    directions: BlockDirections::new(dirs & 0b1000 != 0, dirs & 0b0100 != 0, dirs & 0b0010 != 0, dirs & 0b0001 != 0),
+                    jewel: jewels.contains(&idx),
+                });
+            }
+        }
+        Ok(well)
+    }
+}
+
+fn pack_input(input: Input, up: bool) -> u8 {
+    INPUT_CODES.iter().position(|it| *it == input).unwrap_or(0) as u8 | if up { 0x80 } else { 0 }
+}
+
+fn unpack_input(byte: u8) -> Result<(Input, bool), ProtocolError> {
+    INPUT_CODES.get((byte & 0x7f) as usize)
+        .map(|input| (*input, byte & 0x80 != 0))
+        .ok_or_else(|| ProtocolError::Malformed { message: format!("unknown input {}", byte & 0x7f) })
+}
+
+fn de<T: DeBin>(offset: &mut usize, bytes: &[u8]) -> Result<T, ProtocolError> {
+    T::de_bin(offset, bytes).map_err(|e| ProtocolError::Malformed { message: format!("{:?}", e) })
+}
+
+fn varint(offset: &mut usize, bytes: &[u8]) -> Result<u64, ProtocolError> {
+    de_varint(offset, bytes).map_err(|e| ProtocolError::Malformed { message: format!("{:?}", e) })
+}
+
+fn unknown_tag(tag: u8) -> ProtocolError {
+    ProtocolError::Malformed { message: format!("unknown message tag {}", tag) }
+}
+
+fn finished<T>(msg: T, offset: usize, bytes: &[u8]) -> Result<T, ProtocolError> {
+    if offset == bytes.len() {
+        Ok(msg)
+    } else {
+        Err(ProtocolError::Malformed { message: format!("{} trailing bytes", bytes.len() - offset) })
+    }
+}
+
+// nanoserde trusts the lengths it reads and will happily try to allocate whatever a corrupt
+// prefix says, so anything with a length in it is decoded with these instead; item_size is the
+// fewest bytes an item can take, which bounds a list by what's left of the message
+fn length(o: &mut usize, bytes: &[u8], item_size: usize) -> Result<usize, ProtocolError> {
+    let len: u64 = de(o, bytes)?;
+    if len > ((bytes.len() - *o) / item_size) as u64 {
+        return Err(ProtocolError::Malformed { message: format!("length {} runs past the end of the message", len) });
+    }
+    Ok(len as usize)
+}
+
+fn string(o: &mut usize, bytes: &[u8]) -> Result<String, ProtocolError> {
+    let len = length(o, bytes, 1)?;
+    let text = std::str::from_utf8(&bytes[*o..*o + len]).map_err(|_| ProtocolError::InvalidUtf8 {})?;
+    *o += len;
+    Ok(text.to_string())
+}
+
+fn optional<T>(o: &mut usize, bytes: &[u8], value: fn(&mut usize, &[u8]) -> Result<T, ProtocolError>) -> Result<Option<T>, ProtocolError> {
+    match de::<u8>(o, bytes)? {
+    0 => Ok(None),
+    1 => Ok(Some(value(o, bytes)?)),
+    tag => Err(ProtocolError::Malformed { message: format!("invalid option tag {}", tag) }),
+    }
+}
+
+fn list<T>(o: &mut usize, bytes: &[u8], item_size: usize, item: fn(&mut usize, &[u8]) -> Result<T, ProtocolError>) -> Result<Vec<T>, ProtocolError> {
+    let len = length(o, bytes, item_size)?;
+    let mut items = Vec::with_capacity(len);
+    for _ in 0..len {
+        items.push(item(o, bytes)?);
+    }
+    Ok(items)
+}
+
+fn unknown_variant<T>(name: &str, tag: u16) -> Result<T, ProtocolError> {
+    Err(ProtocolError::Malformed { message: format!("unknown {} variant {}", name, tag) })
+}
+
+fn attack_table(o: &mut usize, bytes: &[u8]) -> Result<AttackTable, ProtocolError> {
+    Ok(AttackTable { lines: de(o, bytes)?, combo: list(o, bytes, 4, de)? })
+}
+
+fn protocol_error(o: &mut usize, bytes: &[u8]) -> Result<ProtocolError, ProtocolError> {
+    Ok(match de::<u16>(o, bytes)? {
+    0 => ProtocolError::InvalidUtf8 {},
+    1 => ProtocolError::Malformed { message: string(o, bytes)? },
+    2 => ProtocolError::HandshakeRequired {},
+    3 => ProtocolError::VersionMismatch { server: de(o, bytes)?, client: de(o, bytes)? },
+    4 => ProtocolError::Timeout {},
+    5 => ProtocolError::RateLimited {},
+    6 => ProtocolError::MessageTooLarge { size: de(o, bytes)?, limit: de(o, bytes)? },
+    7 => ProtocolError::Kicked {},
+    8 => ProtocolError::ShuttingDown {},
+    tag => return unknown_variant("error", tag),
+    })
+}
+
+fn room_info(o: &mut usize, bytes: &[u8]) -> Result<RoomInfo, ProtocolError> {
+    Ok(RoomInfo { name: string(o, bytes)?, players: de(o, bytes)?, capacity: de(o, bytes)?, spectators: de(o, bytes)?, playing: de(o, bytes)? })
+}
+
+fn player_rating(o: &mut usize, bytes: &[u8]) -> Result<PlayerRating, ProtocolError> {
+    Ok(PlayerRating { player: string(o, bytes)?, rating: de(o, bytes)?, deviation: de(o, bytes)?, games: de(o, bytes)? })
+}
+
+fn randomizer(o: &mut usize, bytes: &[u8]) -> Result<Randomizer, ProtocolError> {
+    Ok(match de::<u16>(o, bytes)? {
+    0 => Randomizer::TTATGM2P { seed: de(o, bytes)?, history: de(o, bytes)? },
+    1 => Randomizer::Sequence { pieces: list(o, bytes, 2, de)?, index: de(o, bytes)? },
+    tag => return unknown_variant("randomizer", tag),
+    })
+}
+
+fn garbage(o: &mut usize, bytes: &[u8]) -> Result<Garbage, ProtocolError> {
+    Ok(Garbage { pending: list(o, bytes, 4, de)?, seed: de(o, bytes)?, combo: de(o, bytes)? })
+}
+
+fn game_state(o: &mut usize, bytes: &[u8]) -> Result<GameState, ProtocolError> {
+    Ok(match de::<u16>(o, bytes)? {
+    0 => GameState::ActivePiece { piece: de(o, bytes)? },
+    1 => GameState::ClearDelay { ticks_remaining: de(o, bytes)?, rows_to_lower: list(o, bytes, 4, de)? },
+    2 => GameState::PlaceDelay { ticks_remaining: de(o, bytes)? },
+    3 => GameState::GameOver { ticks_remaining: de(o, bytes)? },
+    tag => return unknown_variant("game state", tag),
+    })
+}
+
+fn field(o: &mut usize, bytes: &[u8]) -> Result<Field, ProtocolError> {
+    Ok(Field {
+        randomizer: randomizer(o, bytes)?,
+        well: de(o, bytes)?,
+        next: de(o, bytes)?,
+        level: de(o, bytes)?,
+        gravity_override: de(o, bytes)?,
+        garbage: garbage(o, bytes)?,
+        state: game_state(o, bytes)?,
+    })
+}
+
+fn snapshot(o: &mut usize, bytes: &[u8]) -> Result<Snapshot, ProtocolError> {
+    Ok(match de::<u16>(o, bytes)? {
+    0 => Snapshot::Full { field: Box::new(field(o, bytes)?) },
+    1 => Snapshot::Delta { delta: FieldDelta {
+        base_tick: de(o, bytes)?,
+        rows: list(o, bytes, 4 + WELL_COLS, de)?,
+        next: de(o, bytes)?,
+        level: de(o, bytes)?,
+        gravity_override: de(o, bytes)?,
+        garbage: garbage(o, bytes)?,
+        state: game_state(o, bytes)?,
+        randomizer: randomizer(o, bytes)?,
+    } },
+    tag => return unknown_variant("snapshot", tag),
+    })
+}
+
+fn recorded_player(o: &mut usize, bytes: &[u8]) -> Result<RecordedPlayer, ProtocolError> {
+    Ok(RecordedPlayer {
+        client_id: de(o, bytes)?,
+        player: optional(o, bytes, string)?,
+        inputs: list(o, bytes, 11, de)?,
+        garbage: list(o, bytes, 12, de)?,
+    })
+}
+
+fn match_record(o: &mut usize, bytes: &[u8]) -> Result<MatchRecord, ProtocolError> {
+    Ok(MatchRecord {
+        id: de(o, bytes)?,
+        room: string(o, bytes)?,
+        date: de(o, bytes)?,
+        seed: de(o, bytes)?,
+        attack_table: attack_table(o, bytes)?,
+        ranked: de(o, bytes)?,
+        players: list(o, bytes, 21, recorded_player)?,
+        winner: de(o, bytes)?,
+        ticks: de(o, bytes)?,
+    })
+}
+
+fn match_summary(o: &mut usize, bytes: &[u8]) -> Result<MatchSummary, ProtocolError> {
+    Ok(MatchSummary {
+        id: de(o, bytes)?,
+        room: string(o, bytes)?,
+        date: de(o, bytes)?,
+        ranked: de(o, bytes)?,
+        players: list(o, bytes, 8, string)?,
+        winner: optional(o, bytes, string)?,
+        ticks: de(o, bytes)?,
+    })
+}
+
+pub fn ser_client(msg: &ClientToServer, output: &mut Vec<u8>) {
+    match msg {
+    ClientToServer::Hello { version, format } => {
+        output.push(0);
+        version.ser_bin(output);
+        format.ser_bin(output);
+    }
+    ClientToServer::Join { token } => {
+        output.push(1);
+        token.ser_bin(output);
+    }
+    ClientToServer::Input { input, up, tick } => {
+        output.push(2);
+        output.push(pack_input(*input, *up));
+        ser_varint(*tick, output);
+    }
+    ClientToServer::CreateRoom { name, capacity, attack_table } => {
+        output.push(3);
+        name.ser_bin(output);
+        capacity.ser_bin(output);
+        attack_table.ser_bin(output);
+    }
+    ClientToServer::JoinRoom { name } => {
+        output.push(4);
+        name.ser_bin(output);
+    }
+    ClientToServer::LeaveRoom {} => output.push(5),
+    ClientToServer::Spectate { name } => {
+        output.push(6);
+        name.ser_bin(output);
+    }
+    ClientToServer::ListRooms {} => output.push(7),
+    ClientToServer::Ready { ready } => {
+        output.push(8);
+        ready.ser_bin(output);
+    }
+    ClientToServer::Ping { nonce } => {
+        output.push(9);
+        nonce.ser_bin(output);
+    }
+    ClientToServer::Pong { nonce } => {
+        output.push(10);
+        nonce.ser_bin(output);
+    }
+    ClientToServer::TimeSync { client_time } => {
+        output.push(11);
+        ser_varint(*client_time, output);
+    }
+    ClientToServer::Identify { player, key } => {
+        output.push(12);
+        player.ser_bin(output);
+        key.ser_bin(output);
+    }
+    ClientToServer::QueueRanked {} => output.push(13),
+    ClientToServer::LeaveQueue {} => output.push(14),
+    ClientToServer::GetRating { player } => {
+        output.push(15);
+        player.ser_bin(output);
+    }
+    ClientToServer::ListMatches {} => output.push(16),
+    ClientToServer::GetMatch { id } => {
+        output.push(17);
+        ser_varint(*id, output);
+    }
+    ClientToServer::Chat { message } => {
+        output.push(18);
+        message.ser_bin(output);
+    }
+    }
+}
+
+pub fn de_client(bytes: &[u8]) -> Result<ClientToServer, ProtocolError> {
+    let o = &mut 0;
+    let tag: u8 = de(o, bytes)?;
+    let msg = match tag {
+    0 => ClientToServer::Hello { version: de(o, bytes)?, format: de(o, bytes)? },
+    1 => ClientToServer::Join { token: optional(o, bytes, string)? },
+    2 => {
+        let (input, up) = unpack_input(de(o, bytes)?)?;
+        ClientToServer::Input { input, up, tick: varint(o, bytes)? }
+    }
+    3 => ClientToServer::CreateRoom { name: string(o, bytes)?, capacity: de(o, bytes)?, attack_table: optional(o, bytes, attack_table)? },
+    4 => ClientToServer::JoinRoom { name: string(o, bytes)? },
+    5 => ClientToServer::LeaveRoom {},
+    6 => ClientToServer::Spectate { name: string(o, bytes)? },
+    7 => ClientToServer::ListRooms {},
+    8 => ClientToServer::Ready { ready: de(o, bytes)? },
+    9 => ClientToServer::Ping { nonce: de(o, bytes)? },
+    10 => ClientToServer::Pong { nonce: de(o, bytes)? },
+    11 => ClientToServer::TimeSync { client_time: varint(o, bytes)? },
+    12 => ClientToServer::Identify { player: string(o, bytes)?, key: optional(o, bytes, string)? },
+    13 => ClientToServer::QueueRanked {},
+    14 => ClientToServer::LeaveQueue {},
+    15 => ClientToServer::GetRating { player: string(o, bytes)? },
+    16 => ClientToServer::ListMatches {},
+    17 => ClientToServer::GetMatch { id: varint(o, bytes)? },
+    18 => ClientToServer::Chat { message: string(o, bytes)? },
+    tag => return Err(unknown_tag(tag)),
+    };
+    finished(msg, *o, bytes)
+}
+
+pub fn ser_server(msg: &ServerToClient, output: &mut Vec<u8>) {
+    match msg {
+    ServerToClient::Hello { version, format } => {
+        output.push(0);
+        version.ser_bin(output);
+        format.ser_bin(output);
+    }
+    ServerToClient::Error { error } => {
+        output.push(1);
+        error.ser_bin(output);
+    }
+    ServerToClient::Join { client_id, field } => {
+        output.push(2);
+        client_id.ser_bin(output);
+        field.ser_bin(output);
+    }
+    ServerToClient::Leave { client_id } => {
+        output.push(3);
+        client_id.ser_bin(output);
+    }
+    ServerToClient::Input { client_id, input, up, tick } => {
+        output.push(4);
+        client_id.ser_bin(output);
+        output.push(pack_input(*input, *up));
+        ser_varint(*tick, output);
+    }
+    ServerToClient::Tick { client_id, tick } => {
+        output.push(5);
+        client_id.ser_bin(output);
+        ser_varint(*tick, output);
+    }
+    ServerToClient::RoomList { rooms } => {
+        output.push(6);
+        rooms.ser_bin(output);
+    }
+    ServerToClient::RoomJoined { room } => {
+        output.push(7);
+        room.ser_bin(output);
+    }
+    ServerToClient::RoomLeft {} => output.push(8),
+    ServerToClient::Spectating { room } => {
+        output.push(9);
+        room.ser_bin(output);
+    }
+    ServerToClient::Snapshot { client_id, tick, snapshot } => {
+        output.push(10);
+        client_id.ser_bin(output);
+        ser_varint(*tick, output);
+        snapshot.ser_bin(output);
+    }
+    ServerToClient::RoomError { message } => {
+        output.push(11);
+        message.ser_bin(output);
+    }
+    ServerToClient::Ready { client_id, ready } => {
+        output.push(12);
+        client_id.ser_bin(output);
+        ready.ser_bin(output);
+    }
+    ServerToClient::Countdown { start_tick, now } => {
+        output.push(13);
+        ser_varint(*start_tick, output);
+        ser_varint(*now, output);
+    }
+    ServerToClient::CountdownCancelled {} => output.push(14),
+    ServerToClient::Start { seed, start_tick } => {
+        output.push(15);
+        seed.ser_bin(output);
+        ser_varint(*start_tick, output);
+    }
+    ServerToClient::Garbage { client_id, lines, tick } => {
+        output.push(16);
+        client_id.ser_bin(output);
+        lines.ser_bin(output);
+        ser_varint(*tick, output);
+    }
+    ServerToClient::RoomFinished { winner } => {
+        output.push(17);
+        winner.ser_bin(output);
+    }
+    ServerToClient::Welcome { client_id, token, resumed } => {
+        output.push(18);
+        client_id.ser_bin(output);
+        token.ser_bin(output);
+        resumed.ser_bin(output);
+    }
+    ServerToClient::Ping { nonce } => {
+        output.push(19);
+        nonce.ser_bin(output);
+    }
+    ServerToClient::Pong { nonce } => {
+        output.push(20);
+        nonce.ser_bin(output);
+    }
+    ServerToClient::TimeSync { client_time, server_time, tick } => {
+        output.push(21);
+        ser_varint(*client_time, output);
+        ser_varint(*server_time, output);
+        ser_varint(*tick, output);
+    }
+    ServerToClient::Queued { rating } => {
+        output.push(22);
+        rating.ser_bin(output);
+    }
+    ServerToClient::QueueLeft {} => output.push(23),
+    ServerToClient::MatchFound { room, opponent } => {
+        output.push(24);
+        room.ser_bin(output);
+        opponent.ser_bin(output);
+    }
+    ServerToClient::Rating { rating } => {
+        output.push(25);
+        rating.ser_bin(output);
+    }
+    ServerToClient::RatingChanged { rating, change } => {
+        output.push(26);
+        rating.ser_bin(output);
+        change.ser_bin(output);
+    }
+    ServerToClient::MatchList { matches } => {
+        output.push(27);
+        matches.ser_bin(output);
+    }
+    ServerToClient::MatchRecording { record } => {
+        output.push(28);
+        record.ser_bin(output);
+    }
+    ServerToClient::Announcement { message } => {
+        output.push(29);
+        message.ser_bin(output);
+    }
+    ServerToClient::Chat { client_id, name, message } => {
+        output.push(30);
+        client_id.ser_bin(output);
+        name.ser_bin(output);
+        message.ser_bin(output);
+    }
+    ServerToClient::Identified { player, key } => {
+        output.push(31);
+        player.ser_bin(output);
+        key.ser_bin(output);
+    }
+    }
+}
+
+pub fn de_server(bytes: &[u8]) -> Result<ServerToClient, ProtocolError> {
+    let o = &mut 0;
+    let tag: u8 = de(o, bytes)?;
+    let msg = match tag {
+    0 => ServerToClient::Hello { version: de(o, bytes)?, format: de(o, bytes)? },
+    1 => ServerToClient::Error { error: protocol_error(o, bytes)? },
+    2 => ServerToClient::Join { client_id: de(o, bytes)?, field: field(o, bytes)? },
+    3 => ServerToClient::Leave { client_id: de(o, bytes)? },
+    4 => {
+        let client_id = de(o, bytes)?;
+        let (input, up) = unpack_input(de(o, bytes)?)?;
+        ServerToClient::Input { client_id, input, up, tick: varint(o, bytes)? }
+    }
+    5 => ServerToClient::Tick { client_id: de(o, bytes)?, tick: varint(o, bytes)? },
+    6 => ServerToClient::RoomList { rooms: list(o, bytes, 21, room_info)? },
+    7 => ServerToClient::RoomJoined { room: room_info(o, bytes)? },
+    8 => ServerToClient::RoomLeft {},
+    9 => ServerToClient::Spectating { room: room_info(o, bytes)? },
+    10 => ServerToClient::Snapshot { client_id: de(o, bytes)?, tick: varint(o, bytes)?, snapshot: snapshot(o, bytes)? },
+    11 => ServerToClient::RoomError { message: string(o, bytes)? },
+    12 => ServerToClient::Ready { client_id: de(o, bytes)?, ready: de(o, bytes)? },
+    13 => ServerToClient::Countdown { start_tick: varint(o, bytes)?, now: varint(o, bytes)? },
+    14 => ServerToClient::CountdownCancelled {},
+    15 => ServerToClient::Start { seed: de(o, bytes)?, start_tick: varint(o, bytes)? },
+    16 => ServerToClient::Garbage { client_id: de(o, bytes)?, lines: de(o, bytes)?, tick: varint(o, bytes)? },
+    17 => ServerToClient::RoomFinished { winner: de(o, bytes)? },
+    18 => ServerToClient::Welcome { client_id: de(o, bytes)?, token: string(o, bytes)?, resumed: de(o, bytes)? },
+    19 => ServerToClient::Ping { nonce: de(o, bytes)? },
+    20 => ServerToClient::Pong { nonce: de(o, bytes)? },
+    21 => ServerToClient::TimeSync { client_time: varint(o, bytes)?, server_time: varint(o, bytes)?, tick: varint(o, bytes)? },
+    22 => ServerToClient::Queued { rating: player_rating(o, bytes)? },
+    23 => ServerToClient::QueueLeft {},
+    24 => ServerToClient::MatchFound { room: string(o, bytes)?, opponent: player_rating(o, bytes)? },
+    25 => ServerToClient::Rating { rating: player_rating(o, bytes)? },
+    26 => ServerToClient::RatingChanged { rating: player_rating(o, bytes)?, change: de(o, bytes)? },
+    27 => ServerToClient::MatchList { matches: list(o, bytes, 42, match_summary)? },
+    28 => ServerToClient::MatchRecording { record: match_record(o, bytes)? },
+    29 => ServerToClient::Announcement { message: string(o, bytes)? },
+    30 => ServerToClient::Chat { client_id: de(o, bytes)?, name: string(o, bytes)?, message: string(o, bytes)? },
+    31 => ServerToClient::Identified { player: string(o, bytes)?, key: string(o, bytes)? },
+    tag => return Err(unknown_tag(tag)),
+    };
+    finished(msg, *o, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{field::Field, testing::{client_messages, played_field, server_messages}};
+
+    fn client_bytes() -> Vec<Vec<u8>> {
+        client_messages().iter().map(|it| {
+            let mut output = vec![];
+            ser_client(it, &mut output);
+            output
+        }).collect()
+    }
+
+    fn server_bytes() -> Vec<Vec<u8>> {
+        server_messages().iter().map(|it| {
+            let mut output = vec![];
+            ser_server(it, &mut output);
+            output
+        }).collect()
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        for (msg, bytes) in client_messages().iter().zip(client_bytes()) {
+            assert_eq!(de_client(&bytes).unwrap().serialize_json(), msg.serialize_json());
+        }
+        for (msg, bytes) in server_messages().iter().zip(server_bytes()) {
+            assert_eq!(de_server(&bytes).unwrap().serialize_json(), msg.serialize_json());
+        }
+    }
+
+    #[test]
+    fn field_round_trips() {
+        let field = played_field();
+        let bytes = field.serialize_bin();
+        let decoded = Field::deserialize_bin(&bytes).unwrap();
+        assert_eq!(decoded.serialize_json(), field.serialize_json());
+        assert_eq!(decoded.serialize_bin(), bytes);
+        assert!(bytes.len() * 4 < field.serialize_json().len());
+    }
+
+    #[test]
+    fn truncated_messages_are_errors() {
+        assert!(de_client(&[]).is_err());
+        assert!(de_server(&[]).is_err());
+        for bytes in client_bytes() {
+            for len in 0..bytes.len() {
+                assert!(de_client(&bytes[..len]).is_err(), "{:?} truncated to {}", bytes, len);
+            }
+        }
+        for bytes in server_bytes() {
+            for len in 0..bytes.len() {
+                assert!(de_server(&bytes[..len]).is_err(), "{:?} truncated to {}", bytes, len);
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_messages_never_panic() {
+        for bytes in client_bytes().into_iter().chain(server_bytes()) {
+            for idx in 0..bytes.len() {
+                for value in [0x00, 0x7f, 0x80, 0xff] {
+                    let mut corrupt = bytes.clone();
+                    corrupt[idx] = value;
+                    let _ = de_client(&corrupt);
+                    let _ = de_server(&corrupt);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_messages_are_errors() {
+        assert!(de_client(&[200]).is_err());
+        assert!(de_server(&[200]).is_err());
+        assert!(de_client(&[2, 6, 0]).is_err());
+        assert!(de_client(&[7, 0]).is_err());
+        assert!(de_client(&[0, 9, 0, 0, 0, 7]).is_err());
+
+        let mut huge_name = vec![4];
+        huge_name.extend(u64::MAX.to_le_bytes());
+        huge_name.extend(b"lobby");
+        assert!(de_client(&huge_name).is_err());
+        huge_name[0] = 11;
+        assert!(de_server(&huge_name).is_err());
+
+        let mut bad_utf8 = vec![18];
+        bad_utf8.extend(2u64.to_le_bytes());
+        bad_utf8.extend([0xff, 0xfe]);
+        assert_eq!(de_client(&bad_utf8).err(), Some(ProtocolError::InvalidUtf8 {}));
+
+        let mut huge_list = vec![6];
+        huge_list.extend(u64::MAX.to_le_bytes());
+        assert!(de_server(&huge_list).is_err());
+    }
+}
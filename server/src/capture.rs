@@ -0,0 +1,189 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{fs::File, io::{BufRead, BufReader, BufWriter, Write}, path::{Path, PathBuf}, time::Duration};
+
+use logic::proto::PROTOCOL_VERSION;
+use nanoserde::{DeJson, SerJson};
+
+use crate::{config::Config, machine::{Event, Outbound, Server}, rating::RatingStore, recorder::MatchStore, world::World};
+
+// the first line of a capture is everything the world started from, every line after it is one
+// event along with a hash of what the server sent in response
+#[derive(SerJson, DeJson)]
+pub struct CaptureHeader {
+    pub version: u32,
+    pub config: Config,
+    pub token_seed: u64,
+    pub start_date: u64,
+    pub ratings: String,
+    pub matches: String,
+}
+
+#[derive(SerJson, DeJson)]
+struct CapturedEvent {
+    connection: u64,
+    time: u64,
+    event: Event,
+    outbound: u64,
+}
+
+pub struct Capture {
+    file: BufWriter<File>,
+}
+
+// 64 bit FNV-1a over each message's length and bytes, with disconnects as an impossible length;
+// unlike std's hashers it gives the same answer on every build, so old captures stay comparable
+fn digest(outbound: &[Outbound]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for it in outbound {
+        let bytes = it.encode();
+        let length = bytes.as_ref().map(|it| it.len() as u64).unwrap_or(u64::MAX);
+        for byte in length.to_le_bytes().iter().chain(bytes.iter().flatten()) {
+            hash = (hash ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+impl CaptureHeader {
+    pub fn world(&self, matches_dir: PathBuf) -> World {
+        World::new(self.config.clone(), RatingStore::from_json(&self.ratings), MatchStore::from_index(matches_dir, &self.matches), self.token_seed, self.start_date)
+    }
+}
+
+impl Capture {
+    pub fn create(path: &Path, header: &CaptureHeader) -> Result<Capture, String> {
+        let mut file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        writeln!(file, "{}", header.serialize_json()).map_err(|e| e.to_string())?;
+        Ok(Capture { file })
+    }
+    pub fn record(&mut self, connection: u64, now: Duration, event: &Event, outbound: &[Outbound]) -> Result<(), String> {
+        let line = CapturedEvent {
+            connection,
+            time: now.as_nanos() as u64,
+            event: event.clone(),
+            outbound: digest(outbound),
+        };
+        writeln!(self.file, "{}", line.serialize_json()).map_err(|e| e.to_string())?;
+        self.file.flush().map_err(|e| e.to_string())
+    }
+}
+
+// feeds a capture back through a fresh world that doesn't write to disk, stopping at the first
+// event where the server would have sent something different
+pub fn replay(path: &Path, matches_dir: PathBuf, dump: bool) -> Result<usize, String> {
+    let mut lines = BufReader::new(File::open(path).map_err(|e| e.to_string())?).lines();
+    let header = lines.next().ok_or("empty capture")?.map_err(|e| e.to_string())?;
+    let header = CaptureHeader::deserialize_json(&header).map_err(|e| format!("{:?}", e))?;
+    if header.version != PROTOCOL_VERSION {
+        return Err(format!("capture is from protocol version {}, this server speaks {}", header.version, PROTOCOL_VERSION));
+    }
+
+    let mut server = Server::new(header.world(matches_dir));
+    let mut count = 0;
+    for line in lines {
+        let line = line.map_err(|e| e.to_string())?;
+        let captured = CapturedEvent::deserialize_json(&line).map_err(|e| format!("{:?}", e))?;
+        let now = Duration::from_nanos(captured.time);
+        server.advance(now);
+        let outbound = server.handle(captured.connection, now, &captured.event);
+        if dump {
+            for it in &outbound {
+                match it {
+                Outbound::Send { message, .. } => println!("{:.6} {} {}", now.as_secs_f64(), captured.connection, message.serialize_json()),
+                Outbound::Disconnect => println!("{:.6} {} disconnect", now.as_secs_f64(), captured.connection),
+                }
+            }
+        }
+        if digest(&outbound) != captured.outbound {
+            return Err(format!("replay diverged at event {} ({:?} on connection {} at {:?})", count + 1, captured.event, captured.connection, now));
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use logic::{input::Input, proto::{encode_client, ClientToServer, ServerToClient}, wire::WireFormat};
+
+    use super::*;
+
+    fn header(token_seed: u64) -> CaptureHeader {
+        CaptureHeader {
+            version: PROTOCOL_VERSION,
+            config: Config::new(),
+            token_seed,
+            start_date: 0,
+            ratings: String::new(),
+            matches: String::new(),
+        }
+    }
+
+    fn message(msg: ClientToServer) -> Event {
+        let format = if matches!(msg, ClientToServer::Hello { .. }) { WireFormat::Json } else { WireFormat::Binary };
+        Event::Message { bytes: encode_client(&msg, format) }
+    }
+
+    // two players meet in a room, play a little and one of them drops
+    fn session() -> Vec<(u64, u64, Event)> {
+        let mut events = vec![];
+        for tick in 0..600u64 {
+            let script = match tick {
+            0 => vec![Event::Connect {}, message(ClientToServer::Hello { version: PROTOCOL_VERSION, format: WireFormat::Binary }), message(ClientToServer::Join { token: None })],
+            10 => vec![message(ClientToServer::CreateRoom { name: "room".to_string(), capacity: 2, attack_table: None })],
+            20 => vec![message(ClientToServer::JoinRoom { name: "room".to_string() })],
+            30 => vec![message(ClientToServer::Ready { ready: true })],
+            _ if tick % 40 == 0 => vec![message(ClientToServer::Input { input: Input::Left, up: tick % 80 == 0, tick: tick.saturating_sub(200) })],
+            _ => vec![],
+            };
+            for connection in [1, 2] {
+                if tick == 10 && connection == 2 || tick == 20 && connection == 1 {
+                    continue;
+                }
+                events.extend(script.iter().map(|it| (connection, tick, it.clone())));
+                events.push((connection, tick, Event::Timer {}));
+            }
+        }
+        events.push((2, 600, Event::Disconnect {}));
+        events.push((1, 600, Event::Timer {}));
+        events
+    }
+
+    fn record(path: &Path, header: &CaptureHeader) -> Vec<Outbound> {
+        let mut capture = Capture::create(path, header).unwrap();
+        let mut server = Server::new(header.world(PathBuf::new()));
+        let mut sent = vec![];
+        for (connection, tick, event) in session() {
+            let now = Duration::from_micros(tick * 1_000_000 / 60 + 1000);
+            server.advance(now);
+            let outbound = server.handle(connection, now, &event);
+            capture.record(connection, now, &event, &outbound).unwrap();
+            sent.extend(outbound);
+        }
+        sent
+    }
+
+    #[test]
+    fn replaying_a_capture_reproduces_it() {
+        let path = std::env::temp_dir().join(format!("capture-{}.jsonl", std::process::id()));
+        let sent = record(&path, &header(1));
+        assert!(sent.iter().any(|it| matches!(it, Outbound::Send { message, .. } if matches!(**message, ServerToClient::Start { .. }))));
+        assert_eq!(replay(&path, PathBuf::new(), false), Ok(session().len()));
+
+        // a different token seed hands out different tokens, which the replay has to notice
+        let mut lines = std::fs::read_to_string(&path).unwrap().lines().map(|it| it.to_string()).collect::<Vec<_>>();
+        lines[0] = header(2).serialize_json();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        assert!(replay(&path, PathBuf::new(), false).unwrap_err().contains("diverged at event"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn digests_are_stable() {
+        assert_eq!(digest(&[]), 0xcbf29ce484222325);
+        assert_eq!(digest(&[Outbound::Disconnect]), 0x8cf51a8bfca3883d);
+    }
+}
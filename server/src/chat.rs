@@ -0,0 +1,66 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+pub const MAX_CHAT_LENGTH: usize = 200;
+pub const CHAT_BURST: f64 = 5.;
+pub const CHAT_PER_SECOND: f64 = 0.5;
+
+// every chat message passes through a filter before it reaches the room; returning None drops
+// the message, otherwise whatever comes back is what gets sent
+pub trait ChatFilter: Send {
+    fn filter(&self, message: &str) -> Option<String>;
+}
+
+// masks any word that matches one of the configured words, ignoring case and punctuation, and
+// leaves the whitespace between words as it was
+pub struct WordFilter {
+    words: Vec<String>,
+}
+
+impl WordFilter {
+    pub fn new(words: &[String]) -> WordFilter {
+        WordFilter {
+            words: words.iter().map(|it| it.to_lowercase()).collect(),
+        }
+    }
+}
+
+impl ChatFilter for WordFilter {
+    fn filter(&self, message: &str) -> Option<String> {
+        let mut filtered = String::new();
+        for piece in message.split_inclusive(char::is_whitespace) {
+            let word = piece.trim_end_matches(char::is_whitespace);
+            let bare = word.chars().filter(|it| it.is_alphanumeric()).collect::<String>().to_lowercase();
+            if self.words.contains(&bare) {
+                filtered.push_str(&"*".repeat(word.chars().count()));
+            } else {
+                filtered.push_str(word);
+            }
+            filtered.push_str(&piece[word.len()..]);
+        }
+        Some(filtered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(message: &str) -> Option<String> {
+        WordFilter::new(&["Heck".to_string()]).filter(message)
+    }
+
+    #[test]
+    fn words_are_masked_ignoring_case_and_punctuation() {
+        assert_eq!(filter("Heck, what the\theck  HECK!"), Some("***** what the\t****  *****".to_string()));
+        assert_eq!(filter("heckin hecks"), Some("heckin hecks".to_string()));
+        assert_eq!(filter(""), Some(String::new()));
+    }
+
+    #[test]
+    fn any_whitespace_separates_words() {
+        assert_eq!(filter("heck\u{3000}ok"), Some("****\u{3000}ok".to_string()));
+        assert_eq!(filter("ok\u{a0}heck"), Some("ok\u{a0}****".to_string()));
+    }
+}
@@ -0,0 +1,158 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{path::Path, str::FromStr};
+
+use logic::clock::TICKS_PER_SECOND;
+use nanoserde::{DeJson, SerJson};
+
+use crate::world::MAX_CATCH_UP;
+
+#[derive(SerJson, DeJson, Clone, Debug)]
+pub struct Config {
+    pub tcp_address: String,
+    pub websocket_address: String,
+    pub status_address: String,
+    pub ticks_per_second: f64,
+    pub max_rooms: u32,
+    pub max_room_capacity: u32,
+    pub max_spectators: u32,
+    pub chat_filter: Vec<String>,
+}
+
+// a config file only has to mention what it changes, and flags on the command line win over it
+#[derive(DeJson)]
+struct ConfigOverrides {
+    tcp_address: Option<String>,
+    websocket_address: Option<String>,
+    status_address: Option<String>,
+    ticks_per_second: Option<f64>,
+    max_rooms: Option<u32>,
+    max_room_capacity: Option<u32>,
+    max_spectators: Option<u32>,
+    chat_filter: Option<Vec<String>>,
+}
+
+fn flag<T: FromStr>(args: &[String], name: &str) -> Result<Option<T>, String> {
+    match args.iter().skip_while(|it| *it != name).nth(1) {
+    Some(value) => value.parse().map(Some).map_err(|_| format!("invalid value {} for {}", value, name)),
+    None => Ok(None),
+    }
+}
+
+impl ConfigOverrides {
+    fn from_args(args: &[String]) -> Result<ConfigOverrides, String> {
+        Ok(ConfigOverrides {
+            tcp_address: flag(args, "--tcp")?,
+            websocket_address: flag(args, "--websocket")?,
+            status_address: flag(args, "--status")?,
+            ticks_per_second: flag(args, "--tick-rate")?,
+            max_rooms: flag(args, "--max-rooms")?,
+            max_room_capacity: flag(args, "--max-room-capacity")?,
+            max_spectators: flag(args, "--max-spectators")?,
+            chat_filter: None,
+        })
+    }
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            tcp_address: "0.0.0.0:8088".to_string(),
+            websocket_address: "0.0.0.0:6507".to_string(),
+            status_address: "127.0.0.1:9107".to_string(),
+            ticks_per_second: TICKS_PER_SECOND as f64,
+            max_rooms: 64,
+            max_room_capacity: 8,
+            max_spectators: 16,
+            chat_filter: vec![],
+        }
+    }
+    // defaults, then the file given with --config, then the rest of the flags
+    pub fn from_args(args: &[String]) -> Result<Config, String> {
+        let mut config = Config::new();
+        if let Some(path) = flag::<String>(args, "--config")? {
+            let contents = std::fs::read_to_string(Path::new(&path)).map_err(|e| format!("couldn't read {}: {}", path, e))?;
+            config.apply(ConfigOverrides::deserialize_json(&contents).map_err(|e| format!("couldn't parse {}: {:?}", path, e))?);
+        }
+        config.apply(ConfigOverrides::from_args(args)?);
+
+        // the tick rate is how often the server wakes up; the world always steps at
+        // TICKS_PER_SECOND and catches up by wall time, but only MAX_CATCH_UP steps at once
+        let slowest = TICKS_PER_SECOND as f64 / MAX_CATCH_UP as f64;
+        if !(config.ticks_per_second.is_finite() && config.ticks_per_second >= slowest) {
+            return Err(format!("the tick rate must be at least {}", slowest));
+        }
+        if config.max_room_capacity == 0 {
+            return Err("rooms must hold at least one player".to_string());
+        }
+        Ok(config)
+    }
+    fn apply(&mut self, overrides: ConfigOverrides) {
+        if let Some(it) = overrides.tcp_address {
+            self.tcp_address = it;
+        }
+        if let Some(it) = overrides.websocket_address {
+            self.websocket_address = it;
+        }
+        if let Some(it) = overrides.status_address {
+            self.status_address = it;
+        }
+        if let Some(it) = overrides.ticks_per_second {
+            self.ticks_per_second = it;
+        }
+        if let Some(it) = overrides.max_rooms {
+            self.max_rooms = it;
+        }
+        if let Some(it) = overrides.max_room_capacity {
+            self.max_room_capacity = it;
+        }
+        if let Some(it) = overrides.max_spectators {
+            self.max_spectators = it;
+        }
+        if let Some(it) = overrides.chat_filter {
+            self.chat_filter = it;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ').map(|it| it.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_win_over_the_file_which_wins_over_defaults() {
+        let path = std::env::temp_dir().join(format!("config-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"tcp_address":"127.0.0.1:1","max_rooms":4,"chat_filter":["heck"]}"#).unwrap();
+        let config = Config::from_args(&args(&format!("server --max-rooms 2 --config {} --max-spectators 3", path.display()))).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.tcp_address, "127.0.0.1:1");
+        assert_eq!(config.max_rooms, 2);
+        assert_eq!(config.max_spectators, 3);
+        assert_eq!(config.chat_filter, vec!["heck".to_string()]);
+        assert_eq!(config.websocket_address, Config::new().websocket_address);
+        assert_eq!(config.max_room_capacity, Config::new().max_room_capacity);
+    }
+
+    #[test]
+    fn tick_rates_other_than_the_simulation_rate_are_allowed() {
+        assert_eq!(Config::from_args(&args("server --tick-rate 30")).unwrap().ticks_per_second, 30.);
+        assert_eq!(Config::from_args(&args("server --tick-rate 120")).unwrap().ticks_per_second, 120.);
+    }
+
+    #[test]
+    fn bad_configs_are_rejected() {
+        assert!(Config::from_args(&args("server --tick-rate 0")).unwrap_err().contains("tick rate"));
+        assert!(Config::from_args(&args("server --tick-rate 5")).unwrap_err().contains("tick rate"));
+        assert!(Config::from_args(&args("server --tick-rate NaN")).is_err());
+        assert!(Config::from_args(&args("server --max-rooms lots")).unwrap_err().contains("--max-rooms"));
+        assert!(Config::from_args(&args("server --max-room-capacity 0")).is_err());
+        assert!(Config::from_args(&args("server --config /nonexistent/config.json")).is_err());
+    }
+}
@@ -0,0 +1,39 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::time::Duration;
+
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+pub const MESSAGE_BURST: f64 = 120.;
+pub const MESSAGES_PER_SECOND: f64 = 60.;
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(15);
+
+pub struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    tokens: f64,
+    updated: Duration,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64, per_second: f64, now: Duration) -> TokenBucket {
+        TokenBucket {
+            capacity,
+            per_second,
+            tokens: capacity,
+            updated: now,
+        }
+    }
+    pub fn take(&mut self, now: Duration) -> bool {
+        let elapsed = now.saturating_sub(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.updated = now;
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,530 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{collections::BTreeMap, time::Duration};
+
+use logic::{proto::{decode_client, encode_server, ClientToServer, ProtocolError, ServerToClient, PROTOCOL_VERSION}, wire::WireFormat};
+use nanoserde::{DeJson, SerJson};
+
+use crate::{limit::{TokenBucket, IDLE_TIMEOUT, MAX_MESSAGE_SIZE, MESSAGES_PER_SECOND, MESSAGE_BURST}, world::World};
+
+// everything that can happen to a connection, which is also what a capture records
+#[derive(SerJson, DeJson, Clone, Debug)]
+pub enum Event {
+    Connect {},
+    Message { bytes: Vec<u8> },
+    Timer {},
+    Disconnect {},
+    Admin { command: String },
+}
+
+pub enum Outbound {
+    Send { message: Box<ServerToClient>, format: WireFormat },
+    Disconnect,
+}
+
+impl Outbound {
+    pub fn encode(&self) -> Option<Vec<u8>> {
+        match self {
+        Outbound::Send { message, format } => Some(encode_server(message, *format)),
+        Outbound::Disconnect => None,
+        }
+    }
+}
+
+struct Connection {
+    id: Option<u32>,
+    handshake: bool,
+    format: WireFormat,
+    last_heard: Duration,
+    bucket: TokenBucket,
+    kicked: bool,
+}
+
+// the socket side of the server without the sockets: connections are numbered by whoever feeds
+// events in, and `now` is the time since the server started
+pub struct Server {
+    world: World,
+    connections: BTreeMap<u64, Connection>,
+    shutting_down: bool,
+}
+
+fn reject(format: WireFormat, error: ProtocolError) -> Vec<Outbound> {
+    vec![Outbound::Send { message: Box::new(ServerToClient::Error { error }), format }, Outbound::Disconnect]
+}
+
+impl Server {
+    pub fn new(world: World) -> Server {
+        Server {
+            world,
+            connections: BTreeMap::new(),
+            shutting_down: false,
+        }
+    }
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+    pub fn advance(&mut self, now: Duration) -> u64 {
+        self.world.advance(now)
+    }
+    pub fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down
+    }
+    // the world has to be advanced to `now` first, which is left to the caller so it can time it
+    pub fn handle(&mut self, connection: u64, now: Duration, event: &Event) -> Vec<Outbound> {
+        match event {
+        Event::Connect {} => {
+            self.connections.insert(connection, Connection {
+                id: None,
+                handshake: false,
+                format: WireFormat::Json,
+                last_heard: now,
+                bucket: TokenBucket::new(MESSAGE_BURST, MESSAGES_PER_SECOND, now),
+                kicked: false,
+            });
+            vec![]
+        }
+        Event::Message { bytes } => self.message(connection, now, bytes),
+        Event::Timer {} => self.timer(connection, now),
+        Event::Disconnect {} => {
+            if let Some(Connection { id: Some(id), .. }) = self.connections.remove(&connection) {
+                self.world.disconnect(id, connection);
+            }
+            vec![]
+        }
+        Event::Admin { command } => {
+            self.admin(now, command);
+            vec![]
+        }
+        }
+    }
+    // commands from the operator's console; kicked clients and everyone after a shutdown are
+    // dropped on their next timer since that's the only place their sockets can be reached
+    pub fn admin(&mut self, now: Duration, command: &str) -> String {
+        self.world.advance(now);
+        let (name, rest) = command.trim().split_once(' ').unwrap_or((command.trim(), ""));
+        let rest = rest.trim();
+        match name {
+        "clients" => {
+            let clients = self.world.clients();
+            let mut lines = vec![format!("{} clients", clients.len())];
+            lines.extend(clients.into_iter().map(|it| format!(
+                "{} {} room={} spectator={} connected={} rtt={}",
+                it.id,
+                it.player.unwrap_or_else(|| "guest".to_string()),
+                it.room.unwrap_or_else(|| "-".to_string()),
+                it.spectator,
+                it.connected,
+                it.rtt.map(|it| format!("{}ms", it.as_millis())).unwrap_or_else(|| "-".to_string()),
+            )));
+            lines.join("\n")
+        }
+        "rooms" => {
+            let rooms = self.world.rooms();
+            let mut lines = vec![format!("{} rooms", rooms.len())];
+            lines.extend(rooms.into_iter().map(|it| format!(
+                "{} players={}/{} spectators={} playing={}",
+                it.name, it.players, it.capacity, it.spectators, it.playing,
+            )));
+            lines.join("\n")
+        }
+        "kick" => match rest.parse::<u32>() {
+        Ok(id) if self.world.kick(id) => {
+            for connection in self.connections.values_mut().filter(|it| it.id == Some(id)) {
+                connection.kicked = true;
+            }
+            format!("kicked {}", id)
+        }
+        Ok(id) => format!("no client {}", id),
+        Err(_) => "usage: kick <client id>".to_string(),
+        },
+        "broadcast" if !rest.is_empty() => {
+            self.world.broadcast(rest);
+            "sent".to_string()
+        }
+        "broadcast" => "usage: broadcast <message>".to_string(),
+        "shutdown" => {
+            self.shutting_down = true;
+            format!("shutting down, waiting for {} connections to close", self.connections.len())
+        }
+        "help" | "" => "commands: clients, rooms, kick <client id>, broadcast <message>, shutdown".to_string(),
+        _ => format!("unknown command {}, try help", name),
+        }
+    }
+    fn message(&mut self, connection: u64, now: Duration, bytes: &[u8]) -> Vec<Outbound> {
+        let Some(state) = self.connections.get_mut(&connection) else {
+            return vec![];
+        };
+        state.last_heard = now;
+        if self.shutting_down {
+            return reject(state.format, ProtocolError::ShuttingDown {});
+        }
+        if bytes.len() > MAX_MESSAGE_SIZE {
+            return reject(state.format, ProtocolError::MessageTooLarge { size: bytes.len() as u32, limit: MAX_MESSAGE_SIZE as u32 });
+        }
+        if !state.bucket.take(now) {
+            return reject(state.format, ProtocolError::RateLimited {});
+        }
+
+        // the hello message and any reply to it are always json, the rest of the
+        // session uses the format the client picked in its hello
+        let msg = match decode_client(bytes, state.format) {
+            Ok(msg) => msg,
+            Err(error) => return reject(state.format, error),
+        };
+        if !state.handshake && !matches!(msg, ClientToServer::Hello { .. }) {
+            return reject(state.format, ProtocolError::HandshakeRequired {});
+        }
+
+        let format = state.format;
+        let reply = |message| vec![Outbound::Send { message: Box::new(message), format }];
+        let id = match msg {
+        ClientToServer::Hello { version, format } => {
+            if version != PROTOCOL_VERSION {
+                return reject(WireFormat::Json, ProtocolError::VersionMismatch { server: PROTOCOL_VERSION, client: version });
+            }
+            state.handshake = true;
+            state.format = format;
+            return vec![Outbound::Send { message: Box::new(ServerToClient::Hello { version: PROTOCOL_VERSION, format }), format: WireFormat::Json }];
+        }
+        ClientToServer::Ping { nonce } => return reply(ServerToClient::Pong { nonce }),
+        ClientToServer::TimeSync { client_time } => {
+            return reply(ServerToClient::TimeSync { client_time, server_time: now.as_micros() as u64, tick: self.world.tick() });
+        }
+        ClientToServer::Join { token } => {
+            if state.id.is_none() {
+                state.id = Some(self.world.join(token, connection));
+            }
+            return vec![];
+        }
+        _ => match state.id {
+        Some(id) => id,
+        None => return vec![],
+        },
+        };
+
+        match msg {
+        ClientToServer::Input { input, up, tick } => self.world.input(id, input, up, tick),
+        ClientToServer::CreateRoom { name, capacity, attack_table } => self.world.create_room(id, name, capacity, attack_table),
+        ClientToServer::JoinRoom { name } => self.world.join_room(id, name),
+        ClientToServer::LeaveRoom {} => self.world.leave_room(id),
+        ClientToServer::Spectate { name } => self.world.spectate(id, name),
+        ClientToServer::ListRooms {} => self.world.list_rooms(id),
+        ClientToServer::Ready { ready } => self.world.set_ready(id, ready),
+        ClientToServer::Pong { nonce } => self.world.pong(id, nonce),
+        ClientToServer::Identify { player, key } => self.world.identify(id, player, key),
+        ClientToServer::QueueRanked {} => self.world.queue_ranked(id),
+        ClientToServer::LeaveQueue {} => self.world.leave_queue(id),
+        ClientToServer::GetRating { player } => self.world.get_rating(id, player),
+        ClientToServer::ListMatches {} => self.world.list_matches(id),
+        ClientToServer::GetMatch { id: match_id } => self.world.get_match(id, match_id),
+        ClientToServer::Chat { message } => self.world.chat(id, message),
+        ClientToServer::Hello { .. } | ClientToServer::Join { .. } | ClientToServer::Ping { .. } | ClientToServer::TimeSync { .. } => {}
+        }
+        vec![]
+    }
+    fn timer(&mut self, connection: u64, now: Duration) -> Vec<Outbound> {
+        let Some(state) = self.connections.get(&connection) else {
+            return vec![];
+        };
+        if state.kicked {
+            return reject(state.format, ProtocolError::Kicked {});
+        }
+        if self.shutting_down {
+            return reject(state.format, ProtocolError::ShuttingDown {});
+        }
+        if now.saturating_sub(state.last_heard) > IDLE_TIMEOUT {
+            return reject(state.format, ProtocolError::Timeout {});
+        }
+        let format = state.format;
+        match state.id {
+        Some(id) => self.world.dequeue_messages_for(id, connection).into_iter()
+            .map(|message| Outbound::Send { message: Box::new(message), format })
+            .collect(),
+        None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque, path::PathBuf, rc::Rc};
+
+    use logic::{field::Field, hooks::Silent, input::Input, netsim::{NetConditions, SimulatedLink, Transport}, proto::{decode_server, encode_client}, rollback::{RemoteEvent, Rollback, RollbackConfig}};
+    use nanoserde::SerBin;
+
+    use crate::{config::Config, rating::RatingStore, recorder::MatchStore};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Wire {
+        to_server: VecDeque<Vec<u8>>,
+        to_client: VecDeque<Vec<u8>>,
+        closed: bool,
+    }
+
+    // the client's end of an in-memory connection, which closes it when dropped
+    struct ClientEnd(Rc<RefCell<Wire>>);
+
+    impl Transport for ClientEnd {
+        fn send(&mut self, data: &[u8]) {
+            self.0.borrow_mut().to_server.push_back(data.to_vec());
+        }
+        fn try_recv(&mut self) -> Option<Vec<u8>> {
+            self.0.borrow_mut().to_client.pop_front()
+        }
+    }
+
+    impl Drop for ClientEnd {
+        fn drop(&mut self) {
+            self.0.borrow_mut().closed = true;
+        }
+    }
+
+    struct Client {
+        link: SimulatedLink<ClientEnd>,
+        wire: Rc<RefCell<Wire>>,
+        connection: Option<u64>,
+        handshake: bool,
+        received: Vec<ServerToClient>,
+    }
+
+    // clients talk to the server through simulated links, with time moving a tick per step
+    struct Harness {
+        server: Server,
+        clients: Vec<Client>,
+        steps: u64,
+        next_connection: u64,
+    }
+
+    impl Harness {
+        fn new() -> Harness {
+            let world = World::new(Config::new(), RatingStore::from_json(""), MatchStore::from_index(PathBuf::new(), ""), 1, 0);
+            Harness { server: Server::new(world), clients: vec![], steps: 0, next_connection: 0 }
+        }
+        fn now(&self) -> Duration {
+            Duration::from_micros(self.steps * 1_000_000 / 60 + 1000)
+        }
+        fn open(&mut self, client: usize, token: Option<String>) {
+            self.next_connection += 1;
+            let connection = self.next_connection;
+            self.server.handle(connection, self.now(), &Event::Connect {});
+            self.clients[client].connection = Some(connection);
+            self.clients[client].handshake = false;
+            self.send(client, ClientToServer::Hello { version: PROTOCOL_VERSION, format: WireFormat::Binary });
+            self.send(client, ClientToServer::Join { token });
+        }
+        fn connect(&mut self, conditions: NetConditions) -> usize {
+            let wire = Rc::new(RefCell::new(Wire::default()));
+            let link = SimulatedLink::new(ClientEnd(wire.clone()), Some(conditions), self.clients.len() as u32 + 1, self.now().as_secs_f64());
+            self.clients.push(Client { link, wire, connection: None, handshake: false, received: vec![] });
+            self.open(self.clients.len() - 1, None);
+            self.clients.len() - 1
+        }
+        fn reconnect(&mut self, client: usize, token: String) {
+            let wire = Rc::new(RefCell::new(Wire::default()));
+            self.clients[client].link.reconnect(ClientEnd(wire.clone()));
+            self.clients[client].wire = wire;
+            self.open(client, Some(token));
+        }
+        fn send(&mut self, client: usize, msg: ClientToServer) {
+            let format = if matches!(msg, ClientToServer::Hello { .. }) { WireFormat::Json } else { WireFormat::Binary };
+            let now = self.now().as_secs_f64();
+            self.clients[client].link.send(now, &encode_client(&msg, format));
+        }
+        fn step(&mut self) {
+            self.steps += 1;
+            let now = self.now();
+            self.server.advance(now);
+            for client in &mut self.clients {
+                if let Some(connection) = client.connection {
+                    if client.wire.borrow().closed {
+                        self.server.handle(connection, now, &Event::Disconnect {});
+                        client.connection = None;
+                        continue;
+                    }
+                    let incoming = client.wire.borrow_mut().to_server.drain(..).collect::<Vec<_>>();
+                    let events = incoming.into_iter().map(|bytes| Event::Message { bytes }).chain([Event::Timer {}]);
+                    let mut outbound = vec![];
+                    for event in events {
+                        outbound.extend(self.server.handle(connection, now, &event));
+                        // like quad-net, nothing more is read from a connection once the server drops it
+                        if matches!(outbound.last(), Some(Outbound::Disconnect)) {
+                            self.server.handle(connection, now, &Event::Disconnect {});
+                            client.connection = None;
+                            break;
+                        }
+                    }
+                    for bytes in outbound.iter().filter_map(Outbound::encode) {
+                        client.wire.borrow_mut().to_client.push_back(bytes);
+                    }
+                }
+                while let Some(bytes) = client.link.try_recv(now.as_secs_f64()) {
+                    let format = if client.handshake { WireFormat::Binary } else { WireFormat::Json };
+                    let msg = decode_server(&bytes, format).unwrap();
+                    client.handshake |= matches!(msg, ServerToClient::Hello { .. });
+                    client.received.push(msg);
+                }
+            }
+        }
+        fn run(&mut self, seconds: f64) {
+            for _ in 0..(seconds * 60.) as u64 {
+                self.step();
+            }
+        }
+        fn run_until(&mut self, client: usize, found: impl Fn(&ServerToClient) -> bool) {
+            for _ in 0..60 * 10 {
+                if self.clients[client].received.iter().any(&found) {
+                    return;
+                }
+                self.step();
+            }
+            panic!("client {} never got the message", client);
+        }
+        fn welcome(&self, client: usize) -> (u32, String, bool) {
+            self.clients[client].received.iter().rev().find_map(|it| match it {
+            ServerToClient::Welcome { client_id, token, resumed } => Some((*client_id, token.clone(), *resumed)),
+            _ => None,
+            }).unwrap()
+        }
+    }
+
+    fn laggy() -> NetConditions {
+        NetConditions { latency: 0.06, jitter: 0.02, ..NetConditions::new() }
+    }
+
+    #[test]
+    fn opponents_see_the_same_field_over_a_laggy_link() {
+        let mut harness = Harness::new();
+        let (a, b) = (harness.connect(laggy()), harness.connect(laggy()));
+        harness.send(a, ClientToServer::CreateRoom { name: "room".to_string(), capacity: 2, attack_table: None });
+        harness.run_until(a, |it| matches!(it, ServerToClient::RoomJoined { .. }));
+        harness.send(b, ClientToServer::JoinRoom { name: "room".to_string() });
+        harness.run_until(b, |it| matches!(it, ServerToClient::RoomJoined { .. }));
+        harness.send(a, ClientToServer::Ready { ready: true });
+        harness.send(b, ClientToServer::Ready { ready: true });
+        harness.run_until(b, |it| matches!(it, ServerToClient::Start { .. }));
+        let (seed, start_tick) = harness.clients[b].received.iter().find_map(|it| match it {
+        ServerToClient::Start { seed, start_tick } => Some((*seed, *start_tick)),
+        _ => None,
+        }).unwrap();
+        let a_id = harness.welcome(a).0;
+
+        // a plays with its inputs stamped a few ticks ahead, so they reach the server in time
+        let config = RollbackConfig { input_delay: 10, max_rollback: 600 };
+        let mut own = Rollback::new(Field::seeded(seed), 0, config);
+        let script = [Input::Left, Input::CW, Input::Up, Input::Down, Input::Right, Input::Right, Input::Up, Input::Down];
+        for input in script {
+            harness.run(0.3);
+            let tick = harness.server.world().tick() - start_tick + config.input_delay;
+            for (up, at) in [(true, tick), (false, tick + 2)] {
+                harness.send(a, ClientToServer::Input { input, up, tick: at });
+                own.receive(at, RemoteEvent::Input { input, up });
+            }
+        }
+        harness.run(1.);
+
+        let mut remote = Rollback::new(Field::seeded(seed), 0, config);
+        let mut confirmed = 0;
+        for msg in &harness.clients[b].received {
+            match *msg {
+            ServerToClient::Input { client_id, input, up, tick } if client_id == a_id => remote.receive(tick, RemoteEvent::Input { input, up }),
+            ServerToClient::Garbage { client_id, lines, tick } if client_id == a_id => remote.receive(tick, RemoteEvent::Garbage { lines }),
+            ServerToClient::Tick { client_id, tick } if client_id == a_id => {
+                remote.confirm(tick);
+                confirmed = tick;
+            }
+            _ => {}
+            }
+        }
+        assert!(confirmed > 200);
+        own.confirm(confirmed);
+        own.advance(confirmed + 1 + config.input_delay, &mut Silent, &mut Silent);
+        remote.advance(confirmed + 1 + config.input_delay, &mut Silent, &mut Silent);
+        assert_eq!(own.tick(), remote.tick());
+        assert!(own.stats().pieces > 0);
+        assert_eq!(own.field().serialize_bin(), remote.field().serialize_bin());
+    }
+
+    #[test]
+    fn outages_drop_the_connection_and_the_session_resumes() {
+        let mut harness = Harness::new();
+        let client = harness.connect(NetConditions { outage_every: Some(2.), outage_length: 0.5, ..laggy() });
+        harness.run_until(client, |it| matches!(it, ServerToClient::Welcome { .. }));
+        let (id, token, _) = harness.welcome(client);
+
+        harness.run(1.7);
+        assert!(!harness.clients[client].link.connected());
+        assert_eq!(harness.clients[client].connection, None);
+        assert_eq!(harness.server.connection_count(), 0);
+
+        harness.run(0.4);
+        assert!(harness.clients[client].link.can_reconnect(harness.now().as_secs_f64()));
+        harness.reconnect(client, token);
+        harness.run_until(client, |it| matches!(it, ServerToClient::Welcome { resumed: true, .. }));
+        let (resumed_id, _, resumed) = harness.welcome(client);
+        assert_eq!((resumed_id, resumed), (id, true));
+    }
+
+    fn dropped_with(harness: &Harness, client: usize, error: impl Fn(&ProtocolError) -> bool) -> bool {
+        let received = &harness.clients[client].received;
+        let last_error = received.iter().rev().find_map(|it| match it {
+        ServerToClient::Error { error } => Some(error),
+        _ => None,
+        });
+        harness.clients[client].connection.is_none() && harness.server.connection_count() == 0 && last_error.is_some_and(error)
+    }
+
+    #[test]
+    fn oversized_messages_drop_the_connection() {
+        let mut harness = Harness::new();
+        let client = harness.connect(NetConditions::new());
+        harness.run_until(client, |it| matches!(it, ServerToClient::Welcome { .. }));
+
+        let now = harness.now().as_secs_f64();
+        harness.clients[client].link.send(now, &vec![0; MAX_MESSAGE_SIZE + 1]);
+        harness.run(0.1);
+        assert!(dropped_with(&harness, client, |it| matches!(
+            it,
+            ProtocolError::MessageTooLarge { size, limit } if *size as usize == MAX_MESSAGE_SIZE + 1 && *limit as usize == MAX_MESSAGE_SIZE
+        )));
+    }
+
+    #[test]
+    fn bursts_over_the_bucket_drop_the_connection() {
+        let mut harness = Harness::new();
+        let client = harness.connect(NetConditions::new());
+        harness.run_until(client, |it| matches!(it, ServerToClient::Welcome { .. }));
+        // long enough for the bucket to refill after the handshake
+        harness.run(1.);
+
+        for nonce in 0..MESSAGE_BURST as u32 + 1 {
+            harness.send(client, ClientToServer::Ping { nonce });
+        }
+        harness.run(0.1);
+        let pongs = harness.clients[client].received.iter().filter(|it| matches!(it, ServerToClient::Pong { .. })).count();
+        assert_eq!(pongs, MESSAGE_BURST as usize);
+        assert!(dropped_with(&harness, client, |it| matches!(it, ProtocolError::RateLimited {})));
+    }
+
+    #[test]
+    fn idle_connections_time_out() {
+        let mut harness = Harness::new();
+        let client = harness.connect(NetConditions::new());
+        harness.run_until(client, |it| matches!(it, ServerToClient::Welcome { .. }));
+
+        harness.run(IDLE_TIMEOUT.as_secs_f64() - 1.);
+        assert!(harness.clients[client].connection.is_some());
+        harness.send(client, ClientToServer::Pong { nonce: 0 });
+        harness.run(IDLE_TIMEOUT.as_secs_f64() - 1.);
+        assert!(harness.clients[client].connection.is_some());
+
+        harness.run(2.);
+        assert!(dropped_with(&harness, client, |it| matches!(it, ProtocolError::Timeout {})));
+    }
+}
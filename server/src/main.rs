@@ -2,189 +2,321 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use core::str;
-use std::{collections::{HashMap, HashSet, VecDeque}, sync::{Arc, Mutex}, time::Duration};
+use std::{cell::Cell, collections::hash_map::RandomState, hash::BuildHasher, net::TcpListener, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 
-use logic::{field::Field, hooks::{Cubes, Sounds}, input::{Input, InputProvider, Inputs}};
-use nanoserde::{DeJson, SerJson};
-use logic::proto::{ClientToServer, ServerToClient};
-use quad_net::quad_socket::server::{listen, Settings};
+use logic::proto::PROTOCOL_VERSION;
+use quad_net::quad_socket::server::{listen, Settings, SocketHandle};
+use capture::{Capture, CaptureHeader};
+use config::Config;
+use machine::{Event, Outbound, Server};
+use metrics::Metrics;
+use rating::RatingStore;
+use recorder::MatchStore;
+use status::Status;
+use world::World;
+
+mod capture;
+mod chat;
+mod config;
+mod limit;
+mod machine;
+mod matchmaking;
+mod metrics;
+mod rating;
+mod recorder;
+mod room;
+mod status;
+mod world;
+
+const RATINGS_PATH: &str = "ratings.json";
+const MATCHES_DIR: &str = "matches";
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
 
 #[derive(Default)]
 struct ClientState {
-    id: Option<u32>,
-}
-
-struct WorldClientState {
-    field: Field,
-    queued_messages: VecDeque<ServerToClient>,
-    inputs: Inputs,
-    provider: NetworkInputProvider,
-    tick: u64,
+    connection: Cell<Option<u64>>,
 }
 
-struct World {
-    clients: HashMap<u32, WorldClientState>,
+struct Host {
+    server: Server,
+    capture: Option<Capture>,
+    metrics: Metrics,
+    started: Instant,
+    next_connection: u64,
 }
 
-struct NetworkInputProvider {
-    just_pressed: HashSet<Input>,
-    current: HashSet<Input>,
-}
-impl InputProvider for NetworkInputProvider {
-    fn peek(&mut self) {
-    }
-    fn consume(&mut self) {
-        self.just_pressed.clear();
-    }
-    fn key_just_pressed(&self, input: Input) -> bool {
-        self.just_pressed.contains(&input)
-    }
-    fn key_down(&self, input: Input) -> bool {
-        self.current.contains(&input)
+impl Host {
+    fn admin(&mut self, command: &str) -> String {
+        let now = self.started.elapsed();
+        let reply = self.server.admin(now, command);
+        if let Some(capture) = self.capture.as_mut() {
+            if let Err(error) = capture.record(0, now, &Event::Admin { command: command.to_string() }, &[]) {
+                eprintln!("stopped capturing: {}", error);
+                self.capture = None;
+            }
+        }
+        reply
     }
-}
+    fn feed(&mut self, connection: u64, now: Duration, event: Event) -> Vec<Outbound> {
+        let started = Instant::now();
+        let ticks = self.server.advance(now);
+        self.metrics.ticked(ticks, started.elapsed());
 
-#[derive(Clone, Copy)]
-struct DummyImpl;
-impl Cubes for DummyImpl {
-    fn spawn_cube(&mut self, _x: i32, _y: i32, _color: logic::well::Block) {
-    }
-}
-impl Sounds for DummyImpl {
-    fn block_spawn(&mut self, _color: logic::well::Block) {
-    }
-    fn line_clear(&mut self) {
+        let outbound = self.server.handle(connection, now, &event);
+        match event {
+        Event::Connect {} => self.metrics.connected(connection),
+        Event::Message { .. } => self.metrics.received(connection),
+        Event::Disconnect {} => self.metrics.disconnected(connection),
+        Event::Timer {} | Event::Admin { .. } => {}
+        }
+        self.metrics.sent(outbound.iter().filter(|it| matches!(it, Outbound::Send { .. })).count());
+        self.metrics.roll(now);
+        if let Some(capture) = self.capture.as_mut() {
+            if let Err(error) = capture.record(connection, now, &event, &outbound) {
+                eprintln!("stopped capturing: {}", error);
+                self.capture = None;
+            }
+        }
+        outbound
     }
-    fn lock(&mut self) {
+    fn status(&self) -> Status {
+        let world = self.server.world();
+        let rooms = world.rooms();
+        Status {
+            uptime: self.started.elapsed().as_secs_f64(),
+            tick: world.tick(),
+            connected_clients: self.server.connection_count() as u32,
+            sessions: world.clients().len() as u32,
+            players: rooms.iter().map(|it| it.players).sum(),
+            spectators: rooms.iter().map(|it| it.spectators).sum(),
+            rooms,
+            messages_received: self.metrics.messages_received,
+            messages_sent: self.metrics.messages_sent,
+            message_rate: self.metrics.message_rate.clone(),
+            tick_duration: self.metrics.tick_duration.clone(),
+        }
     }
-    fn land(&mut self) {
+    // quad_net has no connect callback, so a connection is introduced to the server the first
+    // time anything happens on it
+    fn handle(&mut self, state: &ClientState, event: Event) -> Vec<Outbound> {
+        let now = self.started.elapsed();
+        let connection = match state.connection.get() {
+            Some(connection) => connection,
+            None => {
+                self.next_connection += 1;
+                state.connection.set(Some(self.next_connection));
+                self.feed(self.next_connection, now, Event::Connect {});
+                self.next_connection
+            }
+        };
+        self.feed(connection, now, event)
     }
 }
 
-impl World {
-    fn enqueue_message_excluding(&mut self, id: u32, message: ServerToClient) {
-        for (client, state) in &mut self.clients {
-            if *client == id {
-                continue;
+fn deliver(out: &mut SocketHandle, outbound: Vec<Outbound>) {
+    for it in outbound {
+        match it.encode() {
+        Some(bytes) => {
+            if out.send(&bytes).is_err() {
+                break;
             }
-            state.queued_messages.push_back(message.clone());
         }
-    }
-    fn enqueue_message_to(&mut self, id: u32, message: ServerToClient) {
-        if let Some(state) = self.clients.get_mut(&id) {
-            state.queued_messages.push_back(message.clone());
+        None => out.disconnect(),
         }
     }
-    fn dequeue_messages_for(&mut self, id: u32) -> VecDeque<ServerToClient> {
-        if let Some(state) = self.clients.get_mut(&id) {
-            let ret = state.queued_messages.clone();
-            state.queued_messages.clear();
-            ret
-        } else {
-            VecDeque::new()
+}
+
+// admin commands are read line by line from stdin; after a shutdown the process waits for every
+// client to be told and dropped before it exits
+fn console(host: Arc<Mutex<Host>>) {
+    for line in std::io::stdin().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let reply = host.lock().unwrap().admin(&line);
+        println!("{}", reply);
+        if host.lock().unwrap().server.is_shutting_down() {
+            break;
         }
     }
-    fn join(&mut self, client_id: u32) {
-        self.clients.insert(client_id, WorldClientState {
-            field: Field::new(),
-            queued_messages: VecDeque::new(),
-            inputs: Inputs::new(),
-            provider: NetworkInputProvider { just_pressed: HashSet::new(), current: HashSet::new() },
-            tick: 0,
-        });
-
-        let clients = self.clients.iter().map(|client| { (client.0.clone(), client.1.field.clone()) } ).collect::<Vec<_>>();
-        for (client, field) in clients {
-            if client == client_id {
-                continue;
-            }
-            self.enqueue_message_to(client_id, ServerToClient::Join { client_id: client, field });
-        }
-        self.enqueue_message_excluding(client_id, ServerToClient::Join { client_id, field: self.clients[&client_id].field.clone() });
+    if !host.lock().unwrap().server.is_shutting_down() {
+        return;
     }
-    fn leave(&mut self, client_id: u32) {
-        self.clients.remove(&client_id);
-        self.enqueue_message_excluding(client_id, ServerToClient::Leave { client_id });
+
+    let started = Instant::now();
+    while host.lock().unwrap().server.connection_count() > 0 && started.elapsed() < SHUTDOWN_GRACE {
+        std::thread::sleep(Duration::from_millis(50));
     }
-    fn input(&mut self, client_id: u32, input: Input, up: bool) {
-        self.enqueue_message_excluding(client_id, ServerToClient::Input { client_id, input, up });
-        if let Some(state) = self.clients.get_mut(&client_id) {
-            if up {
-                state.provider.just_pressed.insert(input);
-                state.provider.current.insert(input);
-            } else {
-                state.provider.just_pressed.remove(&input);
-                state.provider.current.remove(&input);
-            }
+    std::process::exit(0);
+}
+
+fn main() {
+    let arg = |name: &str| std::env::args().skip_while(|it| it != name).nth(1);
+    if let Some(path) = arg("--replay") {
+        match capture::replay(Path::new(&path), PathBuf::from(MATCHES_DIR), std::env::args().any(|it| it == "--dump")) {
+        Ok(count) => eprintln!("replayed {} events and every response matched", count),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
         }
-    }
-    fn tick(&mut self, client_id: u32) {
-        let mut a = DummyImpl;
-        let mut b = DummyImpl;
-        if let Some(state) = self.clients.get_mut(&client_id) {
-            state.inputs.tick(state.tick, &mut state.provider);
-            state.field.update(&state.inputs, &mut a, &mut b);
-            state.tick += 1;
-            self.enqueue_message_excluding(client_id, ServerToClient::Tick { client_id });
         }
+        return;
     }
-}
 
-fn main() {
-    let world = Arc::new(Mutex::new(World {
-        clients: HashMap::new(),
+    let config = Config::from_args(&std::env::args().collect::<Vec<_>>()).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    });
+    let ratings = RatingStore::load(PathBuf::from(RATINGS_PATH));
+    let matches = MatchStore::load(PathBuf::from(MATCHES_DIR));
+    let token_seed = RandomState::new().hash_one(0u64);
+    let start_date = SystemTime::now().duration_since(UNIX_EPOCH).map(|it| it.as_secs()).unwrap_or(0);
+    let capture = arg("--capture").map(|path| {
+        let header = CaptureHeader {
+            version: PROTOCOL_VERSION,
+            config: config.clone(),
+            token_seed,
+            start_date,
+            ratings: ratings.to_json(),
+            matches: matches.index_json(),
+        };
+        Capture::create(Path::new(&path), &header).unwrap_or_else(|error| panic!("couldn't create capture {}: {}", path, error))
+    });
+    let host = Arc::new(Mutex::new(Host {
+        server: Server::new(World::new(config.clone(), ratings, matches, token_seed, start_date)),
+        capture,
+        metrics: Metrics::new(),
+        started: Instant::now(),
+        next_connection: 0,
     }));
+
+    std::thread::spawn({
+        let host = host.clone();
+        move || console(host)
+    });
+    match TcpListener::bind(&config.status_address) {
+    Ok(listener) => {
+        let host = host.clone();
+        std::thread::spawn(move || status::serve(listener, move || host.lock().unwrap().status()));
+    }
+    Err(error) => eprintln!("couldn't serve status on {}: {}", config.status_address, error),
+    }
     listen(
-        "0.0.0.0:8088",
-        "0.0.0.0:6507",
+        config.tcp_address.clone(),
+        config.websocket_address.clone(),
         Settings {
             on_message: {
-                let world = world.clone();
-                move |_out, state: &mut ClientState, msg| {
-                    let msg = ClientToServer::deserialize_json(str::from_utf8(&msg).unwrap()).unwrap();
-
-                    match msg {
-                    ClientToServer::Join { client_id } => {
-                        if state.id.is_none() {
-                            state.id = Some(client_id);
-                            world.lock().unwrap().join(client_id);
-                        }
-                    }
-                    ClientToServer::Input { input, up } => {
-                        if let Some(id) = state.id {
-                            world.lock().unwrap().input(id, input, up);
-                        }
-                    }
-                    ClientToServer::Tick {} => {
-                        if let Some(id) = state.id {
-                            world.lock().unwrap().tick(id);
-                        }
-                    }
-                    }
+                let host = host.clone();
+                move |out, state: &mut ClientState, msg| {
+                    let outbound = host.lock().unwrap().handle(state, Event::Message { bytes: msg });
+                    deliver(out, outbound);
                 }
             },
             on_timer: {
-                let world = world.clone();
+                let host = host.clone();
                 move |out, state| {
-                    if let Some(id) = state.id {
-                        let messages = world.lock().unwrap().dequeue_messages_for(id);
-                        for msg in messages {
-                            out.send(msg.serialize_json().as_bytes()).unwrap();
-                        }
-                    }
+                    let outbound = host.lock().unwrap().handle(state, Event::Timer {});
+                    deliver(out, outbound);
                 }
             },
             on_disconnect: {
-                let world = world.clone();
+                let host = host.clone();
                 move |state| {
-                    if let Some(id) = state.id {
-                        world.lock().unwrap().leave(id);
+                    if state.connection.get().is_some() {
+                        host.lock().unwrap().handle(state, Event::Disconnect {});
                     }
                 }
             },
-            timer: Some(Duration::from_secs_f64(1. / 60.)),
+            timer: Some(Duration::from_secs_f64(1. / config.ticks_per_second)),
             _marker: std::marker::PhantomData,
         },
     );
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{Read, Write}, net::TcpStream};
+
+    use nanoserde::DeJson;
+
+    use logic::{proto::{encode_client, ClientToServer}, wire::WireFormat};
+
+    use super::*;
+
+    fn get(address: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn metric(metrics: &str, name: &str) -> f64 {
+        metrics.lines().find_map(|it| it.strip_prefix(name)?.strip_prefix(' ')).unwrap().parse().unwrap()
+    }
+
+    // two players and a spectator in one room, after a second and a half of play
+    fn host() -> Host {
+        let world = World::new(Config::new(), RatingStore::from_json(""), MatchStore::from_index(PathBuf::new(), ""), 1, 0);
+        let mut host = Host { server: Server::new(world), capture: None, metrics: Metrics::new(), started: Instant::now(), next_connection: 0 };
+        let joined = [
+            ClientToServer::CreateRoom { name: "room".to_string(), capacity: 2, attack_table: None },
+            ClientToServer::JoinRoom { name: "room".to_string() },
+            ClientToServer::Spectate { name: "room".to_string() },
+        ];
+        for (connection, msg) in (1..).zip(joined) {
+            let now = Duration::from_millis(100 * connection);
+            host.feed(connection, now, Event::Connect {});
+            host.feed(connection, now, Event::Message { bytes: encode_client(&ClientToServer::Hello { version: PROTOCOL_VERSION, format: WireFormat::Binary }, WireFormat::Json) });
+            host.feed(connection, now, Event::Message { bytes: encode_client(&ClientToServer::Join { token: None }, WireFormat::Binary) });
+            host.feed(connection, now, Event::Message { bytes: encode_client(&msg, WireFormat::Binary) });
+        }
+        for tick in 1..=90 {
+            for connection in 1..=3 {
+                host.feed(connection, Duration::from_micros(tick * 1_000_000 / 60 + 1000), Event::Timer {});
+            }
+        }
+        host
+    }
+
+    #[test]
+    fn status_is_served_as_prometheus_and_json() {
+        let host = Arc::new(Mutex::new(host()));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || status::serve(listener, move || host.lock().unwrap().status()));
+
+        let metrics = get(address, "/metrics");
+        assert!(metrics.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(metric(&metrics, "server_connected_clients"), 3.);
+        assert_eq!(metric(&metrics, "server_sessions"), 3.);
+        assert_eq!(metric(&metrics, "server_rooms"), 1.);
+        assert_eq!(metric(&metrics, "server_players"), 2.);
+        assert_eq!(metric(&metrics, "server_spectators"), 1.);
+        assert_eq!(metric(&metrics, "server_messages_received_total"), 9.);
+
+        // each connection sent three messages in the first one second window
+        assert_eq!(metric(&metrics, "server_client_message_rate_bucket{le=\"2\"}"), 0.);
+        assert_eq!(metric(&metrics, "server_client_message_rate_bucket{le=\"5\"}"), 3.);
+        assert_eq!(metric(&metrics, "server_client_message_rate_bucket{le=\"+Inf\"}"), 3.);
+        assert_eq!(metric(&metrics, "server_client_message_rate_count"), 3.);
+        let ticks = metric(&metrics, "server_tick");
+        assert!(ticks >= 90.);
+        assert_eq!(metric(&metrics, "server_tick_duration_seconds_bucket{le=\"+Inf\"}"), metric(&metrics, "server_tick_duration_seconds_count"));
+        assert!(metric(&metrics, "server_tick_duration_seconds_bucket{le=\"0.01\"}") <= metric(&metrics, "server_tick_duration_seconds_count"));
+
+        let response = get(address, "/status");
+        assert!(response.contains("Content-Type: application/json"));
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = Status::deserialize_json(body).unwrap();
+        assert_eq!((status.connected_clients, status.sessions, status.players, status.spectators), (3, 3, 2, 1));
+        assert_eq!(status.rooms.iter().map(|it| it.name.as_str()).collect::<Vec<_>>(), vec!["room"]);
+        assert_eq!(status.messages_received, 9);
+        assert_eq!(status.tick as f64, ticks);
+        assert_eq!(status.message_rate.bounds, vec![1., 2., 5., 10., 20., 30., 60., 120.]);
+        assert_eq!(status.message_rate.counts, vec![0, 0, 3, 0, 0, 0, 0, 0]);
+        assert_eq!(status.message_rate.count, 3);
+        assert!(status.tick_duration.count > 0 && status.tick_duration.counts.iter().sum::<u64>() <= status.tick_duration.count);
+        assert!(get(address, "/nope").starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,114 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+pub const BASE_WINDOW: f64 = 50.;
+pub const WINDOW_GROWTH_PER_SECOND: f64 = 10.;
+pub const MAX_WINDOW: f64 = 500.;
+
+pub struct QueueEntry {
+    pub client_id: u32,
+    pub rating: f64,
+    pub queued_at: u64,
+}
+
+pub struct MatchQueue {
+    entries: Vec<QueueEntry>,
+}
+
+impl MatchQueue {
+    pub fn new() -> MatchQueue {
+        MatchQueue {
+            entries: vec![],
+        }
+    }
+    pub fn push(&mut self, entry: QueueEntry) {
+        self.remove(entry.client_id);
+        self.entries.push(entry);
+    }
+    pub fn remove(&mut self, client_id: u32) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|it| it.client_id != client_id);
+        self.entries.len() != len
+    }
+    pub fn window(entry: &QueueEntry, tick: u64, ticks_per_second: f64) -> f64 {
+        let waited = tick.saturating_sub(entry.queued_at) as f64 / ticks_per_second;
+        (BASE_WINDOW + waited * WINDOW_GROWTH_PER_SECOND).min(MAX_WINDOW)
+    }
+    pub fn contains(&self, client_id: u32) -> bool {
+        self.entries.iter().any(|it| it.client_id == client_id)
+    }
+    pub fn clients(&self) -> impl Iterator<Item = u32> + '_ {
+        self.entries.iter().map(|it| it.client_id)
+    }
+    // the longest waiting players get first pick of the closest rating that both windows accept;
+    // entries that aren't available right now keep their place for later
+    pub fn pair(&mut self, tick: u64, ticks_per_second: f64, available: impl Fn(u32) -> bool) -> Vec<(u32, u32)> {
+        self.entries.sort_by_key(|it| it.queued_at);
+        let mut pairs = vec![];
+        let mut matched = vec![false; self.entries.len()];
+        let skipped = self.entries.iter().map(|it| !available(it.client_id)).collect::<Vec<_>>();
+        for idx in 0..self.entries.len() {
+            if matched[idx] || skipped[idx] {
+                continue;
+            }
+            let entry = &self.entries[idx];
+            let window = MatchQueue::window(entry, tick, ticks_per_second);
+            let best = self.entries.iter().enumerate()
+                .filter(|(other, _)| *other != idx && !matched[*other] && !skipped[*other])
+                .filter(|(_, it)| {
+                    let distance = (it.rating - entry.rating).abs();
+                    distance <= window && distance <= MatchQueue::window(it, tick, ticks_per_second)
+                })
+                .min_by(|(_, a), (_, b)| (a.rating - entry.rating).abs().total_cmp(&(b.rating - entry.rating).abs()))
+                .map(|(other, _)| other);
+            if let Some(other) = best {
+                matched[idx] = true;
+                matched[other] = true;
+                pairs.push((entry.client_id, self.entries[other].client_id));
+            }
+        }
+        let mut idx = 0;
+        self.entries.retain(|_| {
+            idx += 1;
+            !matched[idx - 1]
+        });
+        pairs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(entries: &[(u32, f64, u64)]) -> MatchQueue {
+        let mut queue = MatchQueue::new();
+        for (client_id, rating, queued_at) in entries {
+            queue.push(QueueEntry { client_id: *client_id, rating: *rating, queued_at: *queued_at });
+        }
+        queue
+    }
+
+    #[test]
+    fn closest_ratings_are_paired_first() {
+        let mut queue = queue(&[(1, 1500., 0), (2, 1540., 0), (3, 1510., 0), (4, 1545., 0)]);
+        assert_eq!(queue.pair(0, 60., |_| true), vec![(1, 3), (2, 4)]);
+        assert!(!queue.contains(1));
+    }
+
+    #[test]
+    fn windows_widen_while_waiting() {
+        let mut queue = queue(&[(1, 1500., 0), (2, 1600., 0)]);
+        assert_eq!(queue.pair(0, 60., |_| true), vec![]);
+        assert_eq!(queue.pair(4 * 60, 60., |_| true), vec![]);
+        assert_eq!(queue.pair(5 * 60, 60., |_| true), vec![(1, 2)]);
+        assert_eq!(queue.clients().count(), 0);
+    }
+
+    #[test]
+    fn unavailable_entries_stay_queued() {
+        let mut queue = queue(&[(1, 1500., 0), (2, 1500., 1), (3, 1500., 2)]);
+        assert_eq!(queue.pair(0, 60., |it| it != 1), vec![(2, 3)]);
+        assert_eq!(queue.clients().collect::<Vec<_>>(), vec![1]);
+    }
+}
@@ -0,0 +1,104 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+use nanoserde::{DeJson, SerJson};
+
+const MESSAGE_RATE_BUCKETS: &[f64] = &[1., 2., 5., 10., 20., 30., 60., 120.];
+const TICK_DURATION_BUCKETS: &[f64] = &[0.00001, 0.000025, 0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01];
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(SerJson, DeJson, Clone)]
+pub struct Histogram {
+    pub bounds: Vec<f64>,
+    pub counts: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &[f64]) -> Histogram {
+        Histogram {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len()],
+            sum: 0.,
+            count: 0,
+        }
+    }
+    pub fn observe(&mut self, value: f64) {
+        if let Some(idx) = self.bounds.iter().position(|it| value <= *it) {
+            self.counts[idx] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+    // prometheus buckets are cumulative and end with +Inf, which is just the total count
+    pub fn write_prometheus(&self, name: &str, help: &str, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count);
+    }
+}
+
+// message rates are sampled once a second for every open connection, including the quiet ones
+pub struct Metrics {
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    pub message_rate: Histogram,
+    pub tick_duration: Histogram,
+    window: BTreeMap<u64, u32>,
+    window_start: Duration,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            messages_received: 0,
+            messages_sent: 0,
+            message_rate: Histogram::new(MESSAGE_RATE_BUCKETS),
+            tick_duration: Histogram::new(TICK_DURATION_BUCKETS),
+            window: BTreeMap::new(),
+            window_start: Duration::ZERO,
+        }
+    }
+    pub fn connected(&mut self, connection: u64) {
+        self.window.insert(connection, 0);
+    }
+    pub fn disconnected(&mut self, connection: u64) {
+        self.window.remove(&connection);
+    }
+    pub fn received(&mut self, connection: u64) {
+        self.messages_received += 1;
+        if let Some(count) = self.window.get_mut(&connection) {
+            *count += 1;
+        }
+    }
+    pub fn sent(&mut self, count: usize) {
+        self.messages_sent += count as u64;
+    }
+    pub fn ticked(&mut self, ticks: u64, elapsed: Duration) {
+        for _ in 0..ticks {
+            self.tick_duration.observe(elapsed.as_secs_f64() / ticks as f64);
+        }
+    }
+    pub fn roll(&mut self, now: Duration) {
+        let elapsed = now.saturating_sub(self.window_start);
+        if elapsed < RATE_WINDOW {
+            return;
+        }
+        for count in self.window.values_mut() {
+            self.message_rate.observe(*count as f64 / elapsed.as_secs_f64());
+            *count = 0;
+        }
+        self.window_start = now;
+    }
+}
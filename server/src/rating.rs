@@ -0,0 +1,231 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{cmp::Ordering, collections::HashMap, f64::consts::PI, path::PathBuf};
+
+use logic::proto::PlayerRating;
+use nanoserde::{DeJson, SerJson};
+
+const SCALE: f64 = 173.7178;
+const TAU: f64 = 0.5;
+const EPSILON: f64 = 0.000001;
+
+#[derive(SerJson, DeJson, Clone, Copy, Debug)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+    pub games: u32,
+}
+
+impl Rating {
+    pub fn new() -> Rating {
+        Rating {
+            rating: 1500.,
+            deviation: 350.,
+            volatility: 0.06,
+            games: 0,
+        }
+    }
+    pub fn info(&self, player: &str) -> PlayerRating {
+        PlayerRating {
+            player: player.to_string(),
+            rating: self.rating,
+            deviation: self.deviation,
+            games: self.games,
+        }
+    }
+    // one glicko-2 rating period containing the given (opponent, score) results
+    pub fn update(&self, results: &[(Rating, f64)]) -> Rating {
+        let mu = (self.rating - 1500.) / SCALE;
+        let phi = self.deviation / SCALE;
+        if results.is_empty() {
+            let phi = (phi * phi + self.volatility * self.volatility).sqrt();
+            return Rating { deviation: (phi * SCALE).min(350.), ..*self };
+        }
+
+        let g = |phi: f64| 1. / (1. + 3. * phi * phi / (PI * PI)).sqrt();
+        let outcomes = results.iter().map(|(opponent, score)| {
+            let mu_j = (opponent.rating - 1500.) / SCALE;
+            let g_j = g(opponent.deviation / SCALE);
+            let expected = 1. / (1. + (-g_j * (mu - mu_j)).exp());
+            (g_j, expected, *score)
+        }).collect::<Vec<_>>();
+
+        let v = 1. / outcomes.iter().map(|(g_j, e, _)| g_j * g_j * e * (1. - e)).sum::<f64>();
+        let improvement = outcomes.iter().map(|(g_j, e, s)| g_j * (s - e)).sum::<f64>();
+        let delta = v * improvement;
+
+        let a = (self.volatility * self.volatility).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            ex * (delta * delta - phi * phi - v - ex) / (2. * (phi * phi + v + ex).powi(2)) - (x - a) / (TAU * TAU)
+        };
+        let mut big_a = a;
+        let mut big_b = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.;
+            while f(a - k * TAU) < 0. {
+                k += 1.;
+            }
+            a - k * TAU
+        };
+        let mut f_a = f(big_a);
+        let mut f_b = f(big_b);
+        while (big_b - big_a).abs() > EPSILON {
+            let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+            let f_c = f(big_c);
+            if f_c * f_b <= 0. {
+                big_a = big_b;
+                f_a = f_b;
+            } else {
+                f_a /= 2.;
+            }
+            big_b = big_c;
+            f_b = f_c;
+        }
+        let volatility = (big_a / 2.).exp();
+
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let phi = 1. / (1. / (phi_star * phi_star) + 1. / v).sqrt();
+        let mu = mu + phi * phi * improvement;
+        Rating {
+            rating: mu * SCALE + 1500.,
+            deviation: (phi * SCALE).min(350.),
+            volatility,
+            games: self.games + results.len() as u32,
+        }
+    }
+}
+
+#[derive(SerJson, DeJson)]
+struct StoredRatings {
+    players: HashMap<String, Rating>,
+    #[nserde(default)]
+    keys: HashMap<String, String>,
+}
+
+// without a path the store only lives in memory, which is what replaying a captured session uses;
+// a name belongs to whoever first identified with it, and only their key can use it again
+pub struct RatingStore {
+    players: HashMap<String, Rating>,
+    keys: HashMap<String, String>,
+    path: Option<PathBuf>,
+}
+
+impl RatingStore {
+    pub fn load(path: PathBuf) -> RatingStore {
+        let mut store = RatingStore::from_json(&std::fs::read_to_string(&path).unwrap_or_default());
+        store.path = Some(path);
+        store
+    }
+    pub fn from_json(contents: &str) -> RatingStore {
+        let (players, keys) = StoredRatings::deserialize_json(contents).ok()
+            .map(|it| (it.players, it.keys))
+            .unwrap_or_default();
+        RatingStore { players, keys, path: None }
+    }
+    pub fn to_json(&self) -> String {
+        StoredRatings { players: self.players.clone(), keys: self.keys.clone() }.serialize_json()
+    }
+    fn save(&self, stored: StoredRatings) -> Result<(), String> {
+        if let Some(ref path) = self.path {
+            let temp = path.with_extension("tmp");
+            std::fs::write(&temp, stored.serialize_json()).map_err(|e| e.to_string())?;
+            std::fs::rename(&temp, path).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+    pub fn key(&self, player: &str) -> Option<String> {
+        self.keys.get(player).cloned()
+    }
+    pub fn claim(&mut self, player: &str, key: String) -> Result<(), String> {
+        let mut keys = self.keys.clone();
+        keys.insert(player.to_string(), key);
+        self.save(StoredRatings { players: self.players.clone(), keys: keys.clone() })?;
+        self.keys = keys;
+        Ok(())
+    }
+    pub fn get(&self, player: &str) -> Rating {
+        self.players.get(player).cloned().unwrap_or_else(Rating::new)
+    }
+    // every player is rated against the others from their ratings before the match, winning
+    // against anyone who scored lower, and nothing changes in memory unless the new ratings made
+    // it to disk
+    pub fn record_match(&mut self, players: &[(String, f64)]) -> Result<Vec<(String, Rating, Rating)>, String> {
+        let changes = players.iter().map(|(player, score)| {
+            let results = players.iter()
+                .filter(|(other, _)| other != player)
+                .map(|(other, other_score)| {
+                    let result = match score.total_cmp(other_score) {
+                    Ordering::Greater => 1.,
+                    Ordering::Less => 0.,
+                    Ordering::Equal => 0.5,
+                    };
+                    (self.get(other), result)
+                })
+                .collect::<Vec<_>>();
+            let before = self.get(player);
+            (player.clone(), before, before.update(&results))
+        }).collect::<Vec<_>>();
+
+        let mut players = self.players.clone();
+        for (player, _, after) in &changes {
+            players.insert(player.clone(), *after);
+        }
+        self.save(StoredRatings { players: players.clone(), keys: self.keys.clone() })?;
+        self.players = players;
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> Rating {
+        Rating { rating, deviation, ..Rating::new() }
+    }
+
+    // the worked example from Glickman's "Example of the Glicko-2 system"
+    #[test]
+    fn update_matches_the_paper() {
+        let after = rating(1500., 200.).update(&[(rating(1400., 30.), 1.), (rating(1550., 100.), 0.), (rating(1700., 300.), 0.)]);
+        assert!((after.rating - 1464.06).abs() < 0.01, "{:?}", after);
+        assert!((after.deviation - 151.52).abs() < 0.01, "{:?}", after);
+        assert!((after.volatility - 0.05999).abs() < 0.00001, "{:?}", after);
+        assert_eq!(after.games, 3);
+    }
+
+    #[test]
+    fn idle_players_grow_less_certain() {
+        let after = rating(1500., 50.).update(&[]);
+        assert_eq!(after.rating, 1500.);
+        assert!(after.deviation > 50.);
+        assert_eq!(rating(1500., 350.).update(&[]).deviation, 350.);
+    }
+
+    #[test]
+    fn matches_are_scored_against_each_opponent() {
+        let mut store = RatingStore::from_json("");
+        let changes = store.record_match(&[("a".to_string(), 1.), ("b".to_string(), 0.), ("c".to_string(), 0.)]).unwrap();
+        assert!(changes[0].2.rating > 1500.);
+        assert!(changes[1].2.rating < 1500.);
+        assert_eq!(changes[1].2.rating, changes[2].2.rating);
+        assert_eq!(store.get("a").games, 2);
+    }
+
+    #[test]
+    fn keys_survive_a_round_trip() {
+        let mut store = RatingStore::from_json("");
+        store.claim("janet", "secret".to_string()).unwrap();
+        store.record_match(&[("janet".to_string(), 1.), ("guest".to_string(), 0.)]).unwrap();
+        let store = RatingStore::from_json(&store.to_json());
+        assert_eq!(store.key("janet"), Some("secret".to_string()));
+        assert_eq!(store.key("guest"), None);
+        assert_eq!(store.get("janet").games, 1);
+        assert_eq!(RatingStore::from_json(r#"{"players":{}}"#).key("janet"), None);
+    }
+}
@@ -0,0 +1,108 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{collections::HashMap, path::{Path, PathBuf}};
+
+use logic::recording::{MatchRecord, MatchSummary};
+use nanoserde::{DeJson, SerJson};
+
+#[derive(SerJson, DeJson)]
+struct MatchIndex {
+    matches: Vec<MatchSummary>,
+}
+
+// each match is written to <dir>/<id>.json, and index.json keeps the summaries so listing does not
+// have to read every replay; a store that doesn't persist keeps new matches in memory and only
+// reads from the directory
+pub struct MatchStore {
+    dir: PathBuf,
+    matches: Vec<MatchSummary>,
+    persist: bool,
+    unsaved: HashMap<u64, MatchRecord>,
+}
+
+fn write_atomically(path: &Path, contents: &str) -> Result<(), String> {
+    let temp = path.with_extension("tmp");
+    std::fs::write(&temp, contents).map_err(|e| e.to_string())?;
+    std::fs::rename(&temp, path).map_err(|e| e.to_string())
+}
+
+impl MatchStore {
+    pub fn load(dir: PathBuf) -> MatchStore {
+        let mut store = MatchStore::from_index(dir.clone(), &std::fs::read_to_string(dir.join("index.json")).unwrap_or_default());
+        store.persist = true;
+        store
+    }
+    pub fn from_index(dir: PathBuf, index: &str) -> MatchStore {
+        let matches = MatchIndex::deserialize_json(index).ok()
+            .map(|it| it.matches)
+            .unwrap_or_default();
+        MatchStore { dir, matches, persist: false, unsaved: HashMap::new() }
+    }
+    pub fn index_json(&self) -> String {
+        MatchIndex { matches: self.matches.clone() }.serialize_json()
+    }
+    pub fn list(&self) -> &[MatchSummary] {
+        &self.matches
+    }
+    pub fn save(&mut self, mut record: MatchRecord) -> Result<MatchSummary, String> {
+        record.id = self.matches.iter().map(|it| it.id).max().unwrap_or(0) + 1;
+        let summary = record.summary();
+        let mut matches = self.matches.clone();
+        matches.push(summary.clone());
+
+        if self.persist {
+            std::fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+            write_atomically(&self.dir.join(format!("{}.json", record.id)), &record.serialize_json())?;
+            write_atomically(&self.dir.join("index.json"), &MatchIndex { matches: matches.clone() }.serialize_json())?;
+        } else {
+            self.unsaved.insert(record.id, record);
+        }
+        self.matches = matches;
+        Ok(summary)
+    }
+    pub fn get(&self, id: u64) -> Result<MatchRecord, String> {
+        if !self.matches.iter().any(|it| it.id == id) {
+            return Err("no such match".to_string());
+        }
+        if let Some(record) = self.unsaved.get(&id) {
+            return Ok(record.clone());
+        }
+        let contents = std::fs::read_to_string(self.dir.join(format!("{}.json", id))).map_err(|e| e.to_string())?;
+        MatchRecord::deserialize_json(&contents).map_err(|e| format!("{:?}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use logic::{garbage::AttackTable, input::Input};
+
+    use super::*;
+
+    fn record() -> MatchRecord {
+        let mut record = MatchRecord::new("room", 1_700_000_000, 42, AttackTable::new(), true, vec![(1, Some("janet".to_string())), (2, None)]);
+        record.record_input(1, 10, Input::Left, false);
+        record.record_input(1, 14, Input::Left, true);
+        record.record_garbage(2, 30, 3);
+        record.winner = Some(1);
+        record.ticks = 600;
+        record
+    }
+
+    #[test]
+    fn saved_matches_are_there_after_reloading() {
+        let dir = std::env::temp_dir().join(format!("matches-{}", std::process::id()));
+        let mut store = MatchStore::load(dir.clone());
+        let summary = store.save(record()).unwrap();
+        let mut expected = record();
+        expected.id = summary.id;
+        assert_eq!(summary.serialize_json(), expected.summary().serialize_json());
+
+        let reloaded = MatchStore::load(dir.clone());
+        assert_eq!(reloaded.list().len(), 1);
+        assert_eq!(reloaded.list()[0].serialize_json(), summary.serialize_json());
+        assert_eq!(reloaded.get(summary.id).unwrap().serialize_json(), expected.serialize_json());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
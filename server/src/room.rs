@@ -0,0 +1,56 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use logic::{clock::TICKS_PER_SECOND, garbage::AttackTable, proto::RoomInfo, recording::MatchRecord};
+
+pub const MAX_ROOM_NAME: usize = 32;
+pub const COUNTDOWN_TICKS: u64 = 3 * TICKS_PER_SECOND;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomState {
+    Waiting,
+    Countdown { start_tick: u64 },
+    Playing,
+}
+
+pub struct Room {
+    pub name: String,
+    pub capacity: u32,
+    pub members: Vec<u32>,
+    pub spectators: Vec<u32>,
+    pub state: RoomState,
+    pub attack_table: AttackTable,
+    pub ranked: Option<Vec<(u32, String)>>,
+    pub recording: Option<MatchRecord>,
+}
+
+impl Room {
+    pub fn new(name: &str, capacity: u32, attack_table: AttackTable) -> Room {
+        Room {
+            name: name.to_string(),
+            capacity,
+            members: vec![],
+            spectators: vec![],
+            state: RoomState::Waiting,
+            attack_table,
+            ranked: None,
+            recording: None,
+        }
+    }
+    pub fn opponents(&self, client_id: u32) -> impl Iterator<Item = u32> + '_ {
+        self.members.iter().cloned().filter(move |it| *it != client_id)
+    }
+    pub fn is_full(&self) -> bool {
+        self.members.len() as u32 >= self.capacity
+    }
+    pub fn info(&self) -> RoomInfo {
+        RoomInfo {
+            name: self.name.clone(),
+            players: self.members.len() as u32,
+            capacity: self.capacity,
+            spectators: self.spectators.len() as u32,
+            playing: self.state != RoomState::Waiting,
+        }
+    }
+}
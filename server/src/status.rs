@@ -0,0 +1,87 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{fmt::Write as _, io::{Read, Write}, net::{TcpListener, TcpStream}, time::Duration};
+
+use logic::proto::RoomInfo;
+use nanoserde::{DeJson, SerJson};
+
+use crate::metrics::Histogram;
+
+const MAX_REQUEST_SIZE: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(SerJson, DeJson)]
+pub struct Status {
+    pub uptime: f64,
+    pub tick: u64,
+    pub connected_clients: u32,
+    pub sessions: u32,
+    pub players: u32,
+    pub spectators: u32,
+    pub rooms: Vec<RoomInfo>,
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    pub message_rate: Histogram,
+    pub tick_duration: Histogram,
+}
+
+impl Status {
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        let values = [
+            ("server_uptime_seconds", "gauge", "Seconds since the server started", self.uptime),
+            ("server_tick", "gauge", "Current simulation tick", self.tick as f64),
+            ("server_connected_clients", "gauge", "Open client connections", self.connected_clients as f64),
+            ("server_sessions", "gauge", "Sessions including ones waiting to be resumed", self.sessions as f64),
+            ("server_rooms", "gauge", "Open rooms", self.rooms.len() as f64),
+            ("server_players", "gauge", "Players in rooms", self.players as f64),
+            ("server_spectators", "gauge", "Spectators in rooms", self.spectators as f64),
+            ("server_messages_received_total", "counter", "Messages received from clients", self.messages_received as f64),
+            ("server_messages_sent_total", "counter", "Messages sent to clients", self.messages_sent as f64),
+        ];
+        for (name, kind, help, value) in values {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        self.message_rate.write_prometheus("server_client_message_rate", "Messages per second received from each connection", &mut out);
+        self.tick_duration.write_prometheus("server_tick_duration_seconds", "Time spent simulating one tick", &mut out);
+        out
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) {
+    let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, content_type, body.len(), body);
+}
+
+fn handle(mut stream: TcpStream, status: &impl Fn() -> Status) {
+    let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+    let mut request = vec![];
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|it| it == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+        match stream.read(&mut buf) {
+        Ok(0) | Err(_) => break,
+        Ok(n) => request.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.lines().next().unwrap_or_default().split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let path = path.split('?').next().unwrap_or_default();
+    match (method, path) {
+    ("GET", "/metrics") => respond(&mut stream, "200 OK", "text/plain; version=0.0.4", &status().prometheus()),
+    ("GET", "/" | "/status") => respond(&mut stream, "200 OK", "application/json", &status().serialize_json()),
+    ("GET", _) => respond(&mut stream, "404 Not Found", "text/plain", "not found\n"),
+    _ => respond(&mut stream, "405 Method Not Allowed", "text/plain", "only GET is supported\n"),
+    }
+}
+
+// one request at a time is plenty for an operator's dashboard or a scraper
+pub fn serve(listener: TcpListener, status: impl Fn() -> Status) {
+    for stream in listener.incoming().flatten() {
+        handle(stream, &status);
+    }
+}
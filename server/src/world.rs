@@ -0,0 +1,982 @@
+// SPDX-FileCopyrightText: 2024 Janet Blackquill <uhhadd@gmail.com>
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet, VecDeque}, hash::{Hash, Hasher}, time::Duration};
+
+use logic::{clock::TICKS_PER_SECOND, field::{Field, GameState}, garbage::AttackTable, hooks::{Cubes, Sounds}, input::{Input, InputProvider, Inputs}, proto::{RoomInfo, ServerToClient}, recording::MatchRecord, snapshot::{FieldDelta, Snapshot}};
+
+use crate::{chat::{ChatFilter, WordFilter, CHAT_BURST, CHAT_PER_SECOND, MAX_CHAT_LENGTH}, config::Config, limit::TokenBucket, matchmaking::{MatchQueue, QueueEntry}, rating::RatingStore, recorder::MatchStore, room::{Room, RoomState, COUNTDOWN_TICKS, MAX_ROOM_NAME}};
+
+const MAX_INPUT_LEAD: u64 = 30;
+pub const MAX_CATCH_UP: u64 = 10;
+const SNAPSHOT_INTERVAL: u64 = TICKS_PER_SECOND / 10;
+const FULL_SNAPSHOT_INTERVAL: u64 = 10;
+const RECONNECT_GRACE_TICKS: u64 = 30 * TICKS_PER_SECOND;
+const PING_INTERVAL: u64 = 2 * TICKS_PER_SECOND;
+const MATCHMAKING_INTERVAL: u64 = TICKS_PER_SECOND / 2;
+const MAX_PLAYER_NAME: usize = 32;
+
+struct BufferedInput {
+    tick: u64,
+    input: Input,
+    up: bool,
+}
+
+struct WorldClientState {
+    field: Field,
+    queued_messages: VecDeque<ServerToClient>,
+    inputs: Inputs,
+    provider: NetworkInputProvider,
+    pending_inputs: VecDeque<BufferedInput>,
+    tick: u64,
+    room: Option<String>,
+    ready: bool,
+    finished: bool,
+    attack_target: usize,
+    spectator: bool,
+    snapshot_bases: HashMap<u32, (u64, Field)>,
+    snapshots_sent: u64,
+    token: String,
+    connection: u64,
+    disconnected_at: Option<u64>,
+    ping: Option<(u32, Duration)>,
+    rtt: Option<Duration>,
+    player: Option<String>,
+    chat: TokenBucket,
+}
+
+pub struct ClientSummary {
+    pub id: u32,
+    pub player: Option<String>,
+    pub room: Option<String>,
+    pub spectator: bool,
+    pub connected: bool,
+    pub rtt: Option<Duration>,
+}
+
+// everything the world does is a function of the events fed into it and the `now` they carry,
+// so a captured session can be fed through it again to get the same messages out
+pub struct World {
+    config: Config,
+    chat_filter: Box<dyn ChatFilter>,
+    clients: BTreeMap<u32, WorldClientState>,
+    rooms: BTreeMap<String, Room>,
+    now: Duration,
+    tick: u64,
+    tokens: HashMap<String, u32>,
+    token_seed: u64,
+    start_date: u64,
+    next_id: u32,
+    ratings: RatingStore,
+    queue: MatchQueue,
+    next_match: u32,
+    matches: MatchStore,
+}
+
+struct NetworkInputProvider {
+    just_pressed: HashSet<Input>,
+    current: HashSet<Input>,
+}
+impl InputProvider for NetworkInputProvider {
+    fn peek(&mut self) {
+    }
+    fn consume(&mut self) {
+        self.just_pressed.clear();
+    }
+    fn key_just_pressed(&self, input: Input) -> bool {
+        self.just_pressed.contains(&input)
+    }
+    fn key_down(&self, input: Input) -> bool {
+        self.current.contains(&input)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct DummyImpl;
+impl Cubes for DummyImpl {
+    fn spawn_cube(&mut self, _x: i32, _y: i32, _color: logic::well::Block) {
+    }
+}
+impl Sounds for DummyImpl {
+    fn block_spawn(&mut self, _color: logic::well::Block) {
+    }
+    fn line_clear(&mut self) {
+    }
+    fn lock(&mut self) {
+    }
+    fn land(&mut self) {
+    }
+}
+
+impl World {
+    pub fn new(config: Config, ratings: RatingStore, matches: MatchStore, token_seed: u64, start_date: u64) -> World {
+        World {
+            chat_filter: Box::new(WordFilter::new(&config.chat_filter)),
+            config,
+            clients: BTreeMap::new(),
+            rooms: BTreeMap::new(),
+            now: Duration::ZERO,
+            tick: 0,
+            tokens: HashMap::new(),
+            token_seed,
+            start_date,
+            next_id: 0,
+            ratings,
+            queue: MatchQueue::new(),
+            next_match: 0,
+            matches,
+        }
+    }
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+    pub fn clients(&self) -> Vec<ClientSummary> {
+        self.clients.iter().map(|(id, it)| ClientSummary {
+            id: *id,
+            player: it.player.clone(),
+            room: it.room.clone(),
+            spectator: it.spectator,
+            connected: it.disconnected_at.is_none(),
+            rtt: it.rtt,
+        }).collect()
+    }
+    pub fn rooms(&self) -> Vec<RoomInfo> {
+        self.rooms.values().map(|it| it.info()).collect()
+    }
+    // ends the session outright, so the kicked client can't resume it with its token
+    pub fn kick(&mut self, client_id: u32) -> bool {
+        let known = self.clients.contains_key(&client_id);
+        self.leave(client_id);
+        known
+    }
+    pub fn broadcast(&mut self, message: &str) {
+        for id in self.clients.keys().cloned().collect::<Vec<_>>() {
+            self.enqueue_message_to(id, ServerToClient::Announcement { message: message.to_string() });
+        }
+    }
+    fn enqueue_message_to(&mut self, id: u32, message: ServerToClient) {
+        if let Some(state) = self.clients.get_mut(&id) {
+            state.queued_messages.push_back(message.clone());
+        }
+    }
+    fn enqueue_message_to_room(&mut self, room: &str, excluding: Option<u32>, message: ServerToClient) {
+        let members = match self.rooms.get(room) {
+        Some(room) => room.members.iter().chain(room.spectators.iter()).cloned().collect::<Vec<_>>(),
+        None => return,
+        };
+        for member in members {
+            if Some(member) != excluding {
+                self.enqueue_message_to(member, message.clone());
+            }
+        }
+    }
+    fn enqueue_message_excluding(&mut self, id: u32, message: ServerToClient) {
+        let members = match self.clients.get(&id).and_then(|it| it.room.as_ref()).and_then(|it| self.rooms.get(it)) {
+        Some(room) => room.members.clone(),
+        None => return,
+        };
+        for member in members {
+            if member != id {
+                self.enqueue_message_to(member, message.clone());
+            }
+        }
+    }
+    pub fn dequeue_messages_for(&mut self, id: u32, connection: u64) -> VecDeque<ServerToClient> {
+        if let Some(state) = self.clients.get_mut(&id).filter(|it| it.connection == connection) {
+            let ret = state.queued_messages.clone();
+            state.queued_messages.clear();
+            ret
+        } else {
+            VecDeque::new()
+        }
+    }
+    fn new_token(&self, purpose: &str, client_id: u32) -> String {
+        let mut parts = [0u64; 2];
+        for (idx, part) in parts.iter_mut().enumerate() {
+            let mut hasher = DefaultHasher::new();
+            (self.token_seed, purpose, client_id, idx, self.now.as_nanos()).hash(&mut hasher);
+            *part = hasher.finish();
+        }
+        format!("{:016x}{:016x}", parts[0], parts[1])
+    }
+    // a known token resumes the session it was issued for along with everything queued for it
+    // while it was away, anything else starts a new one
+    pub fn join(&mut self, token: Option<String>, connection: u64) -> u32 {
+        if let Some(client_id) = token.and_then(|it| self.tokens.get(&it).cloned()) {
+            let state = self.clients.get_mut(&client_id).unwrap();
+            state.connection = connection;
+            state.disconnected_at = None;
+            state.snapshot_bases.clear();
+            state.snapshots_sent = 0;
+            let token = state.token.clone();
+            self.enqueue_message_to(client_id, ServerToClient::Welcome { client_id, token, resumed: true });
+            return client_id;
+        }
+
+        self.next_id += 1;
+        let client_id = self.next_id;
+        let token = self.new_token("session", client_id);
+        self.tokens.insert(token.clone(), client_id);
+        self.clients.insert(client_id, WorldClientState {
+            field: Field::new(),
+            queued_messages: VecDeque::new(),
+            inputs: Inputs::new(),
+            provider: NetworkInputProvider { just_pressed: HashSet::new(), current: HashSet::new() },
+            pending_inputs: VecDeque::new(),
+            tick: 0,
+            room: None,
+            ready: false,
+            finished: false,
+            attack_target: 0,
+            spectator: false,
+            snapshot_bases: HashMap::new(),
+            snapshots_sent: 0,
+            token: token.clone(),
+            connection,
+            disconnected_at: None,
+            ping: None,
+            rtt: None,
+            player: None,
+            chat: TokenBucket::new(CHAT_BURST, CHAT_PER_SECOND, self.now),
+        });
+        self.enqueue_message_to(client_id, ServerToClient::Welcome { client_id, token, resumed: false });
+        client_id
+    }
+    pub fn disconnect(&mut self, client_id: u32, connection: u64) {
+        let tick = self.tick;
+        if let Some(state) = self.clients.get_mut(&client_id).filter(|it| it.connection == connection) {
+            state.disconnected_at = Some(tick);
+        }
+    }
+    fn leave(&mut self, client_id: u32) {
+        self.queue.remove(client_id);
+        self.leave_room(client_id);
+        if let Some(state) = self.clients.remove(&client_id) {
+            self.tokens.remove(&state.token);
+        }
+    }
+    fn send_pings(&mut self) {
+        let nonce = self.tick as u32;
+        let ids = self.clients.iter()
+            .filter(|(_, it)| it.disconnected_at.is_none())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in ids {
+            self.clients.get_mut(&id).unwrap().ping = Some((nonce, self.now));
+            self.enqueue_message_to(id, ServerToClient::Ping { nonce });
+        }
+    }
+    pub fn pong(&mut self, client_id: u32, nonce: u32) {
+        if let Some(state) = self.clients.get_mut(&client_id) {
+            if let Some((sent_nonce, sent)) = state.ping {
+                if sent_nonce == nonce {
+                    state.rtt = Some(self.now.saturating_sub(sent));
+                    state.ping = None;
+                }
+            }
+        }
+    }
+    fn expire_sessions(&mut self) {
+        let expired = self.clients.iter()
+            .filter(|(_, it)| it.disconnected_at.map(|at| at + RECONNECT_GRACE_TICKS <= self.tick).unwrap_or(false))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in expired {
+            self.leave(id);
+        }
+    }
+    fn room_error(&mut self, client_id: u32, message: &str) {
+        self.enqueue_message_to(client_id, ServerToClient::RoomError { message: message.to_string() });
+    }
+    pub fn list_rooms(&mut self, client_id: u32) {
+        let rooms = self.rooms();
+        self.enqueue_message_to(client_id, ServerToClient::RoomList { rooms });
+    }
+    pub fn create_room(&mut self, client_id: u32, name: String, capacity: u32, attack_table: Option<AttackTable>) {
+        if !self.clients.contains_key(&client_id) {
+            return;
+        }
+        if name.is_empty() || name.chars().count() > MAX_ROOM_NAME {
+            self.room_error(client_id, "invalid room name");
+        } else if capacity == 0 || capacity > self.config.max_room_capacity {
+            self.room_error(client_id, &format!("room capacity must be between 1 and {}", self.config.max_room_capacity));
+        } else if self.rooms.contains_key(&name) {
+            self.room_error(client_id, "a room with that name already exists");
+        } else if self.rooms.len() as u32 >= self.config.max_rooms {
+            self.room_error(client_id, "too many rooms, try joining one");
+        } else if let Some(Err(error)) = attack_table.as_ref().map(AttackTable::validate) {
+            self.room_error(client_id, &error);
+        } else {
+            self.rooms.insert(name.clone(), Room::new(&name, capacity, attack_table.unwrap_or_default()));
+            self.join_room(client_id, name);
+        }
+    }
+    pub fn join_room(&mut self, client_id: u32, name: String) {
+        let error = match self.rooms.get(&name) {
+        None => Some("no such room"),
+        Some(room) if room.members.contains(&client_id) => Some("already in that room"),
+        Some(room) if room.is_full() => Some("room is full"),
+        Some(room) if room.state != RoomState::Waiting => Some("room is in a game"),
+        Some(_) => None,
+        };
+        if let Some(error) = error {
+            self.room_error(client_id, error);
+            return;
+        }
+
+        self.leave_room(client_id);
+        let Some(state) = self.clients.get_mut(&client_id) else {
+            return;
+        };
+        state.field = Field::new();
+        state.room = Some(name.clone());
+        state.ready = false;
+
+        let room = self.rooms.get_mut(&name).unwrap();
+        room.members.push(client_id);
+        let info = room.info();
+        let members = room.members.clone();
+
+        self.enqueue_message_to(client_id, ServerToClient::RoomJoined { room: info });
+        for member in members {
+            if member == client_id {
+                continue;
+            }
+            let (field, ready) = (self.clients[&member].field.clone(), self.clients[&member].ready);
+            self.enqueue_message_to(client_id, ServerToClient::Join { client_id: member, field });
+            self.enqueue_message_to(client_id, ServerToClient::Ready { client_id: member, ready });
+        }
+        self.enqueue_message_excluding(client_id, ServerToClient::Join { client_id, field: self.clients[&client_id].field.clone() });
+    }
+    pub fn spectate(&mut self, client_id: u32, name: String) {
+        let error = match self.rooms.get(&name) {
+        None => Some("no such room"),
+        Some(room) if room.spectators.contains(&client_id) => Some("already spectating that room"),
+        Some(room) if room.spectators.len() as u32 >= self.config.max_spectators => Some("room has too many spectators"),
+        Some(_) => None,
+        };
+        if let Some(error) = error {
+            self.room_error(client_id, error);
+            return;
+        }
+
+        self.leave_room(client_id);
+        let Some(state) = self.clients.get_mut(&client_id) else {
+            return;
+        };
+        state.room = Some(name.clone());
+        state.spectator = true;
+        state.snapshot_bases.clear();
+        state.snapshots_sent = 0;
+
+        let room = self.rooms.get_mut(&name).unwrap();
+        room.spectators.push(client_id);
+        let info = room.info();
+        self.enqueue_message_to(client_id, ServerToClient::Spectating { room: info });
+        self.send_snapshots(&name, client_id);
+    }
+    fn send_snapshots(&mut self, name: &str, spectator: u32) {
+        let members = self.rooms[name].members.clone();
+        let fields = members.iter().map(|it| (*it, self.clients[it].tick, self.clients[it].field.clone())).collect::<Vec<_>>();
+        let Some(state) = self.clients.get_mut(&spectator) else {
+            return;
+        };
+
+        let full = state.snapshots_sent.is_multiple_of(FULL_SNAPSHOT_INTERVAL);
+        state.snapshot_bases.retain(|it, _| members.contains(it));
+        for (client_id, tick, field) in fields {
+            let snapshot = match state.snapshot_bases.get(&client_id) {
+            Some((base_tick, base)) if !full => Snapshot::Delta { delta: FieldDelta::between(*base_tick, base, &field) },
+            _ => Snapshot::Full { field: Box::new(field.clone()) },
+            };
+            state.snapshot_bases.insert(client_id, (tick, field));
+            state.queued_messages.push_back(ServerToClient::Snapshot { client_id, tick, snapshot });
+        }
+        state.snapshots_sent += 1;
+    }
+    pub fn leave_room(&mut self, client_id: u32) {
+        let Some(state) = self.clients.get_mut(&client_id) else {
+            return;
+        };
+        let Some(name) = state.room.take() else {
+            return;
+        };
+        let spectator = std::mem::replace(&mut state.spectator, false);
+        state.snapshot_bases.clear();
+        self.enqueue_message_to(client_id, ServerToClient::RoomLeft {});
+
+        let Some(room) = self.rooms.get_mut(&name) else {
+            return;
+        };
+        if spectator {
+            room.spectators.retain(|it| *it != client_id);
+            return;
+        }
+        room.members.retain(|it| *it != client_id);
+        if room.members.is_empty() {
+            for spectator in self.rooms.remove(&name).unwrap().spectators {
+                self.leave_room(spectator);
+            }
+            return;
+        }
+        self.enqueue_message_to_room(&name, None, ServerToClient::Leave { client_id });
+        self.cancel_countdown(&name);
+        self.check_finished(&name);
+    }
+    pub fn set_ready(&mut self, client_id: u32, ready: bool) {
+        let Some(name) = self.clients.get(&client_id).filter(|it| !it.spectator).and_then(|it| it.room.clone()) else {
+            return;
+        };
+        if self.rooms[&name].state == RoomState::Playing {
+            return;
+        }
+        self.clients.get_mut(&client_id).unwrap().ready = ready;
+        self.enqueue_message_to_room(&name, None, ServerToClient::Ready { client_id, ready });
+
+        if !ready {
+            self.cancel_countdown(&name);
+        } else if self.rooms[&name].members.iter().all(|it| self.clients[it].ready) {
+            let start_tick = self.tick + COUNTDOWN_TICKS;
+            self.rooms.get_mut(&name).unwrap().state = RoomState::Countdown { start_tick };
+            self.enqueue_message_to_room(&name, None, ServerToClient::Countdown { start_tick, now: self.tick });
+        }
+    }
+    fn cancel_countdown(&mut self, name: &str) {
+        if let Some(room) = self.rooms.get_mut(name) {
+            if let RoomState::Countdown { .. } = room.state {
+                room.state = RoomState::Waiting;
+                self.enqueue_message_to_room(name, None, ServerToClient::CountdownCancelled {});
+            }
+        }
+    }
+    fn start_room(&mut self, name: &str) {
+        let seed = self.now.as_nanos() as u32 ^ self.tick as u32;
+        let players = self.rooms[name].members.iter().map(|it| (*it, self.clients[it].player.clone())).collect::<Vec<_>>();
+        let date = self.start_date + self.now.as_secs();
+        let room = self.rooms.get_mut(name).unwrap();
+        room.state = RoomState::Playing;
+        room.recording = Some(MatchRecord::new(name, date, seed, room.attack_table.clone(), room.ranked.is_some(), players));
+        for spectator in room.spectators.clone() {
+            if let Some(state) = self.clients.get_mut(&spectator) {
+                state.snapshots_sent = 0;
+            }
+        }
+        for member in room.members.clone() {
+            if let Some(state) = self.clients.get_mut(&member) {
+                state.field = Field::seeded(seed);
+                state.inputs = Inputs::new();
+                state.provider = NetworkInputProvider { just_pressed: HashSet::new(), current: HashSet::new() };
+                state.pending_inputs.clear();
+                state.tick = 0;
+                state.finished = false;
+            }
+        }
+        self.enqueue_message_to_room(name, None, ServerToClient::Start { seed, start_tick: self.tick });
+    }
+    fn check_finished(&mut self, name: &str) {
+        let Some(room) = self.rooms.get(name) else {
+            return;
+        };
+        if room.state != RoomState::Playing {
+            return;
+        }
+        let alive = room.members.iter().cloned().filter(|it| !self.clients[it].finished).collect::<Vec<_>>();
+        // members who left are already gone, so count the players the match started with
+        let versus = room.recording.as_ref().map(|it| it.players.len()).unwrap_or(room.members.len()) > 1;
+        if (versus && alive.len() > 1) || (!versus && !alive.is_empty()) {
+            return;
+        }
+
+        let winner = if versus { alive.first().cloned() } else { None };
+        let room = self.rooms.get_mut(name).unwrap();
+        room.state = RoomState::Waiting;
+        let ranked = room.ranked.take();
+        let recording = room.recording.take();
+        for member in room.members.clone() {
+            self.clients.get_mut(&member).unwrap().ready = false;
+        }
+        self.enqueue_message_to_room(name, None, ServerToClient::RoomFinished { winner });
+        if let Some(mut record) = recording {
+            record.winner = winner;
+            record.ticks = record.players.iter().filter_map(|it| self.clients.get(&it.client_id)).map(|it| it.tick).max().unwrap_or(0);
+            if let Err(error) = self.matches.save(record) {
+                self.enqueue_message_to_room(name, None, ServerToClient::RoomError { message: format!("could not save match: {}", error) });
+            }
+        }
+        if let Some(players) = ranked {
+            self.rate_match(name, players, winner);
+        }
+    }
+    pub fn list_matches(&mut self, client_id: u32) {
+        let matches = self.matches.list().to_vec();
+        self.enqueue_message_to(client_id, ServerToClient::MatchList { matches });
+    }
+    pub fn get_match(&mut self, client_id: u32, id: u64) {
+        match self.matches.get(id) {
+        Ok(record) => self.enqueue_message_to(client_id, ServerToClient::MatchRecording { record }),
+        Err(error) => self.room_error(client_id, &error),
+        }
+    }
+    // the first identify with a name claims it and hands back the key needed to use it again
+    pub fn identify(&mut self, client_id: u32, player: String, key: Option<String>) {
+        if !self.clients.contains_key(&client_id) {
+            return;
+        }
+        if player.is_empty() || player.chars().count() > MAX_PLAYER_NAME {
+            return self.room_error(client_id, "invalid player name");
+        }
+        if self.queue.contains(client_id) {
+            return self.room_error(client_id, "cannot change names while queued");
+        }
+        let key = match (self.ratings.key(&player), key) {
+        (Some(claimed), Some(key)) if claimed == key => key,
+        (Some(_), _) => return self.room_error(client_id, "that name belongs to someone else"),
+        (None, _) => {
+            let key = self.new_token("key", client_id);
+            if let Err(error) = self.ratings.claim(&player, key.clone()) {
+                return self.room_error(client_id, &format!("could not save identity: {}", error));
+            }
+            key
+        }
+        };
+        self.clients.get_mut(&client_id).unwrap().player = Some(player.clone());
+        self.enqueue_message_to(client_id, ServerToClient::Identified { player, key });
+    }
+    // chat only reaches the sender's room, spectators included
+    pub fn chat(&mut self, client_id: u32, message: String) {
+        let message = message.trim();
+        let Some(state) = self.clients.get_mut(&client_id) else {
+            return;
+        };
+        let Some(room) = state.room.clone() else {
+            self.room_error(client_id, "join a room to chat");
+            return;
+        };
+        if message.is_empty() {
+            return;
+        }
+        if message.chars().any(char::is_control) {
+            self.room_error(client_id, "chat messages can't contain control characters");
+            return;
+        }
+        if message.chars().count() > MAX_CHAT_LENGTH {
+            self.room_error(client_id, &format!("chat messages can be at most {} characters", MAX_CHAT_LENGTH));
+            return;
+        }
+        if !state.chat.take(self.now) {
+            self.room_error(client_id, "you're chatting too fast");
+            return;
+        }
+        let name = state.player.clone().unwrap_or_else(|| format!("guest {}", client_id));
+        match self.chat_filter.filter(message) {
+        Some(message) => self.enqueue_message_to_room(&room, None, ServerToClient::Chat { client_id, name, message }),
+        None => self.room_error(client_id, "your message was blocked"),
+        }
+    }
+    pub fn get_rating(&mut self, client_id: u32, player: String) {
+        let rating = self.ratings.get(&player).info(&player);
+        self.enqueue_message_to(client_id, ServerToClient::Rating { rating });
+    }
+    pub fn queue_ranked(&mut self, client_id: u32) {
+        let Some(state) = self.clients.get(&client_id) else {
+            return;
+        };
+        let Some(player) = state.player.clone() else {
+            return self.room_error(client_id, "identify before queueing for ranked");
+        };
+        if state.room.as_ref().map(|it| self.rooms[it].state != RoomState::Waiting).unwrap_or(false) {
+            return self.room_error(client_id, "cannot queue while in a game");
+        }
+        if self.queue.clients().any(|it| it != client_id && self.clients.get(&it).and_then(|it| it.player.as_ref()) == Some(&player)) {
+            return self.room_error(client_id, "that name is already queued");
+        }
+        let rating = self.ratings.get(&player);
+        self.queue.push(QueueEntry { client_id, rating: rating.rating, queued_at: self.tick });
+        self.enqueue_message_to(client_id, ServerToClient::Queued { rating: rating.info(&player) });
+    }
+    pub fn leave_queue(&mut self, client_id: u32) {
+        if self.queue.remove(client_id) {
+            self.enqueue_message_to(client_id, ServerToClient::QueueLeft {});
+        }
+    }
+    // players who are away or already in a game keep their place in the queue until they're back
+    fn match_players(&mut self) {
+        let available = |id: u32| self.clients.get(&id)
+            .filter(|it| it.disconnected_at.is_none() && it.player.is_some())
+            .map(|it| it.room.as_ref().map(|room| self.rooms[room].state == RoomState::Waiting).unwrap_or(true))
+            .unwrap_or(false);
+        for (a, b) in self.queue.pair(self.tick, TICKS_PER_SECOND as f64, available) {
+            let players = [a, b].iter()
+                .filter_map(|id| self.clients.get(id).and_then(|it| it.player.clone()).map(|player| (*id, player)))
+                .collect::<Vec<_>>();
+            if players.len() != 2 {
+                continue;
+            }
+
+            self.next_match += 1;
+            let name = format!("ranked-{}", self.next_match);
+            let mut room = Room::new(&name, 2, AttackTable::new());
+            room.ranked = Some(players.clone());
+            self.rooms.insert(name.clone(), room);
+            for (idx, (client_id, _)) in players.iter().enumerate() {
+                let (_, opponent) = &players[1 - idx];
+                let opponent = self.ratings.get(opponent).info(opponent);
+                self.join_room(*client_id, name.clone());
+                self.enqueue_message_to(*client_id, ServerToClient::MatchFound { room: name.clone(), opponent });
+            }
+            for (client_id, _) in &players {
+                self.set_ready(*client_id, true);
+            }
+        }
+    }
+    // anyone who left before the end forfeits
+    fn rate_match(&mut self, name: &str, players: Vec<(u32, String)>, winner: Option<u32>) {
+        let members = self.rooms.get(name).map(|it| it.members.clone()).unwrap_or_default();
+        let scores = players.iter()
+            .map(|(client_id, player)| {
+                let score = match winner {
+                _ if !members.contains(client_id) => 0.,
+                Some(winner) if winner == *client_id => 1.,
+                Some(_) => 0.,
+                None => 0.5,
+                };
+                (player.clone(), score)
+            })
+            .collect::<Vec<_>>();
+        match self.ratings.record_match(&scores) {
+        Ok(changes) => {
+            for (player, before, after) in changes {
+                let change = after.rating - before.rating;
+                self.enqueue_message_to_room(name, None, ServerToClient::RatingChanged { rating: after.info(&player), change });
+            }
+        }
+        Err(error) => {
+            for (client_id, _) in players {
+                self.room_error(client_id, &format!("could not save ratings: {}", error));
+            }
+        }
+        }
+    }
+    fn send_garbage(&mut self, name: &str, client_id: u32, lines: u32) {
+        let opponents = self.rooms[name].opponents(client_id)
+            .filter(|it| !self.clients[it].finished)
+            .collect::<Vec<_>>();
+        if opponents.is_empty() {
+            return;
+        }
+
+        let attacker = self.clients.get_mut(&client_id).unwrap();
+        let target = opponents[attacker.attack_target % opponents.len()];
+        attacker.attack_target += 1;
+
+        let victim = self.clients.get_mut(&target).unwrap();
+        victim.field.garbage.queue(lines);
+        let tick = victim.tick;
+        if let Some(record) = self.rooms.get_mut(name).and_then(|it| it.recording.as_mut()) {
+            record.record_garbage(target, tick, lines);
+        }
+        self.enqueue_message_to_room(name, None, ServerToClient::Garbage { client_id: target, lines, tick });
+    }
+    // inputs stamped for a tick that has already been simulated are applied on the next tick,
+    // and inputs stamped too far ahead are pulled back to MAX_INPUT_LEAD
+    pub fn input(&mut self, client_id: u32, input: Input, up: bool, tick: u64) {
+        if let Some(state) = self.clients.get_mut(&client_id) {
+            if state.room.is_none() || state.spectator || state.finished {
+                return;
+            }
+            let tick = tick.clamp(state.tick, state.tick + MAX_INPUT_LEAD);
+            let idx = state.pending_inputs.iter().position(|it| it.tick > tick).unwrap_or(state.pending_inputs.len());
+            state.pending_inputs.insert(idx, BufferedInput { tick, input, up });
+        }
+    }
+    fn step(&mut self, name: &str, client_id: u32) {
+        let mut a = DummyImpl;
+        let mut b = DummyImpl;
+        let mut applied = vec![];
+        let mut attack = 0;
+        let tick = if let Some(state) = self.clients.get_mut(&client_id) {
+            if state.finished {
+                return;
+            }
+            while state.pending_inputs.front().map(|it| it.tick <= state.tick).unwrap_or(false) {
+                let BufferedInput { input, up, .. } = state.pending_inputs.pop_front().unwrap();
+                if up {
+                    state.provider.just_pressed.insert(input);
+                    state.provider.current.insert(input);
+                } else {
+                    state.provider.just_pressed.remove(&input);
+                    state.provider.current.remove(&input);
+                }
+                applied.push((input, up));
+            }
+            state.inputs.tick(state.tick, &mut state.provider);
+            if let Some(locked) = state.field.update(&state.inputs, &mut a, &mut b) {
+                attack = state.field.garbage.attack(&self.rooms[name].attack_table, locked.lines);
+            }
+            state.finished = matches!(state.field.state, GameState::GameOver { .. });
+            state.tick += 1;
+            state.tick - 1
+        } else {
+            return;
+        };
+
+        if let Some(record) = self.rooms.get_mut(name).and_then(|it| it.recording.as_mut()) {
+            for (input, up) in &applied {
+                record.record_input(client_id, tick, *input, *up);
+            }
+        }
+        for (input, up) in applied {
+            self.enqueue_message_excluding(client_id, ServerToClient::Input { client_id, input, up, tick });
+        }
+        self.enqueue_message_excluding(client_id, ServerToClient::Tick { client_id, tick });
+        if attack > 0 {
+            self.send_garbage(name, client_id, attack);
+        }
+    }
+    // returns how many ticks were simulated
+    pub fn advance(&mut self, now: Duration) -> u64 {
+        self.now = self.now.max(now);
+        let target = (self.now.as_secs_f64() * TICKS_PER_SECOND as f64) as u64;
+        if target > self.tick + MAX_CATCH_UP {
+            self.tick = target - MAX_CATCH_UP;
+        }
+        let simulated = target.saturating_sub(self.tick);
+        while self.tick < target {
+            let rooms = self.rooms.keys().cloned().collect::<Vec<_>>();
+            for name in rooms {
+                match self.rooms[&name].state {
+                RoomState::Waiting => {}
+                RoomState::Countdown { start_tick } => {
+                    if self.tick >= start_tick {
+                        self.start_room(&name);
+                    }
+                }
+                RoomState::Playing => {
+                    for member in self.rooms[&name].members.clone() {
+                        self.step(&name, member);
+                    }
+                    self.check_finished(&name);
+                }
+                }
+                if self.tick.is_multiple_of(SNAPSHOT_INTERVAL) {
+                    for spectator in self.rooms.get(&name).map(|it| it.spectators.clone()).unwrap_or_default() {
+                        self.send_snapshots(&name, spectator);
+                    }
+                }
+            }
+            if self.tick.is_multiple_of(PING_INTERVAL) {
+                self.send_pings();
+            }
+            if self.tick.is_multiple_of(MATCHMAKING_INTERVAL) {
+                self.match_players();
+            }
+            self.tick += 1;
+        }
+        self.expire_sessions();
+        simulated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn world() -> World {
+        World::new(Config::new(), RatingStore::from_json(""), MatchStore::from_index(PathBuf::new(), ""), 1, 0)
+    }
+    fn run(world: &mut World, ticks: u64) {
+        let target = world.tick() + ticks;
+        while world.tick() < target {
+            world.advance(Duration::from_micros((world.tick() + 1) * 1_000_000 / 60 + 1000));
+        }
+    }
+    fn messages(world: &mut World, client_id: u32) -> Vec<ServerToClient> {
+        world.dequeue_messages_for(client_id, client_id as u64).into()
+    }
+    fn start_match(world: &mut World, players: u64) -> Vec<u32> {
+        let ids = (1..=players).map(|it| world.join(None, it)).collect::<Vec<_>>();
+        world.create_room(ids[0], "room".to_string(), 8, None);
+        for id in &ids[1..] {
+            world.join_room(*id, "room".to_string());
+        }
+        for id in &ids {
+            world.set_ready(*id, true);
+        }
+        run(world, COUNTDOWN_TICKS + 1);
+        assert!(world.rooms["room"].state == RoomState::Playing);
+        for id in &ids {
+            messages(world, *id);
+        }
+        ids
+    }
+
+    #[test]
+    fn invalid_attack_tables_are_rejected() {
+        let mut world = world();
+        let id = world.join(None, 1);
+        messages(&mut world, id);
+        world.create_room(id, "room".to_string(), 2, Some(AttackTable { lines: [0, 1, 2, u32::MAX], combo: vec![] }));
+        assert!(matches!(messages(&mut world, id)[..], [ServerToClient::RoomError { .. }]));
+        assert!(world.rooms().is_empty());
+    }
+
+    #[test]
+    fn unknown_clients_cannot_create_rooms() {
+        let mut world = world();
+        world.create_room(7, "room".to_string(), 2, None);
+        assert!(world.rooms().is_empty());
+    }
+
+    #[test]
+    fn remaining_player_wins_when_opponent_leaves() {
+        let mut world = world();
+        let ids = start_match(&mut world, 2);
+        world.leave_room(ids[1]);
+        let finished = messages(&mut world, ids[0]).into_iter().find_map(|it| match it {
+        ServerToClient::RoomFinished { winner } => Some(winner),
+        _ => None,
+        });
+        assert_eq!(finished, Some(Some(ids[0])));
+        assert_eq!(world.matches.list()[0].winner.as_deref(), Some("guest 1"));
+    }
+
+    #[test]
+    fn versus_continues_until_one_player_remains() {
+        let mut world = world();
+        let ids = start_match(&mut world, 3);
+        world.leave_room(ids[2]);
+        assert!(!messages(&mut world, ids[0]).iter().any(|it| matches!(it, ServerToClient::RoomFinished { .. })));
+        assert!(world.rooms["room"].state == RoomState::Playing);
+    }
+
+    fn identify(world: &mut World, client_id: u32, player: &str, key: Option<String>) -> Result<String, String> {
+        world.identify(client_id, player.to_string(), key);
+        messages(world, client_id).into_iter().find_map(|it| match it {
+        ServerToClient::Identified { key, .. } => Some(Ok(key)),
+        ServerToClient::RoomError { message } => Some(Err(message)),
+        _ => None,
+        }).unwrap()
+    }
+
+    fn ranked(world: &mut World, players: &[&str]) -> Vec<u32> {
+        players.iter().enumerate().map(|(idx, player)| {
+            let id = world.join(None, idx as u64 + 1);
+            identify(world, id, player, None).unwrap();
+            world.queue_ranked(id);
+            id
+        }).collect()
+    }
+
+    #[test]
+    fn names_need_the_key_they_were_claimed_with() {
+        let mut world = world();
+        let (a, b) = (world.join(None, 1), world.join(None, 2));
+        let key = identify(&mut world, a, "janet", None).unwrap();
+        assert!(identify(&mut world, b, "janet", None).is_err());
+        assert!(identify(&mut world, b, "janet", Some("guess".to_string())).is_err());
+        assert_eq!(world.clients[&b].player, None);
+        assert_eq!(identify(&mut world, b, "janet", Some(key.clone())), Ok(key));
+        assert_eq!(world.clients[&b].player.as_deref(), Some("janet"));
+    }
+
+    #[test]
+    fn names_can_only_queue_once() {
+        let mut world = world();
+        let a = ranked(&mut world, &["janet"])[0];
+        let b = world.join(None, 2);
+        let key = world.ratings.key("janet");
+        identify(&mut world, b, "janet", key).unwrap();
+        world.queue_ranked(b);
+        assert!(matches!(&messages(&mut world, b)[..], [ServerToClient::RoomError { .. }]));
+        run(&mut world, MATCHMAKING_INTERVAL * 20);
+        assert!(!messages(&mut world, a).iter().any(|it| matches!(it, ServerToClient::MatchFound { .. })));
+    }
+
+    #[test]
+    fn disconnected_players_keep_their_place_in_the_queue() {
+        let mut world = world();
+        let ids = ranked(&mut world, &["janet", "alex"]);
+        world.disconnect(ids[0], 1);
+        run(&mut world, MATCHMAKING_INTERVAL * 2);
+        assert!(!messages(&mut world, ids[1]).iter().any(|it| matches!(it, ServerToClient::MatchFound { .. })));
+
+        let token = world.clients[&ids[0]].token.clone();
+        world.join(Some(token), 3);
+        run(&mut world, MATCHMAKING_INTERVAL * 2);
+        assert!(messages(&mut world, ids[1]).iter().any(|it| matches!(it, ServerToClient::MatchFound { .. })));
+    }
+
+    #[test]
+    fn leaving_a_ranked_match_is_a_loss() {
+        let mut world = world();
+        let ids = ranked(&mut world, &["janet", "alex"]);
+        run(&mut world, MATCHMAKING_INTERVAL * 2 + COUNTDOWN_TICKS + 1);
+        assert!(world.clients[&ids[0]].room.as_ref().map(|it| world.rooms[it].state == RoomState::Playing).unwrap_or(false));
+        world.leave_room(ids[1]);
+        assert!(world.ratings.get("janet").rating > 1500.);
+        assert!(world.ratings.get("alex").rating < 1500.);
+    }
+
+    fn chat(world: &mut World, from: u32, to: u32, message: &str) -> Result<String, String> {
+        world.chat(from, message.to_string());
+        let error = messages(world, from).into_iter().find_map(|it| match it {
+        ServerToClient::RoomError { message } => Some(message),
+        _ => None,
+        });
+        match messages(world, to).pop() {
+        Some(ServerToClient::Chat { message, .. }) => Ok(message),
+        _ => Err(error.unwrap_or_default()),
+        }
+    }
+
+    #[test]
+    fn chat_is_checked_before_it_reaches_the_room() {
+        let mut world = world();
+        let (a, b) = (world.join(None, 1), world.join(None, 2));
+        world.create_room(a, "room".to_string(), 2, None);
+        world.join_room(b, "room".to_string());
+        messages(&mut world, b);
+
+        assert!(chat(&mut world, a, b, "line\nbreak").is_err());
+        assert!(chat(&mut world, a, b, "bell\u{7}").is_err());
+        assert!(chat(&mut world, a, b, &"a".repeat(MAX_CHAT_LENGTH + 1)).is_err());
+        assert_eq!(chat(&mut world, a, b, &"é".repeat(MAX_CHAT_LENGTH)), Ok("é".repeat(MAX_CHAT_LENGTH)));
+        assert_eq!(chat(&mut world, a, b, "  gg  "), Ok("gg".to_string()));
+    }
+
+    #[test]
+    fn chat_is_rate_limited() {
+        let mut world = world();
+        let (a, b) = (world.join(None, 1), world.join(None, 2));
+        world.create_room(a, "room".to_string(), 2, None);
+        world.join_room(b, "room".to_string());
+        messages(&mut world, b);
+
+        for _ in 0..CHAT_BURST as u32 {
+            assert!(chat(&mut world, a, b, "gg").is_ok());
+        }
+        assert_eq!(chat(&mut world, a, b, "gg"), Err("you're chatting too fast".to_string()));
+        assert!(chat(&mut world, b, a, "gg").is_ok());
+        run(&mut world, (TICKS_PER_SECOND as f64 / CHAT_PER_SECOND) as u64);
+        assert!(chat(&mut world, a, b, "gg").is_ok());
+        assert!(chat(&mut world, a, b, "gg").is_err());
+    }
+
+    // however often the server wakes up, the world keeps time at TICKS_PER_SECOND
+    #[test]
+    fn the_world_steps_at_the_simulation_rate_whatever_the_timer_rate() {
+        for rate in [30, 60, 120] {
+            let mut world = world();
+            let id = start_match(&mut world, 1)[0];
+            let (started, stepped, base) = (world.tick(), world.clients[&id].tick, world.now);
+            for wake in 1..=2 * rate {
+                world.advance(base + Duration::from_micros(wake * 1_000_000 / rate));
+            }
+            assert_eq!(world.tick(), started + 2 * TICKS_PER_SECOND, "at {} Hz", rate);
+            assert_eq!(world.clients[&id].tick, stepped + 2 * TICKS_PER_SECOND, "at {} Hz", rate);
+        }
+    }
+}